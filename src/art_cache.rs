@@ -0,0 +1,145 @@
+//! On-disk LRU cache of cover art, keyed by track id.
+//!
+//! Every image is stored in its own file. The modification time of a file
+//! records when it was last used, so the eviction order survives restarts.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+
+pub const DEFAULT_MAX_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Whether cover art may be downloaded from `url`. The Pi names the url, so
+/// anything but the web, like `file://` or `smb://`, is refused.
+pub fn is_downloadable(url: &str) -> bool {
+    url::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    size: u64,
+}
+
+#[derive(Debug)]
+pub struct ArtCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Least recently used first.
+    entries: Vec<Entry>,
+    total_bytes: u64,
+}
+
+impl ArtCache {
+    /// Opens the cache stored in `dir`, creating the directory if needed.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) if metadata.is_file() && name.ends_with(".img") => name,
+                _ => continue,
+            };
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((used, Entry { name, size: metadata.len() }));
+        }
+        found.sort_by_key(|(used, _)| *used);
+
+        let mut cache = ArtCache {
+            dir,
+            max_bytes,
+            total_bytes: found.iter().map(|(_, entry)| entry.size).sum(),
+            entries: found.into_iter().map(|(_, entry)| entry).collect(),
+        };
+        cache.evict()?;
+        Ok(cache)
+    }
+
+    /// Returns the cached image of `track_id`, marking it as recently used.
+    pub fn get(&mut self, track_id: &str) -> Option<Vec<u8>> {
+        let name = file_name(track_id);
+        let index = self.entries.iter().position(|entry| entry.name == name)?;
+        let path = self.dir.join(&name);
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => {
+                // removed behind our back
+                let entry = self.entries.remove(index);
+                self.total_bytes -= entry.size;
+                return None;
+            }
+        };
+        if let Err(e) = touch(&path) {
+            eprintln!("Could not update art cache entry: {}", e);
+        }
+        let entry = self.entries.remove(index);
+        self.entries.push(entry);
+        Some(data)
+    }
+
+    /// Stores the image of `track_id`, evicting the least recently used
+    /// images to stay under the size limit. Images larger than the whole
+    /// cache are not stored.
+    pub fn insert(&mut self, track_id: &str, data: &[u8]) -> io::Result<()> {
+        let size = data.len() as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+
+        let name = file_name(track_id);
+        let path = self.dir.join(&name);
+        // write then rename so a crash never leaves a truncated image behind
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+
+        if let Some(index) = self.entries.iter().position(|entry| entry.name == name) {
+            let entry = self.entries.remove(index);
+            self.total_bytes -= entry.size;
+        }
+        self.entries.push(Entry { name, size });
+        self.total_bytes += size;
+        self.evict()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    fn evict(&mut self) -> io::Result<()> {
+        while self.total_bytes > self.max_bytes && !self.entries.is_empty() {
+            let entry = self.entries.remove(0);
+            self.total_bytes -= entry.size;
+            match fs::remove_file(self.dir.join(&entry.name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Track ids are hex encoded so any id makes a valid file name.
+fn file_name(track_id: &str) -> String {
+    let hex: String = track_id.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.img", hex)
+}
+
+fn touch(path: &Path) -> io::Result<()> {
+    fs::File::options().write(true).open(path)?.set_modified(SystemTime::now())
+}
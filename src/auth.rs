@@ -0,0 +1,72 @@
+//! Shared-secret authentication of a session.
+//!
+//! A server that requires it opens every session with an
+//! `[auth_challenge](nonce)` frame, before anything else. The client answers
+//! with `auth <proof>`, the hex encoded HMAC-SHA256 of the nonce keyed with
+//! the secret, and the server replies `[auth](ok)`, or `[auth](failed)` and
+//! closes the connection. The secret itself never goes over the wire.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::RngCore;
+use ring::hmac;
+
+
+/// Answer to an authentication challenge.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Proof(pub [u8; 32]);
+
+impl Proof {
+    /// The answer to `nonce` for the given secret.
+    pub fn new(secret: &str, nonce: &str) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let mut proof = [0; 32];
+        proof.copy_from_slice(hmac::sign(&key, nonce.as_bytes()).as_ref());
+        Proof(proof)
+    }
+
+    /// Whether this answers `nonce` for the given secret, compared in
+    /// constant time.
+    pub fn verify(&self, secret: &str, nonce: &str) -> bool {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        hmac::verify(&key, nonce.as_bytes(), &self.0).is_ok()
+    }
+}
+
+impl fmt::Display for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Proof({})", self)
+    }
+}
+
+impl FromStr for Proof {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(());
+        }
+        let mut proof = [0; 32];
+        for (byte, i) in proof.iter_mut().zip((0..s.len()).step_by(2)) {
+            *byte = u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| ())?;
+        }
+        Ok(Proof(proof))
+    }
+}
+
+/// A fresh random challenge.
+pub fn nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,54 @@
+//! Exponential backoff with jitter for reconnection attempts.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backoff {
+    /// Delay before the first attempt.
+    pub initial: Duration,
+    /// Upper bound for any delay.
+    pub max: Duration,
+    /// Growth factor between consecutive attempts.
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomized, from `0.0` to `1.0`.
+    pub jitter: f64,
+    /// Give up after this many attempts, retry forever when `None`.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given attempt, counting from 1, or `None` once
+    /// `max_attempts` is exhausted.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+
+        let max = self.max.as_secs_f64();
+        let exponent = (attempt - 1).min(i32::MAX as u32) as i32;
+        let base = (self.initial.as_secs_f64() * self.multiplier.powi(exponent)).min(max);
+
+        let jitter = self.jitter.clamp(0., 1.);
+        let factor = if jitter > 0. {
+            rand::thread_rng().gen_range(1. - jitter..=1. + jitter)
+        } else {
+            1.
+        };
+        Some(Duration::from_secs_f64((base * factor).clamp(0., max)))
+    }
+}
@@ -0,0 +1,374 @@
+//! Headless client for scripting the SpotifyPi from a shell.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use tokio::task;
+use tokio::time::{timeout, timeout_at, Instant};
+
+use spotifypi_control_panel::client::{connect_to_ws_with_config, ws_url, ClientConfig, DEFAULT_ADDR};
+use spotifypi_control_panel::protocol::{Command, ConnectStatus, Event, CAPABILITY_ACK, MAX_VOLUME};
+use spotifypi_control_panel::tls::{Fingerprint, Trust};
+
+/// How long after connecting the Pi has to advertise its capabilities.
+/// Pis that do advertise them greet right away.
+const CAPABILITIES_WAIT: Duration = Duration::from_millis(250);
+
+const USAGE: &str = "\
+Usage: spotifypi-ctl [OPTIONS] <COMMAND>
+
+Options:
+    --addr HOST:PORT|URL  the Pi to connect to, wss://HOST:PORT for TLS
+    --timeout SECS        how long to wait for the Pi (default 5)
+    --ca-file FILE        trust wss:// certificates signed by this PEM CA
+    --pin SHA256          trust only the wss:// certificate with this fingerprint
+    --secret SECRET       authenticate with SECRET when the Pi asks for it
+
+Commands:
+    next                 skip to the next track
+    prev                 go back to the previous track
+    toggle               toggle play / pause
+    shuffle              toggle shuffle
+    repeat               cycle repeat off / single song / whole playlist
+    volume <N|+N|-N>     set the volume, or change it relative to the current one
+    get-volume           print the current volume
+    mute | unmute        mute or unmute, on Pis that support it
+    state                print the play, shuffle and repeat state
+    now-playing          print the track being played
+    seek <[M:]SS>        jump to a position in the track being played
+    shutdown [[M:]SS]    shut the Pi down, after a delay if given
+    reboot [[M:]SS]      reboot the Pi, after a delay if given
+    cancel-power         call off a delayed shutdown or reboot
+
+Exit codes:
+    0  success
+    1  could not connect, or the connection closed early
+    2  invalid usage
+    3  the Pi did not answer in time
+    4  the Pi rejected the command
+    5  authentication failed";
+
+const EXIT_CONNECTION: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_TIMEOUT: u8 = 3;
+const EXIT_REJECTED: u8 = 4;
+const EXIT_AUTH: u8 = 5;
+
+enum Action {
+    Send(Command),
+    GetVolume,
+    SetVolume(u8),
+    ChangeVolume(i16),
+    SetMute(bool),
+    GetPlaybackState,
+    GetNowPlaying,
+    Seek(Duration),
+    Power(Command),
+}
+
+struct Options {
+    addr: String,
+    timeout: Duration,
+    trust: Trust,
+    secret: Option<String>,
+    action: Action,
+}
+
+enum Failure {
+    Connection(String),
+    Timeout,
+    Rejected(String),
+    Auth,
+}
+
+/// Returns `None` when help was requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut timeout = Duration::from_secs(5);
+    let mut trust = Trust::System;
+    let mut secret = None;
+    let mut words = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().ok_or("--addr needs a value")?,
+            "--timeout" => {
+                let secs = args.next().ok_or("--timeout needs a value")?;
+                let secs = secs.parse::<f64>().map_err(|_| format!("invalid timeout: {}", secs))?;
+                if !secs.is_finite() || secs <= 0. {
+                    return Err(format!("invalid timeout: {}", secs));
+                }
+                timeout = Duration::from_secs_f64(secs);
+            }
+            "--ca-file" => trust = Trust::CaFile(args.next().ok_or("--ca-file needs a value")?.into()),
+            "--pin" => {
+                let pin = args.next().ok_or("--pin needs a value")?;
+                let pin = pin.parse::<Fingerprint>().map_err(|e| e.to_string())?;
+                trust = Trust::Pinned(Some(pin));
+            }
+            "--secret" => secret = Some(args.next().ok_or("--secret needs a value")?),
+            "-h" | "--help" => return Ok(None),
+            _ => words.push(arg),
+        }
+    }
+
+    let action = match words.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["next"] => Action::Send(Command::NextTrack),
+        ["prev"] => Action::Send(Command::PrevTrack),
+        ["toggle"] => Action::Send(Command::TogglePlayPause),
+        ["shuffle"] => Action::Send(Command::ToggleShuffle),
+        ["repeat"] => Action::Send(Command::ToggleRepeatState),
+        ["shutdown"] => Action::Send(Command::Shutdown(Duration::ZERO)),
+        ["reboot"] => Action::Send(Command::Reboot(Duration::ZERO)),
+        ["shutdown", delay] => Action::Power(Command::Shutdown(parse_delay_arg(delay)?)),
+        ["reboot", delay] => Action::Power(Command::Reboot(parse_delay_arg(delay)?)),
+        ["cancel-power"] => Action::Power(Command::CancelPower),
+        ["get-volume"] => Action::GetVolume,
+        ["mute"] => Action::SetMute(true),
+        ["unmute"] => Action::SetMute(false),
+        ["state"] => Action::GetPlaybackState,
+        ["now-playing"] => Action::GetNowPlaying,
+        ["volume", value] => parse_volume_arg(value)?,
+        ["seek", value] => Action::Seek(parse_position_arg(value)?),
+        [] => return Err("missing command".to_string()),
+        _ => return Err(format!("invalid command: {}", words.join(" "))),
+    };
+
+    Ok(Some(Options { addr, timeout, trust, secret, action }))
+}
+
+fn parse_volume_arg(value: &str) -> Result<Action, String> {
+    let invalid = || format!("invalid volume: {}", value);
+    if value.starts_with('+') || value.starts_with('-') {
+        let delta = value.parse::<i16>().map_err(|_| invalid())?;
+        Ok(Action::ChangeVolume(delta))
+    } else {
+        match value.parse::<u8>() {
+            Ok(volume) if volume <= MAX_VOLUME => Ok(Action::SetVolume(volume)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn parse_position_arg(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid position: {}", value);
+    let (minutes, seconds) = value.split_once(':').unwrap_or(("0", value));
+    let minutes = minutes.parse::<u64>().map_err(|_| invalid())?;
+    let seconds = seconds.parse::<f64>().map_err(|_| invalid())?;
+    let seconds = Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?;
+    let minutes = minutes.checked_mul(60).map(Duration::from_secs).ok_or_else(invalid)?;
+    minutes.checked_add(seconds).ok_or_else(invalid)
+}
+
+fn parse_delay_arg(value: &str) -> Result<Duration, String> {
+    parse_position_arg(value).map_err(|_| format!("invalid delay: {}", value))
+}
+
+/// Events that only matter to the client itself and are not printed.
+fn is_bookkeeping(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Connect(_)
+            | Event::Disconnect
+            | Event::Certificate(_)
+            | Event::Latency(_)
+            | Event::Capabilities(_)
+            | Event::Done(_)
+    )
+}
+
+/// Waits for the next event, printing the ones the Pi sends on the way.
+async fn next_event(output_rx: &mut UnboundedReceiver<Event>, wait: Duration) -> Result<Event, Failure> {
+    match timeout(wait, output_rx.next()).await {
+        Ok(Some(Event::Disconnect)) | Ok(None) => {
+            Err(Failure::Connection("WebSocket connection closed.".to_string()))
+        }
+        Ok(Some(Event::Connect(ConnectStatus::AuthFailed))) => Err(Failure::Auth),
+        Ok(Some(Event::Failed(cmd, message))) => Err(Failure::Rejected(format!("{} failed: {}", cmd, message))),
+        Ok(Some(Event::TimedOut(_))) | Err(_) => Err(Failure::Timeout),
+        Ok(Some(event)) => {
+            if !is_bookkeeping(&event) {
+                println!("{}", event);
+            }
+            Ok(event)
+        }
+    }
+}
+
+async fn wait_for_volume(output_rx: &mut UnboundedReceiver<Event>, wait: Duration) -> Result<u8, Failure> {
+    loop {
+        if let Event::Volume(volume) = next_event(output_rx, wait).await? {
+            return Ok(volume);
+        }
+    }
+}
+
+/// Waits for the Pi to advertise its capabilities right after connecting.
+/// Returns whether it acknowledges commands.
+async fn acks_supported(output_rx: &mut UnboundedReceiver<Event>) -> Result<bool, Failure> {
+    let deadline = Instant::now() + CAPABILITIES_WAIT;
+    loop {
+        match timeout_at(deadline, output_rx.next()).await {
+            Ok(Some(Event::Capabilities(capabilities))) => return Ok(capabilities.iter().any(|c| c == CAPABILITY_ACK)),
+            Ok(Some(Event::Disconnect)) | Ok(None) => {
+                return Err(Failure::Connection("WebSocket connection closed.".to_string()));
+            }
+            Ok(Some(event)) => {
+                if !is_bookkeeping(&event) {
+                    println!("{}", event);
+                }
+            }
+            // a quiet Pi doesn't acknowledge anything
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Waits until `cmd` is acknowledged, or rejected.
+async fn wait_for_done(output_rx: &mut UnboundedReceiver<Event>, cmd: Command, wait: Duration) -> Result<(), Failure> {
+    loop {
+        match next_event(output_rx, wait).await {
+            Ok(Event::Done(done)) if done == cmd => return Ok(()),
+            Ok(_) => {}
+            // going down closes the connection, maybe before acknowledging
+            Err(Failure::Connection(_)) if matches!(cmd, Command::Shutdown(_) | Command::Reboot(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn send(input_tx: &UnboundedSender<Command>, cmd: Command) -> Result<(), Failure> {
+    input_tx.unbounded_send(cmd).map_err(|_| Failure::Connection("WebSocket connection closed.".to_string()))
+}
+
+async fn run(options: Options) -> Result<(), Failure> {
+    let url = ws_url(&options.addr).map_err(|e| Failure::Connection(format!("{}", e)))?;
+    let wait = options.timeout;
+
+    let (output_tx, mut output_rx) : (UnboundedSender<Event>, UnboundedReceiver<Event>) = unbounded();
+    let (input_tx, input_rx) : (UnboundedSender<Command>, UnboundedReceiver<Command>) = unbounded();
+
+    let config = ClientConfig {
+        trust: options.trust,
+        secret: options.secret,
+        ..ClientConfig::default()
+    };
+    task::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+
+    match timeout(wait, output_rx.next()).await {
+        Ok(Some(Event::Connect(ConnectStatus::Ok))) => {}
+        Ok(Some(Event::Connect(ConnectStatus::AuthFailed))) => return Err(Failure::Auth),
+        Ok(Some(Event::Connect(ConnectStatus::CertificateChanged(fingerprint)))) => {
+            return Err(Failure::Connection(format!(
+                "The certificate of the Pi does not match the pinned one, it is now {}.",
+                fingerprint
+            )));
+        }
+        Ok(_) => return Err(Failure::Connection("Connect failed.".to_string())),
+        Err(_) => return Err(Failure::Timeout),
+    }
+
+    match options.action {
+        Action::Send(cmd) => {
+            // without acknowledgements there is nothing to wait for
+            let acks = acks_supported(&mut output_rx).await?;
+            send(&input_tx, cmd)?;
+            if acks {
+                wait_for_done(&mut output_rx, cmd, wait).await?;
+            }
+        }
+        Action::GetVolume => {
+            send(&input_tx, Command::GetVolume)?;
+            wait_for_volume(&mut output_rx, wait).await?;
+        }
+        Action::SetVolume(volume) => {
+            send(&input_tx, Command::SetVolume(volume))?;
+            wait_for_volume(&mut output_rx, wait).await?;
+        }
+        Action::ChangeVolume(delta) => {
+            send(&input_tx, Command::GetVolume)?;
+            let current = wait_for_volume(&mut output_rx, wait).await?;
+            let volume = (current as i16).saturating_add(delta).clamp(0, MAX_VOLUME as i16) as u8;
+            send(&input_tx, Command::SetVolume(volume))?;
+            wait_for_volume(&mut output_rx, wait).await?;
+        }
+        Action::SetMute(muted) => {
+            send(&input_tx, Command::SetMute(muted))?;
+            while !matches!(next_event(&mut output_rx, wait).await?, Event::Muted(_)) {}
+        }
+        Action::GetPlaybackState => {
+            send(&input_tx, Command::GetPlaybackState)?;
+            // repeat is the last of the three state events
+            while !matches!(next_event(&mut output_rx, wait).await?, Event::Repeat(_)) {}
+        }
+        Action::GetNowPlaying => {
+            send(&input_tx, Command::GetNowPlaying)?;
+            while !matches!(next_event(&mut output_rx, wait).await?, Event::Track(_)) {}
+        }
+        Action::Seek(position) => {
+            send(&input_tx, Command::Seek(position))?;
+            while !matches!(next_event(&mut output_rx, wait).await?, Event::Position(_)) {}
+        }
+        Action::Power(cmd) => {
+            send(&input_tx, cmd)?;
+            // a delay of zero goes down right away, without announcing it
+            if cmd != Command::Shutdown(Duration::ZERO) && cmd != Command::Reboot(Duration::ZERO) {
+                while !matches!(
+                    next_event(&mut output_rx, wait).await?,
+                    Event::PowerScheduled { .. } | Event::PowerCancelled
+                ) {}
+            }
+        }
+    }
+
+    // closing the input side flushes pending commands and closes the socket
+    drop(input_tx);
+    let mut result = Ok(());
+    while let Ok(Some(event)) = timeout(wait, output_rx.next()).await {
+        match event {
+            Event::Failed(cmd, message) if result.is_ok() => {
+                result = Err(Failure::Rejected(format!("{} failed: {}", cmd, message)));
+            }
+            event if !is_bookkeeping(&event) => println!("{}", event),
+            _ => {}
+        }
+    }
+    result
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("spotifypi-ctl: {}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match run(options).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Connection(e)) => {
+            eprintln!("spotifypi-ctl: {}", e);
+            ExitCode::from(EXIT_CONNECTION)
+        }
+        Err(Failure::Timeout) => {
+            eprintln!("spotifypi-ctl: the Pi did not answer in time");
+            ExitCode::from(EXIT_TIMEOUT)
+        }
+        Err(Failure::Rejected(e)) => {
+            eprintln!("spotifypi-ctl: {}", e);
+            ExitCode::from(EXIT_REJECTED)
+        }
+        Err(Failure::Auth) => {
+            eprintln!("spotifypi-ctl: authentication failed, check --secret");
+            ExitCode::from(EXIT_AUTH)
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Local mock SpotifyPi server for developing the panel without a Pi.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+
+use spotifypi_control_panel::discovery::MDNS_ADDR;
+use spotifypi_control_panel::mock::{advertise, serve, Advertisement, MockConfig};
+use spotifypi_control_panel::protocol::MAX_VOLUME;
+use spotifypi_control_panel::tls::{server_config, Fingerprint};
+
+
+const USAGE: &str = "\
+Usage: spotifypi-mock [OPTIONS]
+
+Options:
+    --listen ADDR         address to listen on (default 127.0.0.1:9487)
+    --volume N            initial volume (default 50)
+    --delay MS            wait MS milliseconds before every reply
+    --drop-rate P         drop the connection with probability P on each command
+    --drop-after N        drop every connection after N commands
+    --garbage-rate P      send a garbage frame with probability P before a reply
+    --reboot-delay SECS   refuse connections for SECS seconds after `reboot` (default 3)
+    --seed N              seed for fault injection
+    --no-acks             behave like a server without command acknowledgements
+    --no-mute             behave like a server without native mute
+    --no-power-delay      behave like a server that only shuts down or reboots right away
+    --advertise NAME      answer DNS-SD browse queries as device NAME
+    --mdns-listen ADDR    address to answer them on (default 224.0.0.251:5353)
+    --service-type TYPE   DNS-SD service type (default _spotifypi._tcp.local)
+    --tls-cert FILE       serve wss:// with this PEM certificate chain
+    --tls-key FILE        PEM private key of the certificate
+    --secret SECRET       require clients to authenticate with SECRET";
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:9487";
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", option))?;
+    value.parse::<T>().map_err(|_| format!("invalid value for {}: {}", option, value))
+}
+
+fn parse_probability(option: &str, value: Option<String>) -> Result<f64, String> {
+    let p = parse_value::<f64>(option, value)?;
+    if !(0. ..=1.).contains(&p) {
+        return Err(format!("{} must be between 0 and 1", option));
+    }
+    Ok(p)
+}
+
+#[derive(Debug, Default)]
+struct AdvertiseOptions {
+    name: Option<String>,
+    listen: Option<SocketAddr>,
+    service_type: Option<String>,
+}
+
+struct Options {
+    listen: String,
+    config: MockConfig,
+    advertise: AdvertiseOptions,
+    tls: Option<(PathBuf, PathBuf)>,
+}
+
+/// Returns `None` when help was requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut listen = DEFAULT_LISTEN.to_string();
+    let mut config = MockConfig::default();
+    let mut advertise = AdvertiseOptions::default();
+    let mut tls_cert: Option<PathBuf> = None;
+    let mut tls_key: Option<PathBuf> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = parse_value(&arg, args.next())?,
+            "--volume" => {
+                config.state.volume = parse_value(&arg, args.next())?;
+                if config.state.volume > MAX_VOLUME {
+                    return Err(format!("--volume must be at most {}", MAX_VOLUME));
+                }
+            }
+            "--delay" => config.faults.reply_delay = Duration::from_millis(parse_value(&arg, args.next())?),
+            "--drop-rate" => config.faults.drop_rate = parse_probability(&arg, args.next())?,
+            "--drop-after" => config.faults.drop_after = Some(parse_value(&arg, args.next())?),
+            "--garbage-rate" => config.faults.garbage_rate = parse_probability(&arg, args.next())?,
+            "--reboot-delay" => config.reboot_delay = Duration::from_secs(parse_value(&arg, args.next())?),
+            "--seed" => config.seed = Some(parse_value(&arg, args.next())?),
+            "--no-acks" => config.acks = false,
+            "--no-mute" => config.mute = false,
+            "--no-power-delay" => config.power_delay = false,
+            "--advertise" => advertise.name = Some(parse_value(&arg, args.next())?),
+            "--mdns-listen" => advertise.listen = Some(parse_value(&arg, args.next())?),
+            "--service-type" => advertise.service_type = Some(parse_value(&arg, args.next())?),
+            "--tls-cert" => tls_cert = Some(parse_value(&arg, args.next())?),
+            "--tls-key" => tls_key = Some(parse_value(&arg, args.next())?),
+            "--secret" => config.secret = Some(parse_value(&arg, args.next())?),
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    if advertise.name.is_none() && (advertise.listen.is_some() || advertise.service_type.is_some()) {
+        return Err("--mdns-listen and --service-type need --advertise".to_string());
+    }
+
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => return Err("--tls-cert and --tls-key go together".to_string()),
+    };
+
+    Ok(Some(Options { listen, config, advertise, tls }))
+}
+
+/// Binds `addr`, joining the group when it's a multicast address so queries
+/// are received next to any mDNS daemon already running on the host.
+fn bind_mdns(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = match addr {
+        SocketAddr::V4(v4) if v4.ip().is_multicast() => {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_address(true)?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())).into())?;
+            socket.join_multicast_v4(v4.ip(), &Ipv4Addr::UNSPECIFIED)?;
+            socket.into()
+        }
+        _ => std::net::UdpSocket::bind(addr)?,
+    };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+/// Reads the certificate and key, returning the fingerprint clients will pin
+/// along with the settings.
+fn load_tls(cert: &Path, key: &Path) -> io::Result<(rustls::ServerConfig, Fingerprint)> {
+    let cert_pem = std::fs::read(cert)?;
+    let key_pem = std::fs::read(key)?;
+    Ok((server_config(&cert_pem, &key_pem)?, Fingerprint::of_pem(&cert_pem)?))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Options { listen, mut config, advertise: advertise_options, tls } = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("spotifypi-mock: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Some((cert, key)) = tls {
+        match load_tls(&cert, &key) {
+            Ok((tls_config, fingerprint)) => {
+                eprintln!("Certificate fingerprint: {}", fingerprint);
+                config.tls = Some(Arc::new(tls_config));
+            }
+            Err(e) => {
+                eprintln!("spotifypi-mock: could not load {} and {}: {}", cert.display(), key.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("spotifypi-mock: could not listen on {}: {}", listen, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(name) = advertise_options.name {
+        let mdns_listen = advertise_options.listen.unwrap_or(MDNS_ADDR);
+        let socket = match bind_mdns(mdns_listen) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("spotifypi-mock: could not listen on {}: {}", mdns_listen, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        let mut advertisement = Advertisement::new(&name, listener.local_addr().expect("listener has an address"));
+        if let Some(service_type) = advertise_options.service_type {
+            advertisement.service_type = service_type;
+        }
+        tokio::spawn(async move {
+            if let Err(e) = advertise(socket, advertisement).await {
+                eprintln!("spotifypi-mock: advertising stopped: {}", e);
+            }
+        });
+    }
+
+    match serve(listener, config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("spotifypi-mock: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
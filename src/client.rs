@@ -0,0 +1,560 @@
+//! WebSocket client shared by the GTK panel and the command-line tool.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{future, Sink, SinkExt, StreamExt};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{interval, sleep_until, timeout_at, Instant, Interval, MissedTickBehavior};
+use tokio_tungstenite::{
+    client_async_tls_with_config, tungstenite::protocol::Message, Connector, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::auth::Proof;
+use crate::connection::ConnectPhase;
+use crate::protocol::{parse_art_frame, Command, ConnectStatus, Event, Request, CAPABILITY_ACK};
+use crate::tls::{self, Trust};
+
+
+pub const DEFAULT_ADDR: &str = "spotifypi.local:9487";
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrError {
+    Parse(url::ParseError),
+    /// The address names a scheme other than `ws` or `wss`.
+    Scheme(String),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrError::Parse(e) => write!(f, "{}", e),
+            AddrError::Scheme(scheme) => write!(f, "unsupported scheme {:?}, use ws:// or wss://", scheme),
+        }
+    }
+}
+
+impl std::error::Error for AddrError {}
+
+impl From<url::ParseError> for AddrError {
+    fn from(e: url::ParseError) -> Self {
+        AddrError::Parse(e)
+    }
+}
+
+/// Builds the WebSocket url for an address, either a bare `host:port`,
+/// which connects without TLS, or a `ws://` or `wss://` url.
+pub fn ws_url(addr: &str) -> Result<url::Url, AddrError> {
+    let url = match addr.split_once("://") {
+        Some(_) => url::Url::parse(addr)?,
+        None => url::Url::parse(&format!("ws://{}", addr))?,
+    };
+    match url.scheme() {
+        "ws" | "wss" => Ok(url),
+        scheme => Err(AddrError::Scheme(scheme.to_string())),
+    }
+}
+
+/// Periodic ping frames used to detect dead connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Time between two pings.
+    pub interval: Duration,
+    /// How long to wait for the pong before giving up on the connection.
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Disabled when `None`.
+    pub heartbeat: Option<Heartbeat>,
+    /// How long to wait for a command to be acknowledged, when the server
+    /// supports acknowledgements.
+    pub ack_timeout: Duration,
+    /// How many times an idempotent command is resent before it times out.
+    pub max_retries: u32,
+    /// At most one `set_volume` is sent per window, carrying the latest
+    /// value. Zero sends every change.
+    pub volume_coalesce: Duration,
+    /// How the server certificate is checked on `wss://` urls.
+    pub trust: Trust,
+    /// Shared secret for servers that require authentication.
+    pub secret: Option<String>,
+    /// How long the server is given to send its authentication challenge
+    /// before the connection counts as open. Servers that greet with
+    /// anything else are on right away, and zero doesn't wait for quiet
+    /// ones, which then can't ask.
+    pub challenge_wait: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            heartbeat: Some(Heartbeat::default()),
+            ack_timeout: Duration::from_secs(3),
+            max_retries: 2,
+            volume_coalesce: Duration::from_millis(100),
+            trust: Trust::default(),
+            secret: None,
+            challenge_wait: Duration::from_millis(250),
+        }
+    }
+}
+
+struct PendingRequest {
+    command: Command,
+    deadline: Instant,
+    retries: u32,
+}
+
+/// Commands waiting for an acknowledgement from the server.
+struct PendingRequests {
+    acks_supported: bool,
+    next_id: u64,
+    pending: HashMap<u64, PendingRequest>,
+    timeout: Duration,
+    max_retries: u32,
+}
+
+impl PendingRequests {
+    fn new(config: &ClientConfig) -> Self {
+        PendingRequests {
+            acks_supported: false,
+            next_id: 1,
+            pending: HashMap::new(),
+            timeout: config.ack_timeout,
+            max_retries: config.max_retries,
+        }
+    }
+
+    /// Tags `command` with a fresh id when the server acknowledges commands.
+    fn tag(&mut self, command: Command, retries: u32) -> Request {
+        if !self.acks_supported {
+            return command.into();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let deadline = Instant::now() + self.timeout;
+        self.pending.insert(id, PendingRequest { command, deadline, retries });
+        Request { id: Some(id), command }
+    }
+
+    fn resolve(&mut self, id: u64) -> Option<Command> {
+        self.pending.remove(&id).map(|request| request.command)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|request| request.deadline).min()
+    }
+
+    /// Removes the expired requests, returning the ones to resend and the
+    /// ones that ran out of retries.
+    fn expire(&mut self) -> (Vec<Request>, Vec<Command>) {
+        let now = Instant::now();
+        let expired: Vec<u64> = self.pending.iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut resend = Vec::new();
+        let mut timed_out = Vec::new();
+        for id in expired {
+            let request = self.pending.remove(&id).unwrap();
+            if request.command.is_idempotent() && request.retries < self.max_retries {
+                resend.push(self.tag(request.command, request.retries + 1));
+            } else {
+                timed_out.push(request.command);
+            }
+        }
+        (resend, timed_out)
+    }
+}
+
+fn send_event(output_tx: &UnboundedSender<Event>, event: Event) {
+    // the receiver going away just means nobody is listening anymore
+    let _ = output_tx.unbounded_send(event);
+}
+
+/// Connects to the Pi with the default [`ClientConfig`].
+pub async fn connect_to_ws(url: url::Url, input_rx: UnboundedReceiver<Command>, output_tx: UnboundedSender<Event>) {
+    connect_to_ws_with_config(url, ClientConfig::default(), input_rx, output_tx).await
+}
+
+/// Connects to the Pi and pumps frames until either side goes away.
+///
+/// Commands received on `input_rx` are written to the socket, and frames read
+/// from the socket are parsed and forwarded to `output_tx`, except for the
+/// synthetic events only the client reports (see [`Event::is_synthetic`]).
+/// `Connect` and `Disconnect` bracket the session, and `Latency` is
+/// reported after every heartbeat. Once the server advertises the `ack`
+/// capability, commands are tagged with request ids and their outcome is
+/// reported as `Done`, `Failed` or `TimedOut`. Bursts of `SetVolume` are
+/// coalesced so only the latest value of each window is sent. Dropping every
+/// sender of `input_rx` closes the connection, as does a heartbeat that goes
+/// unanswered.
+///
+/// `wss://` urls check the server certificate as set by
+/// [`ClientConfig::trust`]. When pinning, a certificate other than the pinned
+/// one fails the connection with `ConnectStatus::CertificateChanged`, and
+/// connecting with nothing pinned yet reports the accepted certificate with
+/// `Event::Certificate` before `Connect(Ok)`.
+///
+/// Servers requiring authentication are answered with
+/// [`ClientConfig::secret`] before `Connect(Ok)` is reported. A refused or
+/// missing secret is reported as `ConnectStatus::AuthFailed`.
+pub async fn connect_to_ws_with_config(
+    url: url::Url,
+    config: ClientConfig,
+    input_rx: UnboundedReceiver<Command>,
+    output_tx: UnboundedSender<Event>,
+) {
+    // nobody listens for the steps
+    let (progress_tx, _) = unbounded();
+    connect_to_ws_with_progress(url, config, input_rx, output_tx, progress_tx).await
+}
+
+/// Same as [`connect_to_ws_with_config`], also reporting each step of
+/// connecting on `progress_tx` until `Connect` is.
+pub async fn connect_to_ws_with_progress(
+    url: url::Url,
+    config: ClientConfig,
+    mut input_rx: UnboundedReceiver<Command>,
+    output_tx: UnboundedSender<Event>,
+    progress_tx: UnboundedSender<ConnectPhase>,
+) {
+    let presented = Arc::new(Mutex::new(None));
+    let connector = if url.scheme() == "wss" {
+        match tls::client_config(&config.trust, presented.clone()) {
+            Ok(tls_config) => Some(Connector::Rustls(Arc::new(tls_config))),
+            Err(e) => {
+                eprintln!("Failed to set up TLS: {}", e);
+                send_event(&output_tx, Event::Connect(ConnectStatus::Failed));
+                return
+            }
+        }
+    } else {
+        None
+    };
+    let presented = || *presented.lock().unwrap();
+
+    let socket = match open_socket(&url, &progress_tx).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Failed to connect: {}", e);
+            send_event(&output_tx, Event::Connect(ConnectStatus::Failed));
+            return
+        }
+    };
+    let (mut ws_stream, _) = match client_async_tls_with_config(url, socket, None, connector).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("Failed to connect: {}", e);
+            let status = match (&config.trust, presented()) {
+                (Trust::Pinned(Some(pin)), Some(fingerprint)) if *pin != fingerprint => {
+                    ConnectStatus::CertificateChanged(fingerprint)
+                }
+                _ => ConnectStatus::Failed,
+            };
+            send_event(&output_tx, Event::Connect(status));
+            return
+        }
+    };
+
+    eprintln!("WebSocket handshake has been successfully completed");
+    if config.secret.is_some() {
+        let _ = progress_tx.unbounded_send(ConnectPhase::Authenticating);
+    }
+    let greeting = match authenticate(&mut ws_stream, &config).await {
+        Ok(greeting) => greeting,
+        Err(status) => {
+            eprintln!("Authentication failed");
+            send_event(&output_tx, Event::Connect(status));
+            let _ = ws_stream.close(None).await;
+            return
+        }
+    };
+    if let (Trust::Pinned(None), Some(fingerprint)) = (&config.trust, presented()) {
+        send_event(&output_tx, Event::Certificate(fingerprint));
+    }
+    send_event(&output_tx, Event::Connect(ConnectStatus::Ok));
+
+    let (mut write, mut read) = ws_stream.split();
+
+    let mut ping_ticker = match &config.heartbeat {
+        Some(heartbeat) => {
+            let mut ticker = interval(heartbeat.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick fires immediately
+            ticker.tick().await;
+            Some(ticker)
+        }
+        None => None,
+    };
+    let mut ping_seq: u64 = 0;
+    let mut pending_ping: Option<(u64, Instant)> = None;
+    let mut requests = PendingRequests::new(&config);
+    // end of the current volume window, and the value held back until then
+    let mut volume_window: Option<Instant> = None;
+    let mut pending_volume: Option<Command> = None;
+    if let Some(message) = greeting {
+        handle_message(&output_tx, &mut requests, message);
+    }
+
+    'session: loop {
+        let pong_deadline = match (&config.heartbeat, pending_ping) {
+            (Some(heartbeat), Some((_, sent))) => Some(sent + heartbeat.timeout),
+            _ => None,
+        };
+        let ack_deadline = requests.next_deadline();
+
+        tokio::select! {
+            cmd = input_rx.next() => match cmd {
+                Some(cmd @ Command::SetVolume(_)) if !config.volume_coalesce.is_zero() => {
+                    if volume_window.is_some() {
+                        pending_volume = Some(cmd);
+                        continue;
+                    }
+                    volume_window = Some(Instant::now() + config.volume_coalesce);
+                    if let Err(err) = write_command(&mut write, &mut requests, cmd).await {
+                        eprintln!("Message send failed: {}", err);
+                        break;
+                    }
+                }
+                Some(cmd) => {
+                    // the held volume goes first, or it would undo a mute sent after it
+                    if let Some(volume) = pending_volume.take() {
+                        volume_window = Some(Instant::now() + config.volume_coalesce);
+                        if let Err(err) = write_command(&mut write, &mut requests, volume).await {
+                            eprintln!("Message send failed: {}", err);
+                            break;
+                        }
+                    }
+                    if let Err(err) = write_command(&mut write, &mut requests, cmd).await {
+                        eprintln!("Message send failed: {}", err);
+                        break;
+                    }
+                }
+                None => {
+                    if let Some(cmd) = pending_volume.take() {
+                        let _ = write_command(&mut write, &mut requests, cmd).await;
+                    }
+                    let _ = write.close().await;
+                    break;
+                }
+            },
+            message = read.next() => match message {
+                Some(Ok(message @ (Message::Text(_) | Message::Binary(_)))) => {
+                    if !handle_message(&output_tx, &mut requests, message) {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Pong(payload))) => {
+                    if let Some((seq, sent)) = pending_ping {
+                        if payload == seq.to_be_bytes() {
+                            pending_ping = None;
+                            send_event(&output_tx, Event::Latency(sent.elapsed()));
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => eprintln!("Message unwrap failed: {}", err),
+                None => break,
+            },
+            _ = tick(&mut ping_ticker), if pending_ping.is_none() => {
+                ping_seq += 1;
+                if let Err(err) = write.send(Message::Ping(ping_seq.to_be_bytes().to_vec())).await {
+                    eprintln!("Ping send failed: {}", err);
+                    break;
+                }
+                pending_ping = Some((ping_seq, Instant::now()));
+            }
+            _ = sleep_until(volume_window.unwrap_or_else(Instant::now)), if volume_window.is_some() => {
+                volume_window = None;
+                if let Some(cmd) = pending_volume.take() {
+                    volume_window = Some(Instant::now() + config.volume_coalesce);
+                    if let Err(err) = write_command(&mut write, &mut requests, cmd).await {
+                        eprintln!("Message send failed: {}", err);
+                        break;
+                    }
+                }
+            }
+            _ = sleep_until(pong_deadline.unwrap_or_else(Instant::now)), if pong_deadline.is_some() => {
+                eprintln!("Heartbeat timed out, no pong received");
+                break;
+            }
+            _ = sleep_until(ack_deadline.unwrap_or_else(Instant::now)), if ack_deadline.is_some() => {
+                let (resend, timed_out) = requests.expire();
+                for cmd in timed_out {
+                    eprintln!("Command timed out: {}", cmd);
+                    send_event(&output_tx, Event::TimedOut(cmd));
+                }
+                for request in resend {
+                    eprintln!("Retrying: {}", request);
+                    if let Err(err) = write.send(Message::text(request.to_string())).await {
+                        eprintln!("Message send failed: {}", err);
+                        break 'session;
+                    }
+                }
+            }
+        }
+    }
+
+    eprintln!("WebSocket disconnected !!!");
+    send_event(&output_tx, Event::Disconnect);
+}
+
+/// Looks up the host of `url` and opens a socket to the first of its
+/// addresses that accepts.
+async fn open_socket(url: &url::Url, progress_tx: &UnboundedSender<ConnectPhase>) -> io::Result<TcpStream> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("no host and port in {}", url));
+    let port = url.port_or_known_default().ok_or_else(invalid)?;
+    let _ = progress_tx.unbounded_send(ConnectPhase::Resolving);
+    let addrs: Vec<SocketAddr> = match url.host().ok_or_else(invalid)? {
+        url::Host::Domain(domain) => lookup_host((domain, port)).await?.collect(),
+        url::Host::Ipv4(ip) => vec![(ip, port).into()],
+        url::Host::Ipv6(ip) => vec![(ip, port).into()],
+    };
+    let _ = progress_tx.unbounded_send(ConnectPhase::Connecting);
+    TcpStream::connect(&addrs[..]).await
+}
+
+async fn write_command<S>(write: &mut S, requests: &mut PendingRequests, cmd: Command) -> Result<(), S::Error>
+where
+    S: Sink<Message> + Unpin,
+{
+    let request = requests.tag(cmd, 0);
+    write.send(Message::text(request.to_string())).await
+}
+
+/// Answers the challenge of servers requiring authentication. Returns the
+/// first frame when it turns out to be something else, from a server that
+/// doesn't.
+async fn authenticate(
+    ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    config: &ClientConfig,
+) -> Result<Option<Message>, ConnectStatus> {
+    let message = match next_message(ws_stream, config.challenge_wait).await? {
+        Some(message) => message,
+        // a quiet server isn't asking for anything
+        None => return Ok(None),
+    };
+    let nonce = match message.to_text().ok().and_then(|text| text.parse::<Event>().ok()) {
+        Some(Event::AuthChallenge(nonce)) => nonce,
+        _ => return Ok(Some(message)),
+    };
+    let secret = match &config.secret {
+        Some(secret) => secret,
+        None => {
+            eprintln!("The server requires authentication, but no secret is set");
+            return Err(ConnectStatus::AuthFailed);
+        }
+    };
+
+    let answer = Request::from(Command::Auth(Proof::new(secret, &nonce)));
+    ws_stream.send(Message::text(answer.to_string())).await.map_err(|_| ConnectStatus::Failed)?;
+    let answer = next_message(ws_stream, config.ack_timeout).await?;
+    match answer.as_ref().and_then(|message| message.to_text().ok()).map(|text| text.parse::<Event>()) {
+        Some(Ok(Event::AuthResult(true))) => Ok(None),
+        Some(Ok(Event::AuthResult(false))) => Err(ConnectStatus::AuthFailed),
+        _ => Err(ConnectStatus::Failed),
+    }
+}
+
+/// The next text or binary frame, or `None` if there is none within `wait`.
+async fn next_message(
+    ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    wait: Duration,
+) -> Result<Option<Message>, ConnectStatus> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match timeout_at(deadline, ws_stream.next()).await {
+            Ok(Some(Ok(message @ (Message::Text(_) | Message::Binary(_))))) => return Ok(Some(message)),
+            Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => return Err(ConnectStatus::Failed),
+            Ok(Some(Ok(_))) => {}
+            Err(_) => return Ok(None),
+        }
+    }
+}
+
+/// Handles a text frame, or a binary one carrying cover art or text.
+/// Returns `false` when the session has to end.
+fn handle_message(output_tx: &UnboundedSender<Event>, requests: &mut PendingRequests, message: Message) -> bool {
+    match message {
+        Message::Text(text) => handle_frame(output_tx, requests, text),
+        Message::Binary(data) => {
+            if let Some(event) = parse_art_frame(&data) {
+                send_event(output_tx, event);
+            } else if let Ok(text) = String::from_utf8(data) {
+                return handle_frame(output_tx, requests, text);
+            }
+            true
+        }
+        _ => true,
+    }
+}
+
+/// Returns `false` when the session has to end.
+fn handle_frame(output_tx: &UnboundedSender<Event>, requests: &mut PendingRequests, text: String) -> bool {
+    let event = match text.parse::<Event>() {
+        Ok(event) => event,
+        Err(err) => {
+            eprintln!("Event parse failed: {}", err);
+            return true;
+        }
+    };
+
+    match event {
+        // only the client reports those, a server sending them is lying
+        ref event if event.is_synthetic() => {
+            eprintln!("Ignoring a client event sent by the server: {}", event);
+        }
+        Event::Capabilities(ref capabilities) => {
+            requests.acks_supported = capabilities.iter().any(|c| c == CAPABILITY_ACK);
+            send_event(output_tx, event);
+        }
+        Event::Ack(id) => {
+            if let Some(cmd) = requests.resolve(id) {
+                send_event(output_tx, Event::Done(cmd));
+            }
+        }
+        Event::Error { id, message } => {
+            if let Some(cmd) = requests.resolve(id) {
+                send_event(output_tx, Event::Failed(cmd, message));
+            }
+        }
+        // a challenge slower than ClientConfig::challenge_wait, the session
+        // can't go on, and Connect(Ok) was already reported
+        Event::AuthChallenge(_) => {
+            eprintln!("The server asked to authenticate after the session started");
+            return false;
+        }
+        // only expected while authenticating
+        Event::AuthResult(_) => {}
+        event => send_event(output_tx, event),
+    }
+    true
+}
+
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
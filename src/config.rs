@@ -0,0 +1,321 @@
+//! User settings of the panel, persisted as TOML.
+//!
+//! Every field has a default, so a partial or empty file is fine and keys
+//! added by newer versions don't break older files. Times are given in
+//! seconds unless the key says otherwise.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::art_cache;
+use crate::backoff::Backoff;
+use crate::client::{ClientConfig, Heartbeat};
+use crate::discovery::{DiscoveryConfig, DEFAULT_SERVICE_TYPE};
+use crate::shortcuts;
+
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub reconnect: ReconnectConfig,
+    pub timeouts: TimeoutConfig,
+    pub discovery: DiscoverySettings,
+    pub ui: UiConfig,
+    pub desktop: DesktopConfig,
+    pub notifications: NotificationConfig,
+    /// Accelerators of the window actions, by action name. Listed actions
+    /// replace their default shortcuts, an empty list unbinds them.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub shortcuts: BTreeMap<String, Vec<String>>,
+}
+
+/// Geometry of the main window when it was last closed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: i32,
+    /// Zero uses the natural height.
+    pub height: i32,
+    pub maximized: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 600,
+            height: 0,
+            maximized: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Reconnect when an established connection is lost.
+    pub auto: bool,
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomized.
+    pub jitter: f64,
+    /// Retry forever when missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        let backoff = Backoff::default();
+        ReconnectConfig {
+            auto: false,
+            initial_delay: backoff.initial.as_secs_f64(),
+            max_delay: backoff.max.as_secs_f64(),
+            multiplier: backoff.multiplier,
+            jitter: backoff.jitter,
+            max_attempts: backoff.max_attempts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Zero disables the heartbeat.
+    pub heartbeat_interval: f64,
+    pub heartbeat_timeout: f64,
+    pub ack_timeout: f64,
+    pub max_retries: u32,
+    pub volume_coalesce_ms: u64,
+    /// Zero connects without waiting for a password challenge.
+    pub challenge_wait_ms: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        let client = ClientConfig::default();
+        let heartbeat = Heartbeat::default();
+        TimeoutConfig {
+            heartbeat_interval: heartbeat.interval.as_secs_f64(),
+            heartbeat_timeout: heartbeat.timeout.as_secs_f64(),
+            ack_timeout: client.ack_timeout.as_secs_f64(),
+            max_retries: client.max_retries,
+            volume_coalesce_ms: client.volume_coalesce.as_millis() as u64,
+            challenge_wait_ms: client.challenge_wait.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoverySettings {
+    pub enabled: bool,
+    pub service_type: String,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        DiscoverySettings {
+            enabled: true,
+            service_type: DEFAULT_SERVICE_TYPE.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub show_art: bool,
+    /// Size limit of the cover art cache, in MiB.
+    pub art_cache_mib: u64,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig {
+            show_art: true,
+            art_cache_mib: art_cache::DEFAULT_MAX_BYTES / 1024 / 1024,
+        }
+    }
+}
+
+/// Integration with the desktop session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DesktopConfig {
+    /// Publish the Pi as an MPRIS media player on the session bus.
+    pub mpris: bool,
+    /// Show an icon in the system tray.
+    pub tray: bool,
+    /// Closing the window hides it, leaving the tray icon to bring it back.
+    pub close_to_tray: bool,
+}
+
+impl Default for DesktopConfig {
+    fn default() -> Self {
+        DesktopConfig {
+            mpris: true,
+            tray: true,
+            close_to_tray: false,
+        }
+    }
+}
+
+/// Which desktop notifications to show.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// The track being played changed.
+    pub track: bool,
+    /// The connection was lost, given up on or restored.
+    pub connection: bool,
+    /// A shutdown or reboot was scheduled, cancelled or carried out.
+    pub power: bool,
+    /// Least time between two track notifications, in seconds.
+    pub track_interval: f64,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig {
+            track: true,
+            connection: true,
+            power: true,
+            track_interval: 5.,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from `path`, falling back to the defaults when the
+    /// file doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e),
+        };
+        let config: Config = toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write then rename so a reload never sees half a file
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Checks the values serde can't, naming the first offending key.
+    pub fn validate(&self) -> Result<(), String> {
+        let times = [
+            ("reconnect.initial_delay", self.reconnect.initial_delay),
+            ("reconnect.max_delay", self.reconnect.max_delay),
+            ("timeouts.heartbeat_interval", self.timeouts.heartbeat_interval),
+            ("timeouts.heartbeat_timeout", self.timeouts.heartbeat_timeout),
+            ("timeouts.ack_timeout", self.timeouts.ack_timeout),
+            ("notifications.track_interval", self.notifications.track_interval),
+        ];
+        for (key, value) in times {
+            if Duration::try_from_secs_f64(value).is_err() {
+                return Err(format!("{} must be a number of seconds, got {}", key, value));
+            }
+        }
+        let positive = [
+            ("reconnect.initial_delay", self.reconnect.initial_delay),
+            ("timeouts.heartbeat_timeout", self.timeouts.heartbeat_timeout),
+            ("timeouts.ack_timeout", self.timeouts.ack_timeout),
+        ];
+        if let Some((key, value)) = positive.into_iter().find(|(_, value)| *value <= 0.) {
+            return Err(format!("{} must be more than 0 seconds, got {}", key, value));
+        }
+        if self.reconnect.initial_delay > self.reconnect.max_delay {
+            return Err(format!(
+                "reconnect.initial_delay can't be longer than reconnect.max_delay ({} > {})",
+                self.reconnect.initial_delay, self.reconnect.max_delay
+            ));
+        }
+        if self.reconnect.multiplier.is_nan() || self.reconnect.multiplier < 1. {
+            return Err(format!("reconnect.multiplier must be at least 1, got {}", self.reconnect.multiplier));
+        }
+        if !(0. ..=1.).contains(&self.reconnect.jitter) {
+            return Err(format!("reconnect.jitter must be between 0 and 1, got {}", self.reconnect.jitter));
+        }
+        if self.window.width < 0 || self.window.height < 0 {
+            return Err("window size can't be negative".to_string());
+        }
+        if let Some(action) = self.shortcuts.keys().find(|action| shortcuts::find(action).is_none()) {
+            return Err(format!("shortcuts.{} is not an action", action));
+        }
+        Ok(())
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            initial: secs(self.reconnect.initial_delay),
+            max: secs(self.reconnect.max_delay),
+            multiplier: self.reconnect.multiplier,
+            jitter: self.reconnect.jitter,
+            max_attempts: self.reconnect.max_attempts,
+        }
+    }
+
+    /// Settings of the connection. The certificate trust is per device and
+    /// left at its default.
+    pub fn client_config(&self) -> ClientConfig {
+        let heartbeat = (self.timeouts.heartbeat_interval > 0.).then(|| Heartbeat {
+            interval: secs(self.timeouts.heartbeat_interval),
+            timeout: secs(self.timeouts.heartbeat_timeout),
+        });
+        ClientConfig {
+            heartbeat,
+            ack_timeout: secs(self.timeouts.ack_timeout),
+            max_retries: self.timeouts.max_retries,
+            volume_coalesce: Duration::from_millis(self.timeouts.volume_coalesce_ms),
+            challenge_wait: Duration::from_millis(self.timeouts.challenge_wait_ms),
+            ..ClientConfig::default()
+        }
+    }
+
+    pub fn discovery_config(&self) -> DiscoveryConfig {
+        DiscoveryConfig {
+            service_type: self.discovery.service_type.clone(),
+            ..DiscoveryConfig::default()
+        }
+    }
+
+    pub fn track_notification_interval(&self) -> Duration {
+        secs(self.notifications.track_interval)
+    }
+
+    pub fn art_cache_max_bytes(&self) -> u64 {
+        self.ui.art_cache_mib.saturating_mul(1024 * 1024)
+    }
+
+    /// The accelerators bound to `action`, the remapped ones or else the
+    /// defaults.
+    pub fn accels(&self, action: &str) -> Vec<String> {
+        match self.shortcuts.get(action) {
+            Some(accels) => accels.clone(),
+            None => shortcuts::find(action)
+                .map(|shortcut| shortcut.default_accels.iter().map(|accel| accel.to_string()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Only called on validated values.
+fn secs(value: f64) -> Duration {
+    Duration::try_from_secs_f64(value).unwrap_or_default()
+}
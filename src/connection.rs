@@ -0,0 +1,116 @@
+//! State of the connection to the Pi, and how it moves from one state to
+//! the next.
+
+use std::fmt;
+
+
+/// Steps of connecting, reported by
+/// [`connect_to_ws_with_progress`](crate::client::connect_to_ws_with_progress)
+/// before the `Connect` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectPhase {
+    /// Looking up the address of the Pi.
+    Resolving,
+    /// Opening the socket, then the TLS and WebSocket handshakes.
+    Connecting,
+    /// Answering the challenge of a Pi that requires a password.
+    Authenticating,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Idle,
+    Resolving,
+    Connecting,
+    Authenticating,
+    Connected,
+    /// Closing the connection as asked, until the client is done.
+    Disconnecting,
+    /// Waiting out the delay before reconnecting.
+    Backoff,
+    /// The last connect failed or the connection was lost, with the reason.
+    Failed(String),
+}
+
+/// What moves the connection from one state to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// Connecting was started, by hand or once the backoff delay passed.
+    Start,
+    /// The client moved on to another step of connecting.
+    Phase(ConnectPhase),
+    /// The client reported `Connect(Ok)`.
+    Connected,
+    /// Connecting failed or the connection was lost, for the given reason.
+    Failed(String),
+    /// Closing the connection or calling off connecting was asked for.
+    Close,
+    /// The client is done after being asked to close.
+    Closed,
+    /// A reconnect was scheduled after a failure.
+    BackOff,
+    /// The failure or the pending reconnect is forgotten.
+    Reset,
+}
+
+impl ConnectionState {
+    /// The state `input` moves to, or `None` when it doesn't apply to this
+    /// one, like a failure reported while disconnecting.
+    pub fn next(&self, input: Input) -> Option<ConnectionState> {
+        use ConnectionState::*;
+
+        let next = match (self, input) {
+            (Idle | Failed(_) | Backoff, Input::Start) => Resolving,
+            (Resolving, Input::Phase(ConnectPhase::Connecting)) => Connecting,
+            (Resolving | Connecting, Input::Phase(ConnectPhase::Authenticating)) => Authenticating,
+            (Resolving | Connecting | Authenticating, Input::Connected) => Connected,
+            (Resolving | Connecting | Authenticating | Connected, Input::Failed(reason)) => Failed(reason),
+            (Resolving | Connecting | Authenticating | Connected, Input::Close) => Disconnecting,
+            (Disconnecting, Input::Closed) => Idle,
+            (Failed(_), Input::BackOff) => Backoff,
+            (Failed(_) | Backoff, Input::Reset) => Idle,
+            _ => return None,
+        };
+        Some(next)
+    }
+
+    /// Whether a connect is under way.
+    pub fn is_connecting(&self) -> bool {
+        matches!(self, ConnectionState::Resolving | ConnectionState::Connecting | ConnectionState::Authenticating)
+    }
+
+    /// Whether a client is running, connecting, connected or closing.
+    pub fn is_active(&self) -> bool {
+        self.is_connecting() || matches!(self, ConnectionState::Connected | ConnectionState::Disconnecting)
+    }
+
+    /// The state in words, for the device called `name`.
+    pub fn describe(&self, name: &str) -> String {
+        match self {
+            ConnectionState::Idle => "Not connected".to_string(),
+            ConnectionState::Resolving => format!("Looking up {}...", name),
+            ConnectionState::Connecting => format!("Connecting to {}...", name),
+            ConnectionState::Authenticating => format!("Logging in to {}...", name),
+            ConnectionState::Connected => format!("Connected to {}", name),
+            ConnectionState::Disconnecting => format!("Disconnecting from {}...", name),
+            ConnectionState::Backoff => format!("Reconnecting to {}...", name),
+            ConnectionState::Failed(reason) => format!("Not connected: {}", reason),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Idle => write!(f, "idle"),
+            ConnectionState::Resolving => write!(f, "resolving"),
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Authenticating => write!(f, "authenticating"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Disconnecting => write!(f, "disconnecting"),
+            ConnectionState::Backoff => write!(f, "backoff"),
+            ConnectionState::Failed(reason) => write!(f, "failed ({})", reason),
+        }
+    }
+}
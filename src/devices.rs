@@ -0,0 +1,150 @@
+//! Named SpotifyPi devices, persisted as TOML.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::DEFAULT_ADDR;
+use crate::tls::{Fingerprint, Trust};
+
+
+/// How the certificate of a device reached over `wss://` is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrustMode {
+    #[default]
+    System,
+    /// Signed by the CA in [`Device::ca_file`].
+    CaFile,
+    /// Pinned to [`Device::fingerprint`], learned on the first connection.
+    Pin,
+}
+
+impl TrustMode {
+    fn is_system(&self) -> bool {
+        *self == TrustMode::System
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Device {
+    pub name: String,
+    /// `host:port` of the Pi, or a `ws://` or `wss://` url.
+    pub addr: String,
+    /// Shared secret for Pis that require authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "TrustMode::is_system")]
+    pub trust: TrustMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    /// Certificate fingerprint pinned with [`TrustMode::Pin`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl Device {
+    /// The certificate trust to connect with, or why it can't be used.
+    pub fn tls_trust(&self) -> Result<Trust, String> {
+        match self.trust {
+            TrustMode::System => Ok(Trust::System),
+            TrustMode::CaFile => match &self.ca_file {
+                Some(path) => Ok(Trust::CaFile(path.clone())),
+                None => Err(format!("No CA file set for {}", self.name)),
+            },
+            TrustMode::Pin => Ok(Trust::Pinned(self.fingerprint)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceList {
+    /// Name of the device selected last, picked again on startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected: Option<String>,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<Device>,
+}
+
+impl Default for DeviceList {
+    fn default() -> Self {
+        DeviceList {
+            selected: None,
+            devices: vec![Device {
+                name: "SpotifyPi".to_string(),
+                addr: DEFAULT_ADDR.to_string(),
+                ..Device::default()
+            }],
+        }
+    }
+}
+
+impl DeviceList {
+    /// Reads the list from `path`, falling back to the default device when
+    /// the file doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DeviceList::default()),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write then rename so a crash never leaves half a list behind
+        let tmp = path.with_extension("toml.tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // passwords are stored in the clear, keep them to ourselves from the
+        // first byte on
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Device> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Device> {
+        self.devices.iter_mut().find(|device| device.name == name)
+    }
+
+    /// Adds `device`, or replaces the one named `previous_name` when editing.
+    pub fn upsert(&mut self, previous_name: Option<&str>, device: Device) {
+        let name = previous_name.unwrap_or(&device.name);
+        match self.devices.iter().position(|d| d.name == name) {
+            Some(index) => {
+                if self.selected.as_deref() == Some(name) {
+                    self.selected = Some(device.name.clone());
+                }
+                self.devices[index] = device;
+            }
+            None => self.devices.push(device),
+        }
+    }
+
+    /// Returns `false` when there is no device by that name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.devices.len();
+        self.devices.retain(|device| device.name != name);
+        if self.selected.as_deref() == Some(name) {
+            self.selected = None;
+        }
+        self.devices.len() != len
+    }
+}
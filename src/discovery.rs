@@ -0,0 +1,298 @@
+//! Finds SpotifyPi devices on the LAN with DNS-SD over multicast DNS.
+//!
+//! Browsing sends one-shot queries from an ephemeral port, which responders
+//! answer with unicast replies (RFC 6762, section 6.7). This needs no access
+//! to port 5353, so it works next to a system mDNS daemon, and pointing
+//! [`DiscoveryConfig::query_addr`] at a unicast address browses a single
+//! responder, e.g. the mock on loopback.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::UnboundedSender;
+use simple_dns::rdata::{RData, SRV};
+use simple_dns::{Label, Name, Packet, PacketFlag, Question, ResourceRecord, CLASS, QCLASS, QTYPE, TYPE};
+use tokio::net::UdpSocket;
+use tokio::time::{interval, MissedTickBehavior};
+
+
+pub const DEFAULT_SERVICE_TYPE: &str = "_spotifypi._tcp.local";
+
+/// The IPv4 mDNS group.
+pub const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// DNS-SD service type, `.local` is appended when missing.
+    pub service_type: String,
+    /// Where queries are sent.
+    pub query_addr: SocketAddr,
+    /// How often the network is asked again. Devices that stop answering are
+    /// reported lost once their records expire.
+    pub query_interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            service_type: DEFAULT_SERVICE_TYPE.to_string(),
+            query_addr: MDNS_ADDR,
+            query_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A device advertising the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    /// Instance name, e.g. `Kitchen`.
+    pub name: String,
+    /// Host name the service runs on, e.g. `kitchen.local`.
+    pub host: String,
+    pub port: u16,
+    /// Address of `host`, when the responder included it.
+    pub ip: Option<Ipv4Addr>,
+}
+
+impl Service {
+    /// `host:port` to connect to, preferring the advertised address over
+    /// resolving the host name again.
+    pub fn addr(&self) -> String {
+        match self.ip {
+            Some(ip) => format!("{}:{}", ip, self.port),
+            None => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// A device appeared, or its address changed.
+    Found(Service),
+    /// The device with this instance name went away.
+    Lost(String),
+}
+
+#[derive(Debug, Default)]
+struct Instance {
+    /// Instance name as advertised.
+    label: String,
+    expires: Option<Instant>,
+    target: Option<(String, u16)>,
+    announced: Option<Service>,
+}
+
+/// Browses for devices until every receiver of `output_tx` is gone.
+///
+/// Only fails when the socket can't be set up; send and receive errors are
+/// logged and browsing carries on.
+pub async fn browse(config: DiscoveryConfig, output_tx: UnboundedSender<DiscoveryEvent>) -> io::Result<()> {
+    let service_type = normalize_service_type(&config.service_type);
+    let query = query_packet(&service_type)?;
+
+    let bind_addr: SocketAddr = match config.query_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    if config.query_addr.ip().is_multicast() && config.query_addr.is_ipv4() {
+        socket.set_multicast_ttl_v4(255)?;
+    }
+
+    let mut instances: HashMap<String, Instance> = HashMap::new();
+    let mut hosts: HashMap<String, Ipv4Addr> = HashMap::new();
+    let mut query_ticker = interval(config.query_interval);
+    query_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // also checks for expired records in between queries
+    let mut expire_ticker = interval(Duration::from_secs(1));
+    let mut buf = vec![0; 9000];
+
+    while !output_tx.is_closed() {
+        tokio::select! {
+            _ = query_ticker.tick() => {
+                if let Err(e) = socket.send_to(&query, config.query_addr).await {
+                    eprintln!("Failed to send mDNS query to {}: {}", config.query_addr, e);
+                }
+            }
+            _ = expire_ticker.tick() => {
+                let now = Instant::now();
+                instances.retain(|_, instance| match instance.expires {
+                    Some(expires) if expires <= now => {
+                        if let Some(service) = &instance.announced {
+                            let _ = output_tx.unbounded_send(DiscoveryEvent::Lost(service.name.clone()));
+                        }
+                        false
+                    }
+                    _ => true,
+                });
+            }
+            received = socket.recv_from(&mut buf) => {
+                let len = match received {
+                    Ok((len, _)) => len,
+                    Err(e) => {
+                        eprintln!("Failed to receive mDNS reply: {}", e);
+                        continue;
+                    }
+                };
+                let packet = match Packet::parse(&buf[..len]) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        eprintln!("Ignoring malformed mDNS packet: {}", e);
+                        continue;
+                    }
+                };
+                apply_records(&service_type, &packet, &mut instances, &mut hosts, &output_tx);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_records(
+    service_type: &str,
+    packet: &Packet,
+    instances: &mut HashMap<String, Instance>,
+    hosts: &mut HashMap<String, Ipv4Addr>,
+    output_tx: &UnboundedSender<DiscoveryEvent>,
+) {
+    let now = Instant::now();
+    let records = packet.answers.iter().chain(&packet.additional_records);
+
+    for record in records {
+        let name = normalize_name(&record.name);
+        match &record.rdata {
+            RData::PTR(ptr) if name == service_type => {
+                let instance_name = normalize_name(&ptr.0);
+                let label = match instance_label(&ptr.0, service_type) {
+                    Some(label) => label,
+                    None => continue,
+                };
+                if record.ttl == 0 {
+                    // goodbye packet
+                    if let Some(Instance { announced: Some(service), .. }) = instances.remove(&instance_name) {
+                        let _ = output_tx.unbounded_send(DiscoveryEvent::Lost(service.name));
+                    }
+                } else {
+                    let instance = instances.entry(instance_name).or_default();
+                    instance.label = label;
+                    instance.expires = Some(now + Duration::from_secs(record.ttl.into()));
+                }
+            }
+            RData::SRV(srv) if record.ttl > 0 => {
+                // only for instances announced by a PTR record earlier on
+                if let Some(instance) = instances.get_mut(&name) {
+                    instance.target = Some((normalize_name(&srv.target), srv.port));
+                }
+            }
+            RData::A(a) if record.ttl > 0 => {
+                hosts.insert(name, Ipv4Addr::from(a.address));
+            }
+            _ => {}
+        }
+    }
+
+    for instance in instances.values_mut() {
+        let (host, port) = match (&instance.target, instance.expires) {
+            (Some(target), Some(_)) => target,
+            _ => continue,
+        };
+        let service = Service {
+            name: instance.label.clone(),
+            host: host.clone(),
+            port: *port,
+            ip: hosts.get(host).copied(),
+        };
+        if instance.announced.as_ref() != Some(&service) {
+            instance.announced = Some(service.clone());
+            let _ = output_tx.unbounded_send(DiscoveryEvent::Found(service));
+        }
+    }
+}
+
+fn query_packet(service_type: &str) -> io::Result<Vec<u8>> {
+    let name = Name::new(service_type).map_err(invalid_input)?;
+    let mut packet = Packet::new_query(0);
+    packet.questions.push(Question::new(name, QTYPE::TYPE(TYPE::PTR), QCLASS::CLASS(CLASS::IN), true));
+    packet.build_bytes_vec().map_err(invalid_input)
+}
+
+fn invalid_input(e: simple_dns::SimpleDnsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// Lower case and without the trailing dot, as DNS names compare
+/// case-insensitively.
+fn normalize_name(name: &Name) -> String {
+    name.to_string().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn normalize_service_type(service_type: &str) -> String {
+    let service_type = service_type.trim_end_matches('.').to_ascii_lowercase();
+    if service_type.ends_with(".local") {
+        service_type
+    } else {
+        format!("{}.local", service_type)
+    }
+}
+
+/// `Kitchen` for `Kitchen._spotifypi._tcp.local`, or `None` when the name
+/// isn't an instance of `service_type`.
+fn instance_label(instance_name: &Name, service_type: &str) -> Option<String> {
+    // the instance is a single label, which may contain dots and spaces
+    let (label, rest) = instance_name.get_labels().split_first()?;
+    let rest: Vec<String> = rest.iter().map(|label| label.to_string()).collect();
+    rest.join(".").eq_ignore_ascii_case(service_type).then(|| label.to_string())
+}
+
+/// Builds the reply a responder answers a browse query with: the PTR record
+/// of `instance_name` followed by its SRV record and, when `ip` is known, the
+/// A record of `host`. A `ttl` of zero makes it a goodbye packet.
+pub fn service_reply(
+    id: u16,
+    service_type: &str,
+    instance_name: &str,
+    host: &str,
+    port: u16,
+    ip: Option<Ipv4Addr>,
+    ttl: u32,
+) -> io::Result<Vec<u8>> {
+    let service_type = normalize_service_type(service_type);
+    let service = Name::new(&service_type).map_err(invalid_input)?;
+    let mut labels = vec![Label::new_unchecked(instance_name.as_bytes())];
+    labels.extend(service_type.split('.').map(|label| Label::new_unchecked(label.as_bytes())));
+    let instance = Name::new_with_labels(&labels);
+    let host = Name::new(host).map_err(invalid_input)?;
+
+    let mut packet = Packet::new_reply(id);
+    packet.set_flags(PacketFlag::AUTHORITATIVE_ANSWER);
+    packet.answers.push(ResourceRecord::new(service, CLASS::IN, ttl, RData::PTR(instance.clone().into())));
+    packet.additional_records.push(ResourceRecord::new(instance, CLASS::IN, ttl, RData::SRV(SRV {
+        priority: 0,
+        weight: 0,
+        port,
+        target: host.clone(),
+    })));
+    if let Some(ip) = ip {
+        packet.additional_records.push(ResourceRecord::new(host, CLASS::IN, ttl, RData::A(ip.into())));
+    }
+    packet.build_bytes_vec().map_err(invalid_input)
+}
+
+/// The id of `packet` when it's a query browsing for `service_type`, which a
+/// responder should answer.
+pub fn browse_query_id(packet: &[u8], service_type: &str) -> Option<u16> {
+    let service_type = normalize_service_type(service_type);
+    let packet = Packet::parse(packet).ok()?;
+    if packet.has_flags(PacketFlag::RESPONSE) {
+        return None;
+    }
+    packet
+        .questions
+        .iter()
+        .any(|question| {
+            matches!(question.qtype, QTYPE::TYPE(TYPE::PTR) | QTYPE::ANY) && normalize_name(&question.qname) == service_type
+        })
+        .then(|| packet.id())
+}
@@ -0,0 +1,72 @@
+//! Command line of the panel.
+//!
+//! Only one panel runs at a time: starting it again forwards the command
+//! line to the running one, so `spotifypi-control-panel --device kitchen
+//! --next` skips a track on the kitchen Pi from a script or a desktop
+//! shortcut without opening a second window. The running panel parses the
+//! forwarded arguments with [`parse_args`] as well.
+
+use crate::shortcuts;
+
+
+pub const USAGE: &str = "\
+Usage: spotifypi-control-panel [OPTIONS] [ACTIONS]
+
+Starts the panel, or hands the options and actions to the one already
+running.
+
+Options:
+    --device NAME     select the saved or discovered device NAME
+    --connect ADDR    connect to HOST:PORT or a ws:// or wss:// url
+    --autoconnect     connect to the selected device
+    --minimized       start minimized
+    -h, --help        print this help
+
+Actions, run in order once connected, connecting first if needed:
+    --next, --prev    skip to the next or the previous track
+    --<action>        any window action, as named in the [shortcuts] table
+                      of config.toml, e.g. --play-pause or --volume-up";
+
+/// What the panel was started or called with.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LaunchOptions {
+    /// Name of the device to select.
+    pub device: Option<String>,
+    /// Address to connect to, whether it's a saved device or not.
+    pub connect: Option<String>,
+    pub autoconnect: bool,
+    pub minimized: bool,
+    /// Window actions to run, see [`shortcuts`].
+    pub actions: Vec<&'static str>,
+}
+
+impl LaunchOptions {
+    /// Whether a connection is asked for, explicitly or to run actions on.
+    pub fn wants_connection(&self) -> bool {
+        self.connect.is_some() || self.autoconnect || !self.actions.is_empty()
+    }
+}
+
+/// Parses the arguments following the program name. Returns `None` when
+/// help was requested.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<LaunchOptions>, String> {
+    let mut options = LaunchOptions::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--device" => options.device = Some(args.next().ok_or("--device needs a value")?),
+            "--connect" => options.connect = Some(args.next().ok_or("--connect needs a value")?),
+            "--autoconnect" => options.autoconnect = true,
+            "--minimized" => options.minimized = true,
+            "-h" | "--help" => return Ok(None),
+            "--next" => options.actions.push("next-track"),
+            "--prev" => options.actions.push("prev-track"),
+            _ => {
+                let shortcut = arg.strip_prefix("--")
+                    .and_then(shortcuts::find)
+                    .ok_or_else(|| format!("invalid argument: {}", arg))?;
+                options.actions.push(shortcut.action);
+            }
+        }
+    }
+    Ok(Some(options))
+}
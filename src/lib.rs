@@ -0,0 +1,16 @@
+pub mod art_cache;
+pub mod auth;
+pub mod backoff;
+pub mod client;
+pub mod config;
+pub mod connection;
+pub mod devices;
+pub mod discovery;
+pub mod launch;
+pub mod mock;
+pub mod mpris;
+pub mod protocol;
+pub mod rate_limit;
+pub mod shortcuts;
+pub mod tls;
+pub mod tray;
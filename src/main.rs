@@ -2,27 +2,79 @@
 
 pub mod main_window;
 
-use main_window::MainWindow;
+use main_window::{config_path, MainWindow, WINDOW_TITLE};
+use gtk::gio;
 use gtk::prelude::*;
 
+use spotifypi_control_panel::config::Config;
+use spotifypi_control_panel::launch::{self, LaunchOptions};
+
 
 #[tokio::main]
 async fn main() {
+    // checked here, the running panel has no terminal to complain on
+    match launch::parse_args(std::env::args().skip(1)) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            println!("{}", launch::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("spotifypi-control-panel: {}\n\n{}", e, launch::USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    // a second start hands its command line to the first one and exits
     let app = gtk::Application::builder()
         .application_id("site.riddleling.app.spotifypi-control-panel")
+        .flags(gio::ApplicationFlags::HANDLES_COMMAND_LINE)
         .build();
 
-    app.connect_activate(move |app| {
-        build_ui(&app);
+    app.connect_command_line(move |app, command_line| {
+        let args = command_line.arguments().into_iter().skip(1).map(|arg| arg.to_string_lossy().into_owned());
+        let options = match launch::parse_args(args) {
+            Ok(Some(options)) => options,
+            Ok(None) => return 0,
+            Err(e) => {
+                eprintln!("Ignoring command line: {}", e);
+                return 2;
+            }
+        };
+        let win = app.windows().into_iter()
+            .find_map(|win| win.downcast::<MainWindow>().ok())
+            .unwrap_or_else(|| build_ui(app, &options));
+        win.launch(options);
+        0
+    });
+
+    // clicking a notification
+    app.connect_activate(|app| {
+        if let Some(win) = app.windows().first() {
+            win.present();
+        }
     });
 
     app.run();
 }
 
-fn build_ui(app: &gtk::Application) {
-    let win = MainWindow::new(app);
-    win.set_title("SpotifyPi Control Panel");
+fn build_ui(app: &gtk::Application, options: &LaunchOptions) -> MainWindow {
+    let config = match Config::load(&config_path()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Could not load settings from {}, using the defaults: {}", config_path().display(), e);
+            Config::default()
+        }
+    };
+
+    let win = MainWindow::new(app, config);
+    win.set_title(WINDOW_TITLE);
     win.set_border_width(0);
     win.set_window_position(gtk::WindowPosition::Center);
+    if options.minimized {
+        // before showing, so it never flashes up
+        win.iconify();
+    }
     win.show_all();
-}
\ No newline at end of file
+    win
+}
@@ -1,58 +1,240 @@
 use glib::clone;
-use gtk::glib;
+use gtk::{gdk, gdk_pixbuf, gio, glib};
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use once_cell::unsync::OnceCell;
 
-use futures_util::{future, pin_mut, StreamExt};
+use futures_util::StreamExt;
 use futures::channel::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use futures::channel::oneshot;
 use tokio::task;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use zbus::connection;
+
+use spotifypi_control_panel::art_cache::{is_downloadable, ArtCache};
+use spotifypi_control_panel::backoff::Backoff;
+use spotifypi_control_panel::client::{connect_to_ws_with_progress, ws_url, ClientConfig};
+use spotifypi_control_panel::config::Config;
+use spotifypi_control_panel::connection::{ConnectionState, Input};
+use spotifypi_control_panel::devices::{Device, DeviceList, TrustMode};
+use spotifypi_control_panel::discovery::{browse, DiscoveryEvent, Service};
+use spotifypi_control_panel::launch::LaunchOptions;
+use spotifypi_control_panel::mpris::{self, Mpris};
+use spotifypi_control_panel::rate_limit::RateLimit;
+use spotifypi_control_panel::protocol::{
+    Command, ConnectStatus, Event, Position, PowerAction, RepeatState, Track, CAPABILITY_ACK, CAPABILITY_MUTE,
+    CAPABILITY_POWER_DELAY, MAX_VOLUME,
+};
+use spotifypi_control_panel::shortcuts::{self, SHORTCUTS};
+use spotifypi_control_panel::tls::Fingerprint;
+use spotifypi_control_panel::tray::{self, Tray, TrayRequest};
+
+use super::preferences::preferences_dialog;
+use super::{config_dir, config_path, WINDOW_TITLE};
+
+
+/// Width and height of the cover art, in pixels.
+const ART_SIZE: i32 = 64;
+
+/// How long after the last slider move volume reports from the Pi are held
+/// back, so they don't yank the slider while it's being dragged.
+const VOLUME_SETTLE: Duration = Duration::from_millis(300);
+
+/// How long a track has to play before it's announced, so skipping through
+/// tracks only announces the one stopped at.
+const TRACK_SETTLE: Duration = Duration::from_secs(2);
+
+/// How much the volume actions change the volume.
+const VOLUME_STEP: f64 = 5.;
+
+/// How far the seek actions jump.
+const SEEK_STEP: Duration = Duration::from_secs(10);
+
+/// Actions only available while connected. Seeking also needs a track.
+const CONNECTED_ACTIONS: [&str; 11] = [
+    "play-pause",
+    "next-track",
+    "prev-track",
+    "toggle-shuffle",
+    "toggle-repeat",
+    "volume-up",
+    "volume-down",
+    "mute",
+    "shutdown",
+    "reboot",
+    "cancel-power",
+];
+
+/// What activating one of the window actions does.
+type ActionHandler = fn(&MainWindow);
+
+/// A change for the tray icon, carried out by the task running it.
+enum TrayUpdate {
+    Event(Event),
+    Connection(String),
+    Devices(Vec<String>, Option<String>),
+    WindowVisible(bool),
+}
+
+/// Volume restored on unmute when the level before muting is unknown.
+const UNMUTE_FALLBACK_VOLUME: u8 = 50;
+
+/// How long to wait for the Pi to go down after a shutdown or reboot.
+const POWER_DOWN_TIMEOUT: u32 = 60;
+
+/// Delays offered when confirming a shutdown or reboot, in minutes.
+const POWER_DELAYS: [u64; 6] = [0, 1, 5, 15, 30, 60];
 
-use regex::Regex;
+/// Prefix of the combo box ids of discovered devices, saved devices use
+/// their name.
+const DISCOVERED_ID_PREFIX: &str = "discovered:";
 
 
 #[derive(Debug, Default)]
 pub struct MainWindow {
+    // settings
+    config: RefCell<Config>,
+    config_monitor: OnceCell<gio::FileMonitor>,
+
+    // devices
+    devices: RefCell<DeviceList>,
+    /// Devices found on the LAN, by name.
+    discovered: RefCell<Vec<Service>>,
+    /// Closing it stops the running discovery.
+    discovery_tx: RefCell<Option<UnboundedSender<DiscoveryEvent>>>,
+    /// Bumped on every restart, so events of a stopped discovery are dropped.
+    discovery_generation: Cell<u32>,
+    /// Events for the MPRIS bridge, dropping it takes the player off the bus.
+    mpris_tx: RefCell<Option<UnboundedSender<Event>>>,
+    /// Changes for the tray icon, dropping it removes the icon.
+    tray_tx: RefCell<Option<UnboundedSender<TrayUpdate>>>,
+    /// Whether a tray host shows the icon, so the window can be hidden.
+    tray_shown: Cell<bool>,
+    device_combo: OnceCell<gtk::ComboBoxText>,
+    lock_device_combo_signal: Cell<bool>,
+    edit_device_button: OnceCell<gtk::Button>,
+    remove_device_button: OnceCell<gtk::Button>,
+
     // connect
-    ws_addr_entry: OnceCell<gtk::Entry>,
     connect_button: OnceCell<gtk::Button>,
+    cancel_connect_button: OnceCell<gtk::Button>,
+    connection_state: RefCell<ConnectionState>,
+    /// Bumped on every connect, so events of a replaced client are dropped.
+    connection_generation: Cell<u32>,
+    /// The running client, aborted when cancelling.
+    connection_task: RefCell<Option<task::JoinHandle<()>>>,
+    /// Commands for the current connection, dropping it disconnects.
+    input_tx: RefCell<Option<UnboundedSender<Command>>>,
+    /// Device to connect to once the current connection is closed.
+    switch_to: RefCell<Option<(Device, url::Url)>>,
+    /// Actions from the command line, run once connected.
+    launch_actions: RefCell<Vec<&'static str>>,
+    client_config: RefCell<ClientConfig>,
+    /// Url of the device we're connected or connecting to.
+    device: RefCell<Option<String>>,
+    /// Name of that device.
+    device_name: RefCell<String>,
+    acks_supported: Cell<bool>,
+
+    // auto reconnect
+    auto_reconnect_button: OnceCell<gtk::CheckButton>,
+    backoff: RefCell<Backoff>,
+    reconnect_attempt: Cell<u32>,
+    reconnect_source_id: RefCell<Option<glib::SourceId>>,
+
+    // now playing
+    track_title_label: OnceCell<gtk::Label>,
+    track_details_label: OnceCell<gtk::Label>,
+    track_id: RefCell<Option<String>>,
+
+    // album art
+    art_image: OnceCell<gtk::Image>,
+    art_cache: RefCell<Option<ArtCache>>,
+
+    // progress
+    progress_scale: OnceCell<gtk::Scale>,
+    progress_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+    lock_progress_scale_signal: Cell<bool>,
+    /// Whether the slider is being dragged, seeking once it's let go.
+    progress_dragging: Cell<bool>,
+    elapsed_label: OnceCell<gtk::Label>,
+    duration_label: OnceCell<gtk::Label>,
+    /// Last position received from the Pi, and when it was received.
+    position: Cell<Option<(Position, Instant)>>,
+    progress_source_id: RefCell<Option<glib::SourceId>>,
 
     // prev track
     prev_track_button: OnceCell<gtk::Button>,
-    prev_track_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+
+    // playback state, as last reported by the Pi
+    playing: Cell<bool>,
+    shuffle: Cell<bool>,
+    repeat: Cell<RepeatState>,
+    lock_state_buttons_signal: Cell<bool>,
 
     // toggle play / pause
     play_pause_button: OnceCell<gtk::Button>,
-    play_pause_handler_id: RefCell<Option<glib::SignalHandlerId>>,
 
     // next track
     next_track_button: OnceCell<gtk::Button>,
-    next_track_handler_id: RefCell<Option<glib::SignalHandlerId>>,
 
     // toggle shuffle
-    toggle_shuffle_button: OnceCell<gtk::Button>,
-    toggle_shuffle_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+    toggle_shuffle_button: OnceCell<gtk::ToggleButton>,
 
     // toggle repeat off / single song / whole playlist
-    toggle_repeat_state_button: OnceCell<gtk::Button>,
-    toggle_repeat_state_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+    toggle_repeat_state_button: OnceCell<gtk::ToggleButton>,
 
     // power
     power_button: OnceCell<gtk::MenuButton>,
     power_popover: OnceCell<gtk::Popover>,
     shutdown_button: OnceCell<gtk::Button>,
-    shutdown_handler_id: RefCell<Option<glib::SignalHandlerId>>,
     reboot_button: OnceCell<gtk::Button>,
-    reboot_handler_id: RefCell<Option<glib::SignalHandlerId>>,
+    cancel_power_button: OnceCell<gtk::Button>,
+    /// Delayed shutdown or reboot announced by the Pi, and when it happens.
+    power_scheduled: Cell<Option<(PowerAction, Instant)>>,
+    /// Shutdown or reboot under way, waiting for the Pi to go down.
+    power_pending: Cell<Option<PowerAction>>,
+    /// Drives the countdown, then the wait for the Pi to go down.
+    power_source_id: RefCell<Option<glib::SourceId>>,
+    /// Whether the Pi can delay a shutdown or reboot, and call it off.
+    power_delay: Cell<bool>,
+
+    // notifications
+    /// Pending track notification, shown once the track settles.
+    track_notification_source_id: RefCell<Option<glib::SourceId>>,
+    track_notifications: RefCell<RateLimit>,
+    /// Id of the last track announced.
+    notified_track: RefCell<Option<String>>,
+
+    // status
+    status_label: OnceCell<gtk::Label>,
+    status_source_id: RefCell<Option<glib::SourceId>>,
+
+    // latency
+    latency_label: OnceCell<gtk::Label>,
 
     // volume
     volume_label: OnceCell<gtk::Label>,
-    volume_button: OnceCell<gtk::SpinButton>,
+    volume_scale: OnceCell<gtk::Scale>,
     volume_handler_id: RefCell<Option<glib::SignalHandlerId>>,
-    lock_volume_button_signal: Cell<bool>,
+    lock_volume_scale_signal: Cell<bool>,
+    /// When the user last moved the slider.
+    volume_changed_at: Cell<Option<Instant>>,
+    /// Latest volume reported by the Pi while the user was moving the slider.
+    reported_volume: Cell<Option<u8>>,
+    volume_sync_source_id: RefCell<Option<glib::SourceId>>,
+
+    // mute
+    mute_button: OnceCell<gtk::ToggleButton>,
+    muted: Cell<bool>,
+    native_mute: Cell<bool>,
+    /// Volume to restore on unmute, per device url.
+    premute_volumes: RefCell<HashMap<String, u8>>,
 }
 
 #[glib::object_subclass]
@@ -83,27 +265,163 @@ impl ObjectImpl for MainWindow {
             .margin_bottom(10)
             .spacing(5)
             .build();
-        let ws_label = gtk::Label::builder()
-            .label("ws://")
+        let device_label = gtk::Label::builder()
+            .label("Device:")
             .margin_start(5)
             .margin_end(0)
             .build();
 
-        let ws_addr_entry = gtk::Entry::builder()
-            .text("spotifypi.local:9487")
+        let device_combo = gtk::ComboBoxText::new();
+
+        let add_device_button = gtk::Button::builder()
+            .image(&gtk::Image::from_icon_name(Some("list-add"), gtk::IconSize::Button))
+            .tooltip_text("Add a device")
+            .build();
+        let edit_device_button = gtk::Button::builder()
+            .image(&gtk::Image::from_icon_name(Some("document-edit"), gtk::IconSize::Button))
+            .tooltip_text("Edit the selected device")
+            .build();
+        let remove_device_button = gtk::Button::builder()
+            .image(&gtk::Image::from_icon_name(Some("list-remove"), gtk::IconSize::Button))
+            .tooltip_text("Remove the selected device")
             .build();
 
         let connect_button = gtk::Button::builder()
             .label("Connect")
+            .action_name("win.connect")
+            .build();
+        // shown while connecting or waiting to reconnect
+        let cancel_connect_button = gtk::Button::builder()
+            .label("Cancel")
+            .action_name("win.disconnect")
+            .no_show_all(true)
+            .build();
+
+        let auto_reconnect_button = gtk::CheckButton::builder()
+            .label("Auto-reconnect")
             .build();
 
-        box1.pack_start(&ws_label, false, false, 0);
-        box1.pack_start(&ws_addr_entry, true, true, 0);
+        box1.pack_start(&device_label, false, false, 0);
+        box1.pack_start(&device_combo, true, true, 0);
+        box1.pack_start(&add_device_button, false, false, 0);
+        box1.pack_start(&edit_device_button, false, false, 0);
+        box1.pack_start(&remove_device_button, false, false, 0);
         box1.pack_start(&connect_button, false, false, 0);
+        box1.pack_start(&cancel_connect_button, false, false, 0);
+        box1.pack_start(&auto_reconnect_button, false, false, 0);
+
+        device_combo.connect_changed(clone!(@weak obj => move |_| {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.on_device_selected();
+        }));
+
+        add_device_button.connect_clicked(clone!(@weak obj => move |_| {
+            glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+                let priv_ = MainWindow::from_instance(&obj);
+                priv_.edit_device(None, None).await;
+            }));
+        }));
+
+        edit_device_button.connect_clicked(clone!(@weak obj => move |_| {
+            glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+                let priv_ = MainWindow::from_instance(&obj);
+                if let Some(device) = priv_.selected_device() {
+                    let previous_name = priv_.selected_saved_name();
+                    priv_.edit_device(Some(device), previous_name).await;
+                }
+            }));
+        }));
+
+        remove_device_button.connect_clicked(clone!(@weak obj => move |_| {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.remove_selected_device();
+        }));
+
+        auto_reconnect_button.connect_toggled(clone!(@weak obj => move |button| {
+            let priv_ = MainWindow::from_instance(&obj);
+            if !button.is_active() && priv_.cancel_reconnect() {
+                priv_.transition(Input::Reset);
+            }
+            if priv_.config.borrow().reconnect.auto != button.is_active() {
+                priv_.config.borrow_mut().reconnect.auto = button.is_active();
+                priv_.save_config();
+            }
+        }));
+
+
+        // now_playing_box
+        let now_playing_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .homogeneous(false)
+            .margin_start(15)
+            .margin_end(15)
+            .margin_top(10)
+            .margin_bottom(0)
+            .spacing(10)
+            .build();
+        let art_image = gtk::Image::builder()
+            .pixel_size(ART_SIZE)
+            .width_request(ART_SIZE)
+            .height_request(ART_SIZE)
+            .build();
+        let track_labels_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .homogeneous(false)
+            .valign(gtk::Align::Center)
+            .spacing(2)
+            .build();
+        let track_title_label = gtk::Label::builder()
+            .xalign(0.)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+        let track_details_label = gtk::Label::builder()
+            .xalign(0.)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+
+        track_labels_box.pack_start(&track_title_label, false, false, 0);
+        track_labels_box.pack_start(&track_details_label, false, false, 0);
+        now_playing_box.pack_start(&art_image, false, false, 0);
+        now_playing_box.pack_start(&track_labels_box, true, true, 0);
+
+
+        // progress_box
+        let progress_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .homogeneous(false)
+            .margin_start(15)
+            .margin_end(15)
+            .margin_top(10)
+            .margin_bottom(0)
+            .spacing(5)
+            .build();
+        let elapsed_label = gtk::Label::builder()
+            .label("0:00")
+            .build();
+        let progress_scale = gtk::Scale::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .draw_value(false)
+            .build();
+        let duration_label = gtk::Label::builder()
+            .label("0:00")
+            .build();
+
+        progress_box.pack_start(&elapsed_label, false, false, 0);
+        progress_box.pack_start(&progress_scale, true, true, 0);
+        progress_box.pack_start(&duration_label, false, false, 0);
 
-        connect_button.connect_clicked(clone!(@weak obj => move |_| {
+        progress_scale.connect_button_press_event(clone!(@weak obj => @default-return Inhibit(false), move |_, _| {
+            MainWindow::from_instance(&obj).progress_dragging.set(true);
+            Inhibit(false)
+        }));
+        progress_scale.connect_button_release_event(clone!(@weak obj => @default-return Inhibit(false), move |_, _| {
             let priv_ = MainWindow::from_instance(&obj);
-            priv_.on_connect_button_clicked();
+            priv_.progress_dragging.set(false);
+            let input_tx = priv_.input_tx.borrow().clone();
+            if let Some(input_tx) = input_tx {
+                priv_.send_progress_value(&input_tx);
+            }
+            Inhibit(false)
         }));
 
 
@@ -119,12 +437,15 @@ impl ObjectImpl for MainWindow {
             .build();
         let prev_track_button = gtk::Button::builder()
             .label("Prev track")
+            .action_name("win.prev-track")
             .build();
         let play_pause_button = gtk::Button::builder()
-            .label("Play / Pause")
+            .always_show_image(true)
+            .action_name("win.play-pause")
             .build();
         let next_track_button = gtk::Button::builder()
             .label("Next track")
+            .action_name("win.next-track")
             .build();
 
         box2.pack_start(&prev_track_button, false, false, 0);
@@ -142,11 +463,15 @@ impl ObjectImpl for MainWindow {
             .margin_bottom(10)
             .spacing(5)
             .build();
-        let toggle_shuffle_button = gtk::Button::builder()
-            .label("Toggle Shuffle")
+        let toggle_shuffle_button = gtk::ToggleButton::builder()
+            .label("Shuffle")
+            .image(&gtk::Image::from_icon_name(Some("media-playlist-shuffle"), gtk::IconSize::Button))
+            .always_show_image(true)
+            .action_name("win.toggle-shuffle")
             .build();
-        let toggle_repeat_state_button = gtk::Button::builder()
-            .label("Toggle Repeat off / Single song / Whole playlist")
+        let toggle_repeat_state_button = gtk::ToggleButton::builder()
+            .always_show_image(true)
+            .action_name("win.toggle-repeat")
             .build();
 
         box3.pack_start(&toggle_shuffle_button, true, true, 0);
@@ -175,9 +500,16 @@ impl ObjectImpl for MainWindow {
 
         let shutdown_button = gtk::Button::builder()
             .label("Shutdown")
+            .action_name("win.shutdown")
             .build();
         let reboot_button = gtk::Button::builder()
             .label("Reboot")
+            .action_name("win.reboot")
+            .build();
+        // only shown while a delayed shutdown or reboot is pending
+        let cancel_power_button = gtk::Button::builder()
+            .no_show_all(true)
+            .action_name("win.cancel-power")
             .build();
         let power_button = gtk::MenuButton::builder()
             .label("Power")
@@ -186,32 +518,79 @@ impl ObjectImpl for MainWindow {
             .popover(&power_popover)
             .build();
 
+        // preferences
+        let preferences_button = gtk::Button::builder()
+            .image(&gtk::Image::from_icon_name(Some("preferences-system"), gtk::IconSize::Button))
+            .tooltip_text("Preferences")
+            .relief(gtk::ReliefStyle::None)
+            .action_name("win.preferences")
+            .build();
+        let shortcuts_button = gtk::Button::builder()
+            .image(&gtk::Image::from_icon_name(Some("input-keyboard"), gtk::IconSize::Button))
+            .tooltip_text("Keyboard shortcuts")
+            .relief(gtk::ReliefStyle::None)
+            .action_name("win.shortcuts")
+            .build();
+
+        // status
+        let status_label = gtk::Label::builder()
+            .label("")
+            .margin_start(10)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+
+        // latency
+        let latency_label = gtk::Label::builder()
+            .label("")
+            .tooltip_text("Round-trip time to the SpotifyPi")
+            .margin_end(10)
+            .build();
+
         // volume
         let volume_label = gtk::Label::builder()
             .label("Volume:")
             .build();
-        let volume_button = gtk::SpinButton::builder()
+        let volume_scale = gtk::Scale::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .width_request(150)
+            .digits(0)
+            .value_pos(gtk::PositionType::Right)
             .margin_start(0)
             .margin_end(0)
             .build();
 
+        // mute
+        let mute_button = gtk::ToggleButton::builder()
+            .relief(gtk::ReliefStyle::None)
+            .action_name("win.mute")
+            .build();
+
         popover_box.pack_start(&shutdown_button, false, false, 0);
         popover_box.pack_start(&reboot_button, false, false, 0);
+        popover_box.pack_start(&cancel_power_button, false, false, 0);
         popover_box.show_all();
         power_popover.add(&popover_box);
         power_popover.set_position(gtk::PositionType::Top);
 
-        volume_button.set_range(0., 100.);
-        volume_button.set_increments(1., 0.);
-        volume_button.set_value(0.);
+        // the slider also follows the scroll wheel, one page per notch
+        volume_scale.set_range(0., MAX_VOLUME as f64);
+        volume_scale.set_increments(1., 5.);
+        volume_scale.set_value(0.);
 
         action_bor.pack_start(&power_button);
-        action_bor.pack_end(&volume_button);
+        action_bor.pack_start(&preferences_button);
+        action_bor.pack_start(&shortcuts_button);
+        action_bor.pack_start(&status_label);
+        action_bor.pack_end(&volume_scale);
+        action_bor.pack_end(&mute_button);
         action_bor.pack_end(&volume_label);
+        action_bor.pack_end(&latency_label);
         
 
         // add components to main_box
         main_box.pack_start(&box1, false, false, 0);
+        main_box.pack_start(&now_playing_box, false, false, 0);
+        main_box.pack_start(&progress_box, false, false, 0);
         main_box.pack_start(&box2, false, false, 0);
         main_box.pack_start(&box3, false, false, 0);
         main_box.pack_start(&blank_box, true, true, 0);
@@ -220,20 +599,44 @@ impl ObjectImpl for MainWindow {
         // set window
         obj.add(&main_box);
         obj.set_default_size(600, 0);
+        art_image.set_no_show_all(true);
+
+        obj.connect_delete_event(|obj, _| {
+            let priv_ = MainWindow::from_instance(obj);
+            priv_.save_geometry();
+            // only with an icon left to bring it back
+            if priv_.config.borrow().desktop.close_to_tray && priv_.tray_shown.get() {
+                obj.hide();
+                return Inhibit(true);
+            }
+            Inhibit(false)
+        });
+        obj.connect_visible_notify(|obj| {
+            MainWindow::from_instance(obj).update_tray(TrayUpdate::WindowVisible(obj.is_visible()));
+        });
 
-        // disable buttons 
-        prev_track_button.set_sensitive(false);
-        play_pause_button.set_sensitive(false);
-        next_track_button.set_sensitive(false);
-        toggle_shuffle_button.set_sensitive(false);
-        toggle_repeat_state_button.set_sensitive(false);
+        // disable buttons, those driven by an action follow its state
+        self.install_actions();
         power_button.set_sensitive(false);
-        volume_button.set_sensitive(false);
+        volume_scale.set_sensitive(false);
         volume_label.set_sensitive(false);
+        progress_scale.set_sensitive(false);
  
-        self.ws_addr_entry.set(ws_addr_entry).expect("Failed to initialize window state: ws_addr_entry");
+        self.device_combo.set(device_combo).expect("Failed to initialize window state: device_combo");
+        self.edit_device_button.set(edit_device_button).expect("Failed to initialize window state: edit_device_button");
+        self.remove_device_button.set(remove_device_button).expect("Failed to initialize window state: remove_device_button");
         self.connect_button.set(connect_button).expect("Failed to initialize window state: connect_button");
+        self.cancel_connect_button.set(cancel_connect_button).expect("Failed to initialize window state: cancel_connect_button");
+        self.auto_reconnect_button.set(auto_reconnect_button).expect("Failed to initialize window state: auto_reconnect_button");
         
+        self.track_title_label.set(track_title_label).expect("Failed to initialize window state: track_title_label");
+        self.track_details_label.set(track_details_label).expect("Failed to initialize window state: track_details_label");
+        self.art_image.set(art_image).expect("Failed to initialize window state: art_image");
+
+        self.progress_scale.set(progress_scale).expect("Failed to initialize window state: progress_scale");
+        self.elapsed_label.set(elapsed_label).expect("Failed to initialize window state: elapsed_label");
+        self.duration_label.set(duration_label).expect("Failed to initialize window state: duration_label");
+
         self.prev_track_button.set(prev_track_button).expect("Failed to initialize window state: prev_track_button");
         self.play_pause_button.set(play_pause_button).expect("Failed to initialize window state: play_pause_button");
         self.next_track_button.set(next_track_button).expect("Failed to initialize window state: next_track_button");
@@ -244,276 +647,2140 @@ impl ObjectImpl for MainWindow {
         self.power_popover.set(power_popover).expect("Failed to initialize window state: power_popover");
         self.shutdown_button.set(shutdown_button).expect("Failed to initialize window state: shutdown_button");
         self.reboot_button.set(reboot_button).expect("Failed to initialize window state: reboot_button");
+        self.cancel_power_button.set(cancel_power_button).expect("Failed to initialize window state: cancel_power_button");
 
+        self.status_label.set(status_label).expect("Failed to initialize window state: status_label");
+        self.latency_label.set(latency_label).expect("Failed to initialize window state: latency_label");
         self.volume_label.set(volume_label).expect("Failed to initialize window state: volume_label");
-        self.volume_button.set(volume_button).expect("Failed to initialize window state: volume_button");
+        self.volume_scale.set(volume_scale).expect("Failed to initialize window state: volume_scale");
+        self.mute_button.set(mute_button).expect("Failed to initialize window state: mute_button");
 
-        self.lock_volume_button_signal.set(false);
+        self.lock_volume_scale_signal.set(false);
+
+        self.set_now_playing(None);
+        self.set_playing(false);
+        self.set_shuffle(false);
+        self.set_repeat(RepeatState::Off);
+        self.set_muted(false);
+
+        match DeviceList::load(&devices_path()) {
+            Ok(devices) => {
+                self.devices.replace(devices);
+            }
+            Err(e) => eprintln!("Could not load devices from {}: {}", devices_path().display(), e),
+        }
+        self.refresh_device_combo(None);
     }
 }
 
 impl MainWindow {
-    fn on_connect_button_clicked(&self) {
-        let connect_button = self.connect_button.get().unwrap();
-        connect_button.set_sensitive(false);
-
-        let ws_addr_entry = self.ws_addr_entry.get().unwrap();
-        let connect_addr = format!("ws://{}", &ws_addr_entry.text());
+    /// Applies the settings loaded on startup, including the window geometry,
+    /// and starts watching the config file for changes.
+    pub(super) fn init_config(&self, config: Config) {
+        let obj = MainWindow::instance(self);
+        obj.set_default_size(config.window.width, config.window.height);
+        if config.window.maximized {
+            obj.maximize();
+        }
+        self.apply_config(config, true);
+
+        let file = gio::File::for_path(config_path());
+        match file.monitor_file(gio::FileMonitorFlags::WATCH_MOVES, None::<&gio::Cancellable>) {
+            Ok(monitor) => {
+                monitor.connect_changed(clone!(@weak obj => move |_, _, _, event| {
+                    match event {
+                        gio::FileMonitorEvent::ChangesDoneHint
+                        | gio::FileMonitorEvent::Created
+                        | gio::FileMonitorEvent::MovedIn
+                        | gio::FileMonitorEvent::Renamed => {
+                            MainWindow::from_instance(&obj).reload_config();
+                        }
+                        _ => {}
+                    }
+                }));
+                self.config_monitor.set(monitor).expect("Failed to initialize window state: config_monitor");
+            }
+            Err(e) => eprintln!("Could not watch {}: {}", config_path().display(), e),
+        }
+    }
 
-        let url = match url::Url::parse(connect_addr.as_str()) {
-            Ok(url) => url,
+    /// Picks up changes made to the config file by hand. Our own writes
+    /// come back here as well, and are ignored as nothing changed.
+    fn reload_config(&self) {
+        let config = match Config::load(&config_path()) {
+            Ok(config) => config,
             Err(e) => {
-                eprintln!("Url::parse failed: {}", e);
-                // display a dialog
-                let obj = MainWindow::instance(self);
-                glib::MainContext::default().spawn_local(show_dialog(obj, format!("{}", e)));
+                eprintln!("Could not reload settings: {}", e);
+                self.show_status(format!("Could not reload settings: {}", e));
                 return;
             }
         };
-        eprintln!("ws_url: {}", url);
-        ws_addr_entry.select_region(0,0);
+        if config != *self.config.borrow() {
+            self.apply_config(config, false);
+            self.show_status("Settings reloaded".to_string());
+        }
+    }
 
-        let (output_tx, output_rx) : (glib::Sender<String>, glib::Receiver<String>) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-        let (input_tx, input_rx) : (UnboundedSender<Message>, UnboundedReceiver<Message>) = unbounded();
+    /// Connection settings take effect on the next connect.
+    fn apply_config(&self, config: Config, initial: bool) {
+        let previous = self.config.replace(config.clone());
 
-        let prev_track_button = self.prev_track_button.get().unwrap();
-        let play_pause_button = self.play_pause_button.get().unwrap();
-        let next_track_button = self.next_track_button.get().unwrap();
-        let toggle_shuffle_button = self.toggle_shuffle_button.get().unwrap();
-        let toggle_repeat_state_button = self.toggle_repeat_state_button.get().unwrap();
-        let power_popover = self.power_popover.get().unwrap();
-        let shutdown_button = self.shutdown_button.get().unwrap();
-        let reboot_button = self.reboot_button.get().unwrap();
-        let volume_button = self.volume_button.get().unwrap();
-
-        // prev track
-        let prev_track_handler_id = prev_track_button.connect_clicked(clone!(@strong input_tx => move |_| {
-            input_tx.unbounded_send(Message::text("prev_track")).expect("Could not send through channel");
-        }));
-        self.prev_track_handler_id.replace(Some(prev_track_handler_id));
+        self.client_config.replace(config.client_config());
+        self.backoff.replace(config.backoff());
+        self.auto_reconnect_button.get().unwrap().set_active(config.reconnect.auto);
+        self.art_image.get().unwrap().set_visible(config.ui.show_art);
 
-        // toggle play / pause
-        let play_pause_handler_id = play_pause_button.connect_clicked(clone!(@strong input_tx => move |_| {
-            input_tx.unbounded_send(Message::text("toggle_play_pause")).expect("Could not send through channel");
-        }));
-        self.play_pause_handler_id.replace(Some(play_pause_handler_id));
+        if initial || previous.ui.art_cache_mib != config.ui.art_cache_mib {
+            let art_cache_dir = glib::user_cache_dir().join("spotifypi-control-panel").join("art");
+            match ArtCache::open(&art_cache_dir, config.art_cache_max_bytes()) {
+                Ok(art_cache) => {
+                    self.art_cache.replace(Some(art_cache));
+                }
+                Err(e) => eprintln!("Could not open art cache {}: {}", art_cache_dir.display(), e),
+            }
+        }
 
-        // next track
-        let next_track_handler_id = next_track_button.connect_clicked(clone!(@strong input_tx => move |_| {
-            input_tx.unbounded_send(Message::text("next_track")).expect("Could not send through channel");
-        }));
-        self.next_track_handler_id.replace(Some(next_track_handler_id));
+        if initial || previous.discovery != config.discovery {
+            self.restart_discovery();
+        }
 
-        // toggle shuffle
-        let toggle_shuffle_handler_id = toggle_shuffle_button.connect_clicked(clone!(@strong input_tx => move |_| {
-            input_tx.unbounded_send(Message::text("toggle_shuffle")).expect("Could not send through channel");
-        }));
-        self.toggle_shuffle_handler_id.replace(Some(toggle_shuffle_handler_id));
+        if initial || previous.shortcuts != config.shortcuts {
+            self.apply_shortcuts(&config);
+        }
 
-        // toggle repeat off / single song / whole playlist
-        let toggle_repeat_state_handler_id = toggle_repeat_state_button.connect_clicked(clone!(@strong input_tx => move |_| {
-            input_tx.unbounded_send(Message::text("toggle_repeat_state")).expect("Could not send through channel");
-        }));
-        self.toggle_repeat_state_handler_id.replace(Some(toggle_repeat_state_handler_id));
+        if initial || previous.desktop.mpris != config.desktop.mpris {
+            self.restart_mpris();
+        }
 
-        // shutdown
-        let shutdown_handler_id = shutdown_button.connect_clicked(
-            clone!(@strong input_tx, @weak power_popover => move |_| {
-                power_popover.hide();
-                input_tx.unbounded_send(Message::text("shutdown")).expect("Could not send through channel");
-            })
-        );
-        self.shutdown_handler_id.replace(Some(shutdown_handler_id));
+        if initial || previous.desktop.tray != config.desktop.tray {
+            self.restart_tray();
+        }
 
-        // reboot
-        let reboot_handler_id = reboot_button.connect_clicked(
-            clone!(@strong input_tx, @weak power_popover => move |_| {
-                power_popover.hide();
-                input_tx.unbounded_send(Message::text("reboot")).expect("Could not send through channel");
-            })
-        );
-        self.reboot_handler_id.replace(Some(reboot_handler_id));
+        self.track_notifications.borrow_mut().interval = config.track_notification_interval();
+        if !config.notifications.track {
+            self.cancel_track_notification();
+        }
+    }
 
-        // get window instance
+    /// Binds the accelerators of every action, skipping the ones GTK can't
+    /// parse.
+    fn apply_shortcuts(&self, config: &Config) {
+        let app = match MainWindow::instance(self).application() {
+            Some(app) => app,
+            None => return,
+        };
+        for shortcut in SHORTCUTS {
+            let accels: Vec<String> = config.accels(shortcut.action).into_iter()
+                .filter(|accel| {
+                    let valid = gtk::accelerator_parse(accel) != (0, gdk::ModifierType::empty());
+                    if !valid {
+                        eprintln!("Ignoring invalid shortcut for {}: {}", shortcut.action, accel);
+                    }
+                    valid
+                })
+                .collect();
+            let accels: Vec<&str> = accels.iter().map(String::as_str).collect();
+            app.set_accels_for_action(&shortcut.detailed_name(), &accels);
+        }
+    }
+
+    /// Adds a `win.` action for every control, see [`shortcuts`].
+    fn install_actions(&self) {
         let obj = MainWindow::instance(self);
+        let handlers: [(&str, ActionHandler); 17] = [
+            ("connect", MainWindow::on_connect_button_clicked),
+            ("disconnect", MainWindow::cancel_connection),
+            ("play-pause", |priv_| priv_.send(Command::TogglePlayPause)),
+            ("next-track", |priv_| priv_.send(Command::NextTrack)),
+            ("prev-track", |priv_| priv_.send(Command::PrevTrack)),
+            ("seek-forward", |priv_| priv_.seek_by(SEEK_STEP.as_millis() as f64)),
+            ("seek-backward", |priv_| priv_.seek_by(-(SEEK_STEP.as_millis() as f64))),
+            ("toggle-shuffle", |priv_| {
+                // set_shuffle toggling the button activates the action too
+                if priv_.lock_state_buttons_signal.get() {
+                    return;
+                }
+                priv_.send(Command::ToggleShuffle);
+                // wait for the Pi to report the new state
+                priv_.set_shuffle(priv_.shuffle.get());
+            }),
+            ("toggle-repeat", |priv_| {
+                if priv_.lock_state_buttons_signal.get() {
+                    return;
+                }
+                priv_.send(Command::ToggleRepeatState);
+                // wait for the Pi to report the new state
+                priv_.set_repeat(priv_.repeat.get());
+            }),
+            ("volume-up", |priv_| priv_.change_volume(VOLUME_STEP)),
+            ("volume-down", |priv_| priv_.change_volume(-VOLUME_STEP)),
+            ("mute", |priv_| {
+                if priv_.lock_state_buttons_signal.get() {
+                    return;
+                }
+                let input_tx = priv_.input_tx.borrow().clone();
+                if let Some(input_tx) = input_tx {
+                    priv_.toggle_mute(&input_tx);
+                }
+                // wait for the Pi to report the new state
+                priv_.set_muted(priv_.muted.get());
+            }),
+            ("shutdown", |priv_| priv_.on_power_activated(PowerAction::Shutdown)),
+            ("reboot", |priv_| priv_.on_power_activated(PowerAction::Reboot)),
+            ("cancel-power", |priv_| {
+                priv_.power_popover.get().unwrap().hide();
+                priv_.send(Command::CancelPower);
+            }),
+            ("preferences", |priv_| {
+                let obj = MainWindow::instance(priv_);
+                glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+                    MainWindow::from_instance(&obj).edit_preferences().await;
+                }));
+            }),
+            ("shortcuts", MainWindow::show_shortcuts),
+        ];
+        for (name, handler) in handlers {
+            debug_assert!(shortcuts::find(name).is_some(), "{} is not listed in SHORTCUTS", name);
+            let action = gio::SimpleAction::new(name, None);
+            // the others wait for a connection
+            action.set_enabled(matches!(name, "connect" | "preferences" | "shortcuts"));
+            action.connect_activate(clone!(@weak obj => move |_, _| {
+                handler(MainWindow::from_instance(&obj));
+            }));
+            obj.add_action(&action);
+        }
+    }
 
-        // volume
-        let volume_handler_id = volume_button.connect_value_changed(clone!(@weak obj, @strong input_tx => move |_| {
-            let priv_ = MainWindow::from_instance(&obj);
-            priv_.send_volume_value(&input_tx);
-        }));
-        self.volume_handler_id.replace(Some(volume_handler_id));
+    fn action(&self, name: &str) -> gio::SimpleAction {
+        MainWindow::instance(self).lookup_action(name)
+            .and_then(|action| action.downcast().ok())
+            .unwrap_or_else(|| panic!("Missing action: {}", name))
+    }
 
-        // receive message from ws
-        output_rx.attach(
-            None,
-            clone!(@weak obj, @strong input_tx => @default-return Continue(false),
-                move |msg| {
-                    eprintln!(">> msg: {}", msg);
-                    let priv_ = MainWindow::from_instance(&obj);
-                    let (event, value) = get_event_and_value(msg);
-                    if event == "connect" && value == "ok" {
-                        priv_.control_widgets_enable(true);
-                        input_tx.unbounded_send(Message::text("get_volume")).expect("Could not send through channel");
-                    } else if event == "connect" && value == "failed" {
-                        priv_.handle_disconnect("Connect failed.".to_string());
-                    } else if event == "disconnect" {
-                        priv_.handle_disconnect("WebSocket connection closed.".to_string());
-                    } else if event == "volume" {
-                        if let Ok(volume) = value.parse::<i32>() {
-                            priv_.set_volume_value(volume);
-                        }
-                    }
-                    glib::Continue(true)
+    /// Lists the shortcuts as currently bound, remapped ones included.
+    fn show_shortcuts(&self) {
+        let obj = MainWindow::instance(self);
+        let config = self.config.borrow();
+        let section = gtk::ShortcutsSection::builder()
+            .section_name("shortcuts")
+            .visible(true)
+            .build();
+        let mut groups: Vec<(&str, gtk::ShortcutsGroup)> = Vec::new();
+        for shortcut in SHORTCUTS {
+            let accels = config.accels(shortcut.action);
+            if accels.is_empty() {
+                continue;
+            }
+            let group = match groups.iter().find(|(title, _)| *title == shortcut.group) {
+                Some((_, group)) => group.clone(),
+                None => {
+                    let group = gtk::ShortcutsGroup::builder().title(shortcut.group).build();
+                    section.add(&group);
+                    groups.push((shortcut.group, group.clone()));
+                    group
                 }
-            )
-        );
+            };
+            group.add(&gtk::ShortcutsShortcut::builder()
+                .title(shortcut.title)
+                .accelerator(&accels.join(" "))
+                .build());
+        }
+        let window = gtk::ShortcutsWindow::builder()
+            .transient_for(&obj)
+            .modal(true)
+            .build();
+        window.add(&section);
+        window.show_all();
+    }
 
-        // connect to ws
-        connect_button.set_label("Connecting...");
-        task::spawn(async move {
-            connect_to_ws(url, input_rx, output_tx).await;
-        });
+    /// Moves the volume slider by `delta`, which sends the new volume.
+    fn change_volume(&self, delta: f64) {
+        let volume_scale = self.volume_scale.get().unwrap();
+        volume_scale.set_value((volume_scale.value() + delta).clamp(0., MAX_VOLUME as f64));
     }
 
-    fn handle_disconnect(&self, dialog_text: String) {
-        if let Some(id) = self.prev_track_handler_id.borrow_mut().take() {
-            self.prev_track_button.get().unwrap().disconnect(id)
+    /// Moves the progress slider by `delta` milliseconds, which seeks there.
+    fn seek_by(&self, delta: f64) {
+        let progress_scale = self.progress_scale.get().unwrap();
+        progress_scale.set_value(progress_scale.value() + delta);
+    }
+
+    fn save_config(&self) {
+        if let Err(e) = self.config.borrow().save(&config_path()) {
+            eprintln!("Could not save settings to {}: {}", config_path().display(), e);
+            self.show_status(format!("Could not save settings: {}", e));
         }
-        if let Some(id) = self.play_pause_handler_id.borrow_mut().take() {
-            self.play_pause_button.get().unwrap().disconnect(id)
+    }
+
+    fn save_geometry(&self) {
+        let obj = MainWindow::instance(self);
+        let mut config = self.config.borrow_mut();
+        config.window.maximized = obj.is_maximized();
+        if !config.window.maximized {
+            let (width, height) = obj.size();
+            config.window.width = width;
+            config.window.height = height;
         }
-        if let Some(id) = self.next_track_handler_id.borrow_mut().take() {
-            self.next_track_button.get().unwrap().disconnect(id)
+        drop(config);
+        self.save_config();
+    }
+
+    async fn edit_preferences(&self) {
+        let obj = MainWindow::instance(self);
+        let config = self.config.borrow().clone();
+        if let Some(config) = preferences_dialog(&obj, &config).await {
+            self.apply_config(config, false);
+            self.save_config();
         }
-        if let Some(id) = self.toggle_shuffle_handler_id.borrow_mut().take() {
-            self.toggle_shuffle_button.get().unwrap().disconnect(id)
+    }
+
+    /// Stops the running discovery, if any, and starts browsing again with
+    /// the current settings.
+    fn restart_discovery(&self) {
+        if let Some(discovery_tx) = self.discovery_tx.take() {
+            discovery_tx.close_channel();
         }
-        if let Some(id) = self.toggle_repeat_state_handler_id.borrow_mut().take() {
-            self.toggle_repeat_state_button.get().unwrap().disconnect(id)
+        let generation = self.discovery_generation.get().wrapping_add(1);
+        self.discovery_generation.set(generation);
+        if !self.discovered.borrow().is_empty() {
+            self.discovered.borrow_mut().clear();
+            let active = self.device_combo.get().unwrap().active_id().map(|id| id.to_string());
+            self.refresh_device_combo(active);
         }
-        if let Some(id) = self.shutdown_handler_id.borrow_mut().take() {
-            self.shutdown_button.get().unwrap().disconnect(id)
+
+        let config = self.config.borrow();
+        if !config.discovery.enabled {
+            return;
         }
-        if let Some(id) = self.reboot_handler_id.borrow_mut().take() {
-            self.reboot_button.get().unwrap().disconnect(id)
+        let discovery_config = config.discovery_config();
+        drop(config);
+
+        let obj = MainWindow::instance(self);
+        let (discovery_tx, mut discovery_rx) = unbounded();
+        self.discovery_tx.replace(Some(discovery_tx.clone()));
+        task::spawn(async move {
+            if let Err(e) = browse(discovery_config, discovery_tx).await {
+                eprintln!("Device discovery failed: {}", e);
+            }
+        });
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            while let Some(event) = discovery_rx.next().await {
+                let priv_ = MainWindow::from_instance(&obj);
+                if priv_.discovery_generation.get() != generation {
+                    break;
+                }
+                priv_.on_discovery_event(event);
+            }
+        }));
+    }
+
+    /// Publishes the Pi as an MPRIS player when enabled, see [`mpris`].
+    fn restart_mpris(&self) {
+        self.mpris_tx.replace(None);
+        if !self.config.borrow().desktop.mpris {
+            return;
         }
-        if let Some(id) = self.volume_handler_id.borrow_mut().take() {
-            self.volume_button.get().unwrap().disconnect(id)
+
+        let obj = MainWindow::instance(self);
+        let (mpris_tx, mut mpris_rx) = unbounded();
+        let (commands_tx, mut commands_rx) = unbounded();
+        task::spawn(async move {
+            let started = match connection::Builder::session() {
+                Ok(builder) => Mpris::start(builder, commands_tx).await,
+                Err(e) => Err(e),
+            };
+            let mpris = match started {
+                Ok(mpris) => mpris,
+                Err(e) => {
+                    eprintln!("Could not publish the MPRIS player: {}", e);
+                    return;
+                }
+            };
+            while let Some(event) = mpris_rx.next().await {
+                if let Err(e) = mpris.update(&event).await {
+                    eprintln!("Could not update the MPRIS player: {}", e);
+                }
+            }
+        });
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            while let Some(cmd) = commands_rx.next().await {
+                MainWindow::from_instance(&obj).send(cmd);
+            }
+        }));
+
+        // enabled mid-session, catch up on the player state
+        if self.is_connected() {
+            let _ = mpris_tx.unbounded_send(Event::Connect(ConnectStatus::Ok));
+            self.request_state();
         }
+        self.mpris_tx.replace(Some(mpris_tx));
+    }
 
-        self.control_widgets_enable(false);
+    /// Shows the tray icon when enabled, see [`tray`].
+    fn restart_tray(&self) {
+        self.tray_tx.replace(None);
+        self.tray_shown.set(false);
+        if !self.config.borrow().desktop.tray {
+            return;
+        }
 
-        // display a dialog
         let obj = MainWindow::instance(self);
-        glib::MainContext::default().spawn_local(show_dialog(obj, dialog_text));
+        let (tray_tx, mut tray_rx) = unbounded();
+        let (requests_tx, mut requests_rx) = unbounded();
+        let (shown_tx, shown_rx) = oneshot::channel();
+        task::spawn(async move {
+            let started = match connection::Builder::session() {
+                Ok(builder) => Tray::start(builder, requests_tx).await,
+                Err(e) => Err(e),
+            };
+            let tray = match started {
+                Ok(tray) => tray,
+                Err(e) => {
+                    eprintln!("Could not show the tray icon: {}", e);
+                    return;
+                }
+            };
+            let _ = shown_tx.send(());
+            while let Some(update) = tray_rx.next().await {
+                let updated = match update {
+                    TrayUpdate::Event(event) => tray.update(&event).await,
+                    TrayUpdate::Connection(connection) => tray.set_connection(&connection).await,
+                    TrayUpdate::Devices(devices, selected) => tray.set_devices(devices, selected).await,
+                    TrayUpdate::WindowVisible(visible) => tray.set_window_visible(visible).await,
+                };
+                if let Err(e) = updated {
+                    eprintln!("Could not update the tray icon: {}", e);
+                }
+            }
+        });
+        glib::MainContext::default().spawn_local(clone!(@weak obj, @strong tray_tx => async move {
+            if shown_rx.await.is_err() {
+                return;
+            }
+            let priv_ = MainWindow::from_instance(&obj);
+            // unless turned off or restarted while starting
+            let current = priv_.tray_tx.borrow().as_ref().is_some_and(|current| current.same_receiver(&tray_tx));
+            if current {
+                priv_.tray_shown.set(true);
+            }
+        }));
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            while let Some(request) = requests_rx.next().await {
+                MainWindow::from_instance(&obj).on_tray_request(request);
+            }
+        }));
+
+        let _ = tray_tx.unbounded_send(TrayUpdate::Connection(self.connection_text()));
+        let _ = tray_tx.unbounded_send(TrayUpdate::WindowVisible(obj.is_visible()));
+        self.tray_tx.replace(Some(tray_tx));
+        self.update_tray_devices();
+        if self.is_connected() {
+            self.update_tray(TrayUpdate::Event(Event::Connect(ConnectStatus::Ok)));
+            if let Some((action, deadline)) = self.power_scheduled.get() {
+                let delay = deadline.saturating_duration_since(Instant::now());
+                self.update_tray(TrayUpdate::Event(Event::PowerScheduled { action, delay }));
+            }
+            self.request_state();
+        }
     }
 
-    fn control_widgets_enable(&self, enable: bool) {
-        self.ws_addr_entry.get().unwrap().set_editable(!enable);
+    /// The state of the connection, in words.
+    fn connection_text(&self) -> String {
+        self.connection_state.borrow().describe(&self.device_name.borrow())
+    }
 
-        let connect_button = self.connect_button.get().unwrap();
-        connect_button.set_sensitive(!enable);
-        if enable {
-            connect_button.set_label("Connected");
-        } else {
-            connect_button.set_label("Connect");
+    fn update_tray_connection(&self) {
+        self.update_tray(TrayUpdate::Connection(self.connection_text()));
+    }
+
+    fn update_tray(&self, update: TrayUpdate) {
+        if let Some(tray_tx) = self.tray_tx.borrow().as_ref() {
+            // the tray may have failed to start
+            let _ = tray_tx.unbounded_send(update);
         }
-        
-        self.prev_track_button.get().unwrap().set_sensitive(enable);
-        self.play_pause_button.get().unwrap().set_sensitive(enable);
-        self.next_track_button.get().unwrap().set_sensitive(enable);
-        self.toggle_shuffle_button.get().unwrap().set_sensitive(enable);
-        self.toggle_repeat_state_button.get().unwrap().set_sensitive(enable);
-        self.power_button.get().unwrap().set_sensitive(enable);
-        self.volume_button.get().unwrap().set_sensitive(enable);
-        self.volume_label.get().unwrap().set_sensitive(enable);
     }
 
-    fn send_volume_value(&self, input_tx: &UnboundedSender<Message>) {
-        let volume_button = self.volume_button.get().unwrap();
-        if self.lock_volume_button_signal.get() == true {
-            eprintln!("!! lock volume button signal");
-            self.lock_volume_button_signal.set(false);
+    /// Lists the devices of the device list in the tray menu.
+    fn update_tray_devices(&self) {
+        if self.tray_tx.borrow().is_none() {
             return;
         }
-        volume_button.set_sensitive(false);
-        let value = volume_button.value_as_int();
-        eprintln!("< volume: {}", value);
-        let cmd = format!("set_volume {}", value);
-        input_tx.unbounded_send(Message::text(cmd)).expect("Could not send through channel");
+        let device_combo = self.device_combo.get().unwrap();
+        let name = |id: String| match id.strip_prefix(DISCOVERED_ID_PREFIX) {
+            Some(name) => name.to_string(),
+            None => id,
+        };
+        let mut devices = Vec::new();
+        if let Some((model, iter)) = device_combo.model().and_then(|model| Some((model.clone(), model.iter_first()?))) {
+            loop {
+                if let Ok(id) = model.value(&iter, device_combo.id_column()).get::<String>() {
+                    devices.push(name(id));
+                }
+                if !model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        let selected = device_combo.active_id().map(|id| name(id.to_string()));
+        self.update_tray(TrayUpdate::Devices(devices, selected));
     }
 
-    fn set_volume_value(&self, volume: i32) {
-        let volume_button = self.volume_button.get().unwrap();
-        let current_volume = volume_button.value_as_int();
-        if volume != current_volume {
-            self.lock_volume_button_signal.set(true);
-            let new_volume = volume as f64;
-            volume_button.set_value(new_volume);
+    fn on_tray_request(&self, request: TrayRequest) {
+        let obj = MainWindow::instance(self);
+        match request {
+            TrayRequest::Action(action) => {
+                // confirmations need a window to go with
+                if matches!(action, "shutdown" | "reboot") {
+                    obj.present();
+                }
+                self.action(action).activate(None);
+            }
+            TrayRequest::SetVolume(volume) if self.is_connected() => {
+                self.volume_scale.get().unwrap().set_value(volume as f64);
+            }
+            TrayRequest::SetVolume(_) => {}
+            TrayRequest::SelectDevice(name) => {
+                self.select_device(&name);
+            }
+            TrayRequest::ToggleWindow if obj.is_visible() => obj.hide(),
+            TrayRequest::ToggleWindow => obj.present(),
+            TrayRequest::Quit => {
+                self.save_geometry();
+                if let Some(app) = obj.application() {
+                    app.quit();
+                }
+            }
+            TrayRequest::Shown(shown) => {
+                // closing the window can't hide it to a tray that's gone
+                self.tray_shown.set(shown);
+                if !shown && !obj.is_visible() {
+                    obj.present();
+                }
+            }
         }
-        volume_button.set_sensitive(true);
     }
-}
 
-impl WidgetImpl for MainWindow {}
-impl ContainerImpl for MainWindow {}
-impl BinImpl for MainWindow {}
-impl WindowImpl for MainWindow {}
-impl ApplicationWindowImpl for MainWindow {}
+    /// Asks the Pi for the state the window shows.
+    fn request_state(&self) {
+        self.request(Command::GetVolume);
+        self.request(Command::GetPlaybackState);
+        self.request(Command::GetNowPlaying);
+    }
 
+    /// Forwards `event` to the MPRIS bridge, if it's running.
+    fn publish(&self, event: &Event) {
+        if let Some(mpris_tx) = self.mpris_tx.borrow().as_ref().filter(|_| mpris::is_published(event)) {
+            // the bridge may have failed to start
+            let _ = mpris_tx.unbounded_send(event.clone());
+        }
+        if tray::is_published(event) {
+            self.update_tray(TrayUpdate::Event(event.clone()));
+        }
+    }
 
-async fn connect_to_ws(url: url::Url, input_rx: UnboundedReceiver<Message>, output_tx: glib::Sender<String>) {
-    let (ws_stream, _) = match connect_async(url).await {
-        Ok(ws) => ws,
-        Err(e) => {
-            eprintln!("Failed to connect: {}", e);
-            output_tx.send("[connect](failed)".to_string()).expect("Could not send through channel");
-            return
+    fn on_connect_button_clicked(&self) {
+        if self.is_connected() {
+            self.disconnect();
+            return;
         }
-    };
+        // clicking while a reconnect is pending retries right away
+        self.cancel_reconnect();
 
-    eprintln!("WebSocket handshake has been successfully completed");
-    output_tx.send("[connect](ok)".to_string()).expect("Could not send through channel");
+        let (device, url) = match self.selected_target() {
+            Some(target) => target,
+            None => return,
+        };
+        eprintln!("ws_url: {}", url);
 
-    let (write, read) = ws_stream.split();
+        self.connect(device, url);
+    }
 
-    let input_to_ws = input_rx.map(Ok).forward(write);
-    let ws_to_output = {
-        read.for_each(|message| async {
-            match message {
-                Ok(msg) => {
-                    let data = msg.into_data();
-                    if let Ok(text) = String::from_utf8(data) {
-                        output_tx.send(text).expect("Could not send through channel");
-                    }
-                }
-                Err(err) => eprintln!("Message unwrap failed: {}", err)
+    /// The selected device and its url, showing a dialog when the address or
+    /// the certificate settings are invalid.
+    fn selected_target(&self) -> Option<(Device, url::Url)> {
+        match self.selected_device() {
+            Some(device) => self.target(device),
+            None => {
+                let obj = MainWindow::instance(self);
+                glib::MainContext::default().spawn_local(show_dialog(obj, "No device selected.".to_string()));
+                None
             }
-        })
-    };
-
-    pin_mut!(input_to_ws, ws_to_output);
-    future::select(input_to_ws, ws_to_output).await;
-
-    eprintln!("WebSocket disconnected !!!");
-    output_tx.send("[disconnect]()".to_string()).expect("Could not send through channel");
-}
-
-fn get_event_and_value(msg: String) -> (String, String) {
-    let re = Regex::new(r"\[(?P<event>.+?)\]\((?P<value>.*?)\)").unwrap();
-    match re.captures(&msg) {
-        Some(caps) => {
-            let event = &caps["event"];
-            let value = &caps["value"];
-            (event.to_string(), value.to_string())
         }
-        None => ("".to_string(), "".to_string())
     }
-}
 
-async fn show_dialog<W: IsA<gtk::Window>>(window: W, message: String) {
-    let dialog = gtk::MessageDialog::builder()
+    /// `device` and its url, see [`Self::selected_target`].
+    fn target(&self, device: Device) -> Option<(Device, url::Url)> {
+        let obj = MainWindow::instance(self);
+        let url = match ws_url(&device.addr) {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Url::parse failed: {}", e);
+                // display a dialog
+                glib::MainContext::default().spawn_local(show_dialog(obj, format!("{}: {}", device.name, e)));
+                return None;
+            }
+        };
+        if url.scheme() == "wss" {
+            if let Err(e) = device.tls_trust() {
+                glib::MainContext::default().spawn_local(show_dialog(obj, e));
+                return None;
+            }
+        }
+        Some((device, url))
+    }
+
+    /// Applies the options the panel was started or called with, see
+    /// [`launch`](spotifypi_control_panel::launch).
+    pub(super) fn launch(&self, options: LaunchOptions) {
+        let obj = MainWindow::instance(self);
+        if options.minimized {
+            obj.iconify();
+        } else {
+            obj.present();
+        }
+
+        if let Some(name) = &options.device {
+            // selecting it switches over when connected to another device
+            if !self.select_device(name) {
+                self.show_status(format!("No device named {}.", name));
+                return;
+            }
+        }
+
+        let target = match &options.connect {
+            Some(addr) => self.target(self.device_for_addr(addr)),
+            None if options.wants_connection() && !self.is_connected() => self.selected_target(),
+            None => None,
+        };
+        if let Some((device, url)) = target {
+            self.connect_to(device, url);
+        }
+
+        let switching = self.switch_to.borrow().is_some();
+        if self.is_connected() && !switching {
+            for action in options.actions {
+                self.action(action).activate(None);
+            }
+        } else if switching || self.connection_state.borrow().is_connecting() {
+            self.launch_actions.borrow_mut().extend(options.actions);
+        }
+    }
+
+    /// Selects the saved or discovered device called `name`. Returns `false`
+    /// if there is none.
+    fn select_device(&self, name: &str) -> bool {
+        let device_combo = self.device_combo.get().unwrap();
+        device_combo.set_active_id(Some(name))
+            || device_combo.set_active_id(Some(&format!("{}{}", DISCOVERED_ID_PREFIX, name)))
+    }
+
+    /// The saved device at `addr`, or an unsaved one named after it.
+    fn device_for_addr(&self, addr: &str) -> Device {
+        self.devices.borrow().devices.iter()
+            .find(|device| device.addr == addr)
+            .cloned()
+            .unwrap_or_else(|| Device {
+                name: addr.to_string(),
+                addr: addr.to_string(),
+                ..Device::default()
+            })
+    }
+
+    /// Connects to `url`, switching over from the current connection unless
+    /// it's to the same device. Does nothing while a connect is under way.
+    fn connect_to(&self, device: Device, url: url::Url) {
+        let state = self.connection_state.borrow().clone();
+        match state {
+            ConnectionState::Connected => {
+                if self.device.borrow().as_deref() != Some(url.as_str()) {
+                    self.switch_to.replace(Some((device, url)));
+                    self.disconnect();
+                }
+            }
+            ConnectionState::Disconnecting => {
+                self.switch_to.replace(Some((device, url)));
+            }
+            state if state.is_connecting() => {}
+            _ => {
+                self.cancel_reconnect();
+                self.connect(device, url);
+            }
+        }
+    }
+
+    fn run_launch_actions(&self) {
+        for action in self.launch_actions.take() {
+            self.action(action).activate(None);
+        }
+    }
+
+    /// Closes the current connection without reconnecting.
+    fn disconnect(&self) {
+        // the client closes the socket once every sender is gone
+        self.disconnect_handlers();
+        self.transition(Input::Close);
+    }
+
+    /// Calls off connecting or the pending reconnect, or disconnects. A
+    /// connect that hangs, or a close, is cut short by stopping the client.
+    fn cancel_connection(&self) {
+        let state = self.connection_state.borrow().clone();
+        match state {
+            ConnectionState::Connected => self.disconnect(),
+            ConnectionState::Backoff => {
+                self.cancel_reconnect();
+                self.transition(Input::Reset);
+            }
+            ConnectionState::Idle | ConnectionState::Failed(_) => {}
+            _ => {
+                self.cancel_reconnect();
+                self.launch_actions.borrow_mut().clear();
+                self.switch_to.replace(None);
+                self.disconnect();
+                // its events end with it, closing the session
+                if let Some(task) = self.connection_task.borrow_mut().take() {
+                    task.abort();
+                }
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        *self.connection_state.borrow() == ConnectionState::Connected
+    }
+
+    /// Moves the connection to its next state and updates the window to
+    /// match. Inputs that don't apply to the current state, like a failure
+    /// reported while disconnecting, are dropped. Returns whether it moved.
+    fn transition(&self, input: Input) -> bool {
+        let next = match self.connection_state.borrow().next(input) {
+            Some(next) => next,
+            None => return false,
+        };
+        eprintln!("connection: {}", next);
+        self.connection_state.replace(next);
+        self.update_control_widgets();
+        true
+    }
+
+    fn selected_device(&self) -> Option<Device> {
+        let id = self.device_combo.get().unwrap().active_id()?;
+        match id.strip_prefix(DISCOVERED_ID_PREFIX) {
+            Some(name) => self.discovered.borrow().iter()
+                .find(|service| service.name == name)
+                .map(|service| Device {
+                    name: service.name.clone(),
+                    addr: service.addr(),
+                    ..Device::default()
+                }),
+            None => self.devices.borrow().get(&id).cloned(),
+        }
+    }
+
+    /// Name of the selected device when it's a saved one.
+    fn selected_saved_name(&self) -> Option<String> {
+        let id = self.device_combo.get().unwrap().active_id()?;
+        (!id.starts_with(DISCOVERED_ID_PREFIX)).then(|| id.to_string())
+    }
+
+    fn on_device_selected(&self) {
+        if self.lock_device_combo_signal.get() {
+            return;
+        }
+        // discovered devices may be gone by the next start
+        if let Some(name) = self.selected_saved_name() {
+            self.devices.borrow_mut().selected = Some(name);
+            self.save_devices();
+        }
+        self.update_device_buttons();
+        self.update_tray_devices();
+
+        // switch right away when connected to another device
+        if self.is_connected() {
+            if let Some(target) = self.selected_target() {
+                self.switch_to.replace(Some(target));
+                self.disconnect();
+            }
+        } else if self.cancel_reconnect() {
+            self.transition(Input::Reset);
+            if let Some((device, url)) = self.selected_target() {
+                self.connect(device, url);
+            }
+        }
+    }
+
+    /// Asks for the details of a device, saving it as a new device or in
+    /// place of the saved device `previous_name`.
+    async fn edit_device(&self, device: Option<Device>, previous_name: Option<String>) {
+        let obj = MainWindow::instance(self);
+        let edited = match device_dialog(&obj, device).await {
+            Some(edited) => edited,
+            None => return,
+        };
+
+        let taken = previous_name.as_deref() != Some(edited.name.as_str())
+            && self.devices.borrow().get(&edited.name).is_some();
+        if taken {
+            show_dialog(obj, format!("There is already a device named \"{}\".", edited.name)).await;
+            return;
+        }
+
+        let name = edited.name.clone();
+        self.devices.borrow_mut().upsert(previous_name.as_deref(), edited);
+        self.devices.borrow_mut().selected = Some(name.clone());
+        self.save_devices();
+        self.refresh_device_combo(Some(name));
+    }
+
+    fn remove_selected_device(&self) {
+        if let Some(name) = self.selected_saved_name() {
+            self.devices.borrow_mut().remove(&name);
+            self.save_devices();
+            self.refresh_device_combo(None);
+        }
+    }
+
+    fn on_discovery_event(&self, event: DiscoveryEvent) {
+        eprintln!("discovery: {:?}", event);
+        let mut discovered = self.discovered.borrow_mut();
+        match event {
+            DiscoveryEvent::Found(service) => {
+                discovered.retain(|known| known.name != service.name);
+                discovered.push(service);
+                discovered.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+            DiscoveryEvent::Lost(name) => discovered.retain(|known| known.name != name),
+        }
+        drop(discovered);
+
+        let active = self.device_combo.get().unwrap().active_id().map(|id| id.to_string());
+        self.refresh_device_combo(active);
+    }
+
+    /// Pins `fingerprint` as the certificate of the saved device `name`.
+    /// Pins the certificate accepted on first use, unless the device isn't
+    /// pinning or has a certificate pinned already.
+    fn pin_first_certificate(&self, name: &str, fingerprint: Fingerprint) {
+        let unpinned = self.devices.borrow().get(name)
+            .is_some_and(|device| device.trust == TrustMode::Pin && device.fingerprint.is_none());
+        if unpinned {
+            self.pin_certificate(name, fingerprint);
+        }
+    }
+
+    fn pin_certificate(&self, name: &str, fingerprint: Fingerprint) {
+        match self.devices.borrow_mut().get_mut(name) {
+            Some(device) => device.fingerprint = Some(fingerprint),
+            None => return,
+        }
+        self.save_devices();
+        self.show_status(format!("Pinned the certificate of {}: {}", name, fingerprint));
+    }
+
+    /// Asks whether to trust the certificate `presented` by `device` in place
+    /// of the pinned one, connecting again if so.
+    async fn handle_certificate_changed(&self, device: Device, presented: Fingerprint) {
+        self.disconnect_handlers();
+        // never retry on our own, the new certificate needs a decision
+        self.cancel_reconnect();
+
+        let obj = MainWindow::instance(self);
+        if !certificate_changed_dialog(&obj, &device, presented).await {
+            return;
+        }
+        self.pin_certificate(&device.name, presented);
+        let device = self.devices.borrow().get(&device.name).cloned();
+        if let Some(device) = device {
+            if let Ok(url) = ws_url(&device.addr) {
+                self.connect(device, url);
+            }
+        }
+    }
+
+    fn save_devices(&self) {
+        if let Err(e) = self.devices.borrow().save(&devices_path()) {
+            eprintln!("Could not save devices to {}: {}", devices_path().display(), e);
+            self.show_status(format!("Could not save devices: {}", e));
+        }
+    }
+
+    /// Fills the combo box with the saved devices followed by the discovered
+    /// ones not saved yet, selecting the entry with id `active` if there is
+    /// one, else the device selected last.
+    fn refresh_device_combo(&self, active: Option<String>) {
+        let device_combo = self.device_combo.get().unwrap();
+        let devices = self.devices.borrow();
+
+        self.lock_device_combo_signal.set(true);
+        device_combo.remove_all();
+        for device in &devices.devices {
+            device_combo.append(Some(&device.name), &format!("{} ({})", device.name, device.addr));
+        }
+        for service in self.discovered.borrow().iter() {
+            let addr = service.addr();
+            if devices.devices.iter().any(|device| device.addr == addr) {
+                continue;
+            }
+            device_combo.append(
+                Some(&format!("{}{}", DISCOVERED_ID_PREFIX, service.name)),
+                &format!("{} ({}, discovered)", service.name, addr),
+            );
+        }
+        let fallback = devices.selected.as_deref()
+            .filter(|name| devices.get(name).is_some())
+            .or_else(|| devices.devices.first().map(|device| device.name.as_str()));
+        if !device_combo.set_active_id(active.as_deref()) {
+            device_combo.set_active_id(fallback);
+        }
+        self.lock_device_combo_signal.set(false);
+
+        drop(devices);
+        self.update_device_buttons();
+        self.update_tray_devices();
+    }
+
+    fn update_device_buttons(&self) {
+        let selected = self.device_combo.get().unwrap().active_id().is_some();
+        // saving a discovered device goes through the edit dialog
+        self.edit_device_button.get().unwrap().set_sensitive(selected);
+        self.remove_device_button.get().unwrap().set_sensitive(self.selected_saved_name().is_some());
+    }
+
+    fn connect(&self, device: Device, url: url::Url) {
+        // a fingerprint may have been pinned since `device` was picked
+        let device = self.devices.borrow().get(&device.name)
+            .filter(|saved| saved.addr == device.addr)
+            .cloned()
+            .unwrap_or(device);
+        // one connect at a time
+        if self.connection_state.borrow().next(Input::Start).is_none() {
+            return;
+        }
+        self.device.replace(Some(url.to_string()));
+        self.device_name.replace(device.name.clone());
+        let generation = self.connection_generation.get().wrapping_add(1);
+        self.connection_generation.set(generation);
+        self.transition(Input::Start);
+
+        let (output_tx, mut output_rx) : (UnboundedSender<Event>, UnboundedReceiver<Event>) = unbounded();
+        let (input_tx, input_rx) : (UnboundedSender<Command>, UnboundedReceiver<Command>) = unbounded();
+        let (progress_tx, mut progress_rx) = unbounded();
+        self.input_tx.replace(Some(input_tx.clone()));
+
+        let volume_scale = self.volume_scale.get().unwrap();
+        let progress_scale = self.progress_scale.get().unwrap();
+
+        // get window instance
+        let obj = MainWindow::instance(self);
+
+        // volume
+        let volume_handler_id = volume_scale.connect_value_changed(clone!(@weak obj, @strong input_tx => move |_| {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.send_volume_value(&input_tx);
+        }));
+        self.volume_handler_id.replace(Some(volume_handler_id));
+
+        // progress
+        let progress_handler_id = progress_scale.connect_value_changed(clone!(@weak obj, @strong input_tx => move |_| {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.send_progress_value(&input_tx);
+        }));
+        self.progress_handler_id.replace(Some(progress_handler_id));
+
+        // steps of connecting
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            while let Some(phase) = progress_rx.next().await {
+                let priv_ = MainWindow::from_instance(&obj);
+                if priv_.connection_generation.get() != generation {
+                    break;
+                }
+                // late steps, after connecting, are dropped
+                priv_.transition(Input::Phase(phase));
+            }
+        }));
+
+        // receive message from ws
+        glib::MainContext::default().spawn_local(
+            clone!(@weak obj, @strong device, @strong url => async move {
+                while let Some(event) = output_rx.next().await {
+                    let priv_ = MainWindow::from_instance(&obj);
+                    // a newer connect took over
+                    if priv_.connection_generation.get() != generation {
+                        break;
+                    }
+                    eprintln!(">> event: {}", event);
+                    priv_.publish(&event);
+                    match event {
+                        Event::Connect(ConnectStatus::Ok) => {
+                            if !priv_.transition(Input::Connected) {
+                                continue;
+                            }
+                            if priv_.reconnect_attempt.replace(0) > 0 && priv_.config.borrow().notifications.connection {
+                                priv_.notify("connection", &format!("Reconnected to {}", device.name), None, None);
+                            }
+                            priv_.request_state();
+                        }
+                        Event::Connect(ConnectStatus::Failed) => {
+                            if priv_.transition(Input::Failed("connect failed".to_string())) {
+                                priv_.handle_connection_lost(&device, &url, false, "Connect failed.".to_string());
+                            }
+                        }
+                        Event::Connect(ConnectStatus::AuthFailed) => {
+                            // the server closing the connection after refusing us is no news
+                            if priv_.transition(Input::Failed("authentication failed".to_string())) {
+                                priv_.cancel_reconnect();
+                                priv_.handle_disconnect(format!(
+                                    "Authentication failed. Check the password of {} in its device settings.",
+                                    device.name
+                                ));
+                            }
+                        }
+                        Event::Connect(ConnectStatus::CertificateChanged(presented)) => {
+                            if priv_.transition(Input::Failed("certificate changed".to_string())) {
+                                priv_.handle_certificate_changed(device.clone(), presented).await;
+                            }
+                        }
+                        Event::Certificate(fingerprint) => {
+                            priv_.pin_first_certificate(&device.name, fingerprint);
+                        }
+                        Event::Disconnect => {
+                            priv_.on_session_closed(&device, &url);
+                        }
+                        Event::Latency(latency) => {
+                            priv_.set_latency_value(Some(latency));
+                        }
+                        Event::Volume(volume) => {
+                            priv_.set_volume_value(volume);
+                            priv_.remember_volume(volume);
+                        }
+                        Event::Muted(muted) => {
+                            priv_.set_muted(muted);
+                        }
+                        Event::Playing(playing) => {
+                            priv_.set_playing(playing);
+                        }
+                        Event::Shuffle(shuffle) => {
+                            priv_.set_shuffle(shuffle);
+                        }
+                        Event::Repeat(repeat) => {
+                            priv_.set_repeat(repeat);
+                        }
+                        Event::Position(position) => {
+                            priv_.set_position(Some(position));
+                        }
+                        Event::Track(track) => {
+                            priv_.set_now_playing(track.as_ref());
+                            if let Some(track) = track {
+                                if !priv_.show_cached_art(&track.id) {
+                                    priv_.request(Command::GetArt);
+                                }
+                                priv_.announce_track(track);
+                            }
+                            // the track is the last of the state asked for on connect
+                            priv_.run_launch_actions();
+                        }
+                        Event::ArtUrl { track_id, url } => {
+                            priv_.fetch_art(track_id, url);
+                        }
+                        Event::ArtImage { track_id, data } => {
+                            priv_.store_art(&track_id, &data);
+                        }
+                        Event::PowerScheduled { action, delay } => {
+                            // announced again on every connect
+                            let known = priv_.power_scheduled.get().is_some_and(|(known, _)| known == action);
+                            priv_.set_power_scheduled(&device.name, action, delay);
+                            if !known {
+                                let title = format!("{} of {} in {}", power_noun(action), device.name, format_duration(delay));
+                                priv_.notify_power(&title);
+                            }
+                        }
+                        Event::PowerCancelled => {
+                            if let Some((action, _)) = priv_.power_scheduled.get() {
+                                priv_.reset_power();
+                                let message = format!("{} of {} cancelled", power_noun(action), device.name);
+                                priv_.notify_power(&message);
+                                priv_.show_status(message);
+                            }
+                        }
+                        Event::Capabilities(capabilities) => {
+                            priv_.acks_supported.set(capabilities.iter().any(|c| c == CAPABILITY_ACK));
+                            priv_.native_mute.set(capabilities.iter().any(|c| c == CAPABILITY_MUTE));
+                            priv_.power_delay.set(capabilities.iter().any(|c| c == CAPABILITY_POWER_DELAY));
+                            priv_.set_command_busy(Command::CancelPower, false);
+                        }
+                        Event::Done(cmd) => {
+                            priv_.set_command_busy(cmd, false);
+                        }
+                        Event::Failed(cmd, message) => {
+                            priv_.set_command_busy(cmd, false);
+                            priv_.show_status(format!("{} failed: {}", cmd, message));
+                            if let Some(refresh) = resync_command(cmd) {
+                                priv_.request(refresh);
+                            }
+                        }
+                        Event::TimedOut(cmd) => {
+                            priv_.set_command_busy(cmd, false);
+                            priv_.show_status(format!("{} timed out", cmd));
+                            if let Some(refresh) = resync_command(cmd) {
+                                priv_.request(refresh);
+                            }
+                        }
+                        // consumed by the client
+                        Event::Ack(_) | Event::Error { .. } | Event::AuthChallenge(_) | Event::AuthResult(_) => {}
+                    }
+                }
+                // cancelled clients are gone without a word
+                let priv_ = MainWindow::from_instance(&obj);
+                if priv_.connection_generation.get() == generation {
+                    priv_.on_session_closed(&device, &url);
+                }
+            })
+        );
+
+        // connect to ws
+        let client_config = ClientConfig {
+            trust: device.tls_trust().unwrap_or_default(),
+            secret: device.secret.clone(),
+            ..self.client_config.borrow().clone()
+        };
+        let connection_task = task::spawn(async move {
+            connect_to_ws_with_progress(url, client_config, input_rx, output_tx, progress_tx).await;
+        });
+        self.connection_task.replace(Some(connection_task));
+    }
+
+    /// The client is done: after disconnecting as asked, switches to the
+    /// device picked meanwhile, otherwise the connection was lost.
+    fn on_session_closed(&self, device: &Device, url: &url::Url) {
+        let state = self.connection_state.borrow().clone();
+        match state {
+            ConnectionState::Disconnecting => {
+                self.connection_task.replace(None);
+                self.transition(Input::Closed);
+                if let Some((device, url)) = self.switch_to.take() {
+                    self.connect(device, url);
+                }
+            }
+            ConnectionState::Connected => {
+                // read before the lost connection resets it
+                let power = self.power_going_down();
+                self.transition(Input::Failed("connection closed".to_string()));
+                match power {
+                    Some(action) => self.handle_power_down(device, url, action),
+                    None => self.handle_connection_lost(device, url, true, "WebSocket connection closed.".to_string()),
+                }
+            }
+            state if state.is_connecting() => {
+                self.transition(Input::Failed("connect failed".to_string()));
+                self.handle_connection_lost(device, url, false, "Connect failed.".to_string());
+            }
+            // a failed connect was handled when reported
+            _ => {}
+        }
+    }
+
+    fn on_power_activated(&self, action: PowerAction) {
+        self.power_popover.get().unwrap().hide();
+        let input_tx = self.input_tx.borrow().clone();
+        if let Some(input_tx) = input_tx {
+            self.confirm_power(input_tx, self.device_name.borrow().clone(), action);
+        }
+    }
+
+    /// Asks whether to shut down or reboot the device called `name`, and
+    /// when, then sends the command.
+    fn confirm_power(&self, input_tx: UnboundedSender<Command>, name: String, action: PowerAction) {
+        let obj = MainWindow::instance(self);
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            let power_delay = MainWindow::from_instance(&obj).power_delay.get();
+            let delay = match power_dialog(&obj, &name, action, power_delay).await {
+                Some(delay) => delay,
+                None => return,
+            };
+            // the connection may have closed while the dialog was open
+            if input_tx.is_closed() {
+                return;
+            }
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.send_command(&input_tx, action.command(delay));
+            // delayed ones are announced by the Pi
+            if delay.is_zero() {
+                priv_.expect_power_down(&name, action);
+            }
+        }));
+    }
+
+    /// Counts down to a delayed shutdown or reboot announced by the Pi.
+    fn set_power_scheduled(&self, name: &str, action: PowerAction, delay: Duration) {
+        self.reset_power();
+        let deadline = Instant::now() + delay;
+        self.power_scheduled.set(Some((action, deadline)));
+        let cancel_power_button = self.cancel_power_button.get().unwrap();
+        cancel_power_button.set_label(&format!("Cancel {}", action));
+        cancel_power_button.set_visible(self.power_delay.get());
+        self.show_power_countdown(action, deadline);
+
+        let obj = MainWindow::instance(self);
+        let name = name.to_string();
+        let source_id = glib::timeout_add_local(
+            Duration::from_millis(250),
+            clone!(@weak obj => @default-return Continue(false), move || {
+                let priv_ = MainWindow::from_instance(&obj);
+                if Instant::now() < deadline {
+                    priv_.show_power_countdown(action, deadline);
+                    return Continue(true);
+                }
+                // returning false removes the source
+                priv_.power_source_id.replace(None);
+                priv_.expect_power_down(&name, action);
+                Continue(false)
+            })
+        );
+        self.power_source_id.replace(Some(source_id));
+    }
+
+    fn show_power_countdown(&self, action: PowerAction, deadline: Instant) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let remaining = Duration::from_secs(remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64);
+        let label = format!("{} in {}", power_noun(action), format_duration(remaining));
+        self.power_button.get().unwrap().set_label(&label);
+    }
+
+    /// Waits for the Pi to go down after a shutdown or reboot, reporting it
+    /// when it doesn't.
+    fn expect_power_down(&self, name: &str, action: PowerAction) {
+        self.reset_power();
+        self.power_pending.set(Some(action));
+        let label = match action {
+            PowerAction::Shutdown => "Shutting down...",
+            PowerAction::Reboot => "Rebooting...",
+        };
+        self.power_button.get().unwrap().set_label(label);
+
+        let obj = MainWindow::instance(self);
+        let name = name.to_string();
+        let source_id = glib::timeout_add_seconds_local_once(POWER_DOWN_TIMEOUT, clone!(@weak obj => move || {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.power_source_id.replace(None);
+            priv_.reset_power();
+            priv_.show_status(format!("{} did not go down", name));
+        }));
+        self.power_source_id.replace(Some(source_id));
+    }
+
+    /// The shutdown or reboot under way, or about to happen: the Pi may
+    /// close the connection a little before the countdown runs out.
+    fn power_going_down(&self) -> Option<PowerAction> {
+        let about_to = self.power_scheduled.get()
+            .filter(|(_, deadline)| deadline.saturating_duration_since(Instant::now()) < Duration::from_secs(2))
+            .map(|(action, _)| action);
+        self.power_pending.get().or(about_to)
+    }
+
+    /// The Pi went down as asked: reported in the status bar rather than as
+    /// a lost connection, and reconnected to after a reboot.
+    fn handle_power_down(&self, device: &Device, url: &url::Url, action: PowerAction) {
+        self.disconnect_handlers();
+        let reconnect = action == PowerAction::Reboot && self.auto_reconnect_button.get().unwrap().is_active();
+        if !reconnect || !self.schedule_reconnect(device.clone(), url.clone()) {
+            self.transition(Input::Reset);
+        }
+        let message = match action {
+            PowerAction::Reboot => format!("{} is rebooting", device.name),
+            PowerAction::Shutdown => format!("{} has shut down", device.name),
+        };
+        self.notify_power(&message);
+        self.show_status(message);
+    }
+
+    /// Forgets any delayed or pending shutdown or reboot.
+    fn reset_power(&self) {
+        if let Some(id) = self.power_source_id.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        self.power_scheduled.set(None);
+        self.power_pending.set(None);
+        self.cancel_power_button.get().unwrap().hide();
+        self.power_button.get().unwrap().set_label("Power");
+    }
+
+    fn handle_connection_lost(&self, device: &Device, url: &url::Url, was_connected: bool, dialog_text: String) {
+        let auto_reconnect = self.auto_reconnect_button.get().unwrap().is_active();
+        // a failing first connect is more likely a wrong address than a blip
+        if !auto_reconnect || (!was_connected && self.reconnect_attempt.get() == 0) {
+            self.handle_disconnect(dialog_text);
+            return;
+        }
+
+        self.disconnect_handlers();
+        if !self.schedule_reconnect(device.clone(), url.clone()) {
+            let attempts = self.reconnect_attempt.replace(0);
+            self.handle_disconnect(format!("{} Gave up after {} reconnect attempts.", dialog_text, attempts));
+        } else if was_connected && self.config.borrow().notifications.connection {
+            self.notify("connection", &format!("Lost the connection to {}", device.name), Some("Reconnecting..."), None);
+        }
+    }
+
+    /// Arms a countdown that reconnects to `url` once the backoff delay for
+    /// the next attempt has passed. Returns `false` when out of attempts.
+    fn schedule_reconnect(&self, device: Device, url: url::Url) -> bool {
+        let attempt = self.reconnect_attempt.get() + 1;
+        let delay = match self.backoff.borrow().delay(attempt) {
+            Some(delay) => delay,
+            None => return false,
+        };
+        self.reconnect_attempt.set(attempt);
+        eprintln!("reconnect attempt {} in {:?}", attempt, delay);
+        self.transition(Input::BackOff);
+
+        let deadline = Instant::now() + delay;
+        self.show_reconnect_countdown(deadline, attempt);
+
+        let obj = MainWindow::instance(self);
+        let source_id = glib::timeout_add_local(
+            Duration::from_millis(250),
+            clone!(@weak obj => @default-return Continue(false), move || {
+                let priv_ = MainWindow::from_instance(&obj);
+                if Instant::now() < deadline {
+                    priv_.show_reconnect_countdown(deadline, attempt);
+                    return Continue(true);
+                }
+                // returning false removes the source
+                priv_.reconnect_source_id.replace(None);
+                priv_.connect(device.clone(), url.clone());
+                Continue(false)
+            })
+        );
+        self.reconnect_source_id.replace(Some(source_id));
+        true
+    }
+
+    fn show_reconnect_countdown(&self, deadline: Instant, attempt: u32) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let secs = remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64;
+        let connect_button = self.connect_button.get().unwrap();
+        connect_button.set_label(&format!("Reconnecting in {}s (attempt {})", secs, attempt));
+    }
+
+    /// Stops a pending reconnect countdown. Returns `true` if one was pending.
+    fn cancel_reconnect(&self) -> bool {
+        self.reconnect_attempt.set(0);
+        match self.reconnect_source_id.borrow_mut().take() {
+            Some(id) => {
+                glib::source_remove(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gives up on the failed connection, explaining why with `dialog_text`.
+    fn handle_disconnect(&self, dialog_text: String) {
+        self.launch_actions.borrow_mut().clear();
+        self.disconnect_handlers();
+
+        let obj = MainWindow::instance(self);
+        let notified = self.config.borrow().notifications.connection && self.notify(
+            "connection",
+            &format!("Disconnected from {}", self.device_name.borrow()),
+            Some(&dialog_text),
+            None,
+        );
+        // a hidden window has nothing to show a dialog over
+        if notified && !obj.is_visible() {
+            return;
+        }
+        glib::MainContext::default().spawn_local(show_dialog(obj, dialog_text));
+    }
+
+    /// Shows a desktop notification, replacing the last one of `kind`.
+    /// Skipped while the window is in front, as it shows the same. Returns
+    /// whether it was shown.
+    fn notify(&self, kind: &str, title: &str, body: Option<&str>, icon: Option<gio::Icon>) -> bool {
+        let obj = MainWindow::instance(self);
+        let app = match obj.application() {
+            Some(app) if !obj.is_active() => app,
+            _ => return false,
+        };
+        let notification = gio::Notification::new(title);
+        notification.set_body(body);
+        if let Some(icon) = icon {
+            notification.set_icon(&icon);
+        }
+        app.send_notification(Some(kind), &notification);
+        true
+    }
+
+    fn notify_power(&self, title: &str) {
+        if self.config.borrow().notifications.power {
+            self.notify("power", title, None, None);
+        }
+    }
+
+    /// Announces `track` once it has played for a while, and no sooner than
+    /// the rate limit allows.
+    fn announce_track(&self, track: Track) {
+        self.cancel_track_notification();
+        // the Pi repeats the track when asked what's playing
+        let known = self.notified_track.borrow().as_deref() == Some(track.id.as_str());
+        if !self.config.borrow().notifications.track || known {
+            return;
+        }
+
+        let delay = self.track_notifications.borrow().wait(Instant::now()).max(TRACK_SETTLE);
+        let obj = MainWindow::instance(self);
+        let source_id = glib::timeout_add_local_once(delay, clone!(@weak obj => move || {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.track_notification_source_id.replace(None);
+            // the cover art has likely arrived by now
+            let art = priv_.art_cache.borrow_mut().as_mut().and_then(|art_cache| art_cache.get(&track.id));
+            let icon = art.map(|data| gio::BytesIcon::new(&glib::Bytes::from_owned(data)).upcast());
+            let body = format!("{}\n{}", track.artist, track.album);
+            if priv_.notify("track", &track.title, Some(&body), icon) {
+                priv_.track_notifications.borrow_mut().record(Instant::now());
+            }
+            priv_.notified_track.replace(Some(track.id.clone()));
+        }));
+        self.track_notification_source_id.replace(Some(source_id));
+    }
+
+    fn cancel_track_notification(&self) {
+        if let Some(id) = self.track_notification_source_id.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+    }
+
+    fn disconnect_handlers(&self) {
+        self.input_tx.replace(None);
+        if let Some(id) = self.volume_handler_id.borrow_mut().take() {
+            self.volume_scale.get().unwrap().disconnect(id)
+        }
+        if let Some(id) = self.progress_handler_id.borrow_mut().take() {
+            self.progress_scale.get().unwrap().disconnect(id)
+        }
+    }
+
+    /// Brings the controls in line with the connection state.
+    fn update_control_widgets(&self) {
+        let state = self.connection_state.borrow().clone();
+        let enable = state == ConnectionState::Connected;
+        if !enable {
+            self.acks_supported.set(false);
+            self.native_mute.set(false);
+            self.power_delay.set(false);
+        }
+        self.device_combo.get().unwrap().set_sensitive(!state.is_connecting());
+
+        let connect_button = self.connect_button.get().unwrap();
+        let attempt = match self.reconnect_attempt.get() {
+            0 => String::new(),
+            attempt => format!(" (attempt {})", attempt),
+        };
+        match state {
+            ConnectionState::Idle | ConnectionState::Failed(_) => connect_button.set_label("Connect"),
+            ConnectionState::Resolving => connect_button.set_label(&format!("Looking up...{}", attempt)),
+            ConnectionState::Connecting => connect_button.set_label(&format!("Connecting...{}", attempt)),
+            ConnectionState::Authenticating => connect_button.set_label(&format!("Logging in...{}", attempt)),
+            ConnectionState::Connected => connect_button.set_label("Disconnect"),
+            ConnectionState::Disconnecting => connect_button.set_label("Disconnecting..."),
+            // counting down, see show_reconnect_countdown
+            ConnectionState::Backoff => {}
+        }
+        // clicking while a reconnect is pending retries right away
+        let busy = state.is_connecting() || state == ConnectionState::Disconnecting;
+        self.action("connect").set_enabled(!busy);
+        let cancellable = busy || state == ConnectionState::Backoff;
+        self.action("disconnect").set_enabled(cancellable || enable);
+        self.cancel_connect_button.get().unwrap().set_visible(cancellable);
+
+        for name in CONNECTED_ACTIONS {
+            self.action(name).set_enabled(enable && self.supports_action(name));
+        }
+        self.power_button.get().unwrap().set_sensitive(enable);
+        self.volume_scale.get().unwrap().set_sensitive(enable);
+        self.volume_label.get().unwrap().set_sensitive(enable);
+
+        if !enable {
+            self.reset_power();
+            self.set_latency_value(None);
+            self.set_now_playing(None);
+            self.set_playing(false);
+            self.set_shuffle(false);
+            self.set_repeat(RepeatState::Off);
+            self.set_muted(false);
+            self.cancel_track_notification();
+        }
+        self.update_tray_connection();
+    }
+
+    fn set_playing(&self, playing: bool) {
+        self.playing.set(playing);
+        let play_pause_button = self.play_pause_button.get().unwrap();
+        let (label, icon) = if playing {
+            ("Pause", "media-playback-pause")
+        } else {
+            ("Play", "media-playback-start")
+        };
+        play_pause_button.set_label(label);
+        play_pause_button.set_image(Some(&gtk::Image::from_icon_name(Some(icon), gtk::IconSize::Button)));
+    }
+
+    fn set_shuffle(&self, shuffle: bool) {
+        self.shuffle.set(shuffle);
+        let toggle_shuffle_button = self.toggle_shuffle_button.get().unwrap();
+        if toggle_shuffle_button.is_active() != shuffle {
+            self.lock_state_buttons_signal.set(true);
+            toggle_shuffle_button.set_active(shuffle);
+            self.lock_state_buttons_signal.set(false);
+        }
+        toggle_shuffle_button.set_tooltip_text(Some(if shuffle { "Shuffle on" } else { "Shuffle off" }));
+    }
+
+    fn set_repeat(&self, repeat: RepeatState) {
+        self.repeat.set(repeat);
+        let toggle_repeat_state_button = self.toggle_repeat_state_button.get().unwrap();
+        let active = repeat != RepeatState::Off;
+        if toggle_repeat_state_button.is_active() != active {
+            self.lock_state_buttons_signal.set(true);
+            toggle_repeat_state_button.set_active(active);
+            self.lock_state_buttons_signal.set(false);
+        }
+        let (label, icon) = match repeat {
+            RepeatState::Off => ("Repeat off", "media-playlist-repeat"),
+            RepeatState::Track => ("Repeat single song", "media-playlist-repeat-song"),
+            RepeatState::Context => ("Repeat whole playlist", "media-playlist-repeat"),
+        };
+        toggle_repeat_state_button.set_label(label);
+        toggle_repeat_state_button.set_image(Some(&gtk::Image::from_icon_name(Some(icon), gtk::IconSize::Button)));
+    }
+
+    fn set_now_playing(&self, track: Option<&Track>) {
+        let track_title_label = self.track_title_label.get().unwrap();
+        let track_details_label = self.track_details_label.get().unwrap();
+        let obj = MainWindow::instance(self);
+
+        let track_id = track.map(|track| track.id.clone());
+        if *self.track_id.borrow() != track_id {
+            self.show_art(None);
+        }
+        self.track_id.replace(track_id);
+
+        match track {
+            Some(track) => {
+                track_title_label.set_markup(&format!("<big><b>{}</b></big>", glib::markup_escape_text(&track.title)));
+                track_details_label.set_label(&format!("{} \u{2014} {} \u{00b7} {}", track.artist, track.album, format_duration(track.duration)));
+                obj.set_title(&format!("{} \u{2014} {} \u{00b7} {}", track.title, track.artist, WINDOW_TITLE));
+            }
+            None => {
+                track_title_label.set_markup("<big><b>Not playing</b></big>");
+                track_details_label.set_label("");
+                obj.set_title(WINDOW_TITLE);
+                self.set_position(None);
+            }
+        }
+    }
+
+    fn set_position(&self, position: Option<Position>) {
+        let progress_scale = self.progress_scale.get().unwrap();
+
+        if let Some(id) = self.progress_source_id.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        self.position.set(position.map(|position| (position, Instant::now())));
+
+        let duration = position.map(|position| position.duration).unwrap_or_default();
+        self.lock_progress_scale_signal.set(true);
+        progress_scale.set_range(0., duration.as_millis() as f64);
+        self.lock_progress_scale_signal.set(false);
+        self.duration_label.get().unwrap().set_label(&format_duration(duration));
+        let seekable = position.is_some() && self.is_connected();
+        progress_scale.set_sensitive(seekable);
+        self.action("seek-forward").set_enabled(seekable);
+        self.action("seek-backward").set_enabled(seekable);
+        self.update_progress();
+
+        // interpolate between updates from the Pi
+        if position.is_some_and(|position| position.playing) {
+            let obj = MainWindow::instance(self);
+            let source_id = glib::timeout_add_local(
+                Duration::from_millis(250),
+                clone!(@weak obj => @default-return Continue(false), move || {
+                    MainWindow::from_instance(&obj).update_progress();
+                    Continue(true)
+                })
+            );
+            self.progress_source_id.replace(Some(source_id));
+        }
+    }
+
+    fn current_position(&self) -> Duration {
+        match self.position.get() {
+            Some((position, received)) if position.playing => {
+                (position.position + received.elapsed()).min(position.duration)
+            }
+            Some((position, _)) => position.position,
+            None => Duration::ZERO,
+        }
+    }
+
+    fn update_progress(&self) {
+        // leave the slider to the user
+        if self.progress_dragging.get() {
+            return;
+        }
+        let progress_scale = self.progress_scale.get().unwrap();
+        let current = self.current_position();
+        self.elapsed_label.get().unwrap().set_label(&format_duration(current));
+
+        let value = current.as_millis() as f64;
+        if progress_scale.value() != value {
+            self.lock_progress_scale_signal.set(true);
+            progress_scale.set_value(value);
+            self.lock_progress_scale_signal.set(false);
+        }
+    }
+
+    fn send_progress_value(&self, input_tx: &UnboundedSender<Command>) {
+        if self.lock_progress_scale_signal.get() {
+            return;
+        }
+        let (mut position, _) = match self.position.get() {
+            Some(position) => position,
+            None => return,
+        };
+        // keep interpolating from where the user dropped the slider
+        position.position = Duration::from_millis(self.progress_scale.get().unwrap().value() as u64);
+        self.position.set(Some((position, Instant::now())));
+        self.elapsed_label.get().unwrap().set_label(&format_duration(position.position));
+        // a single seek once the slider is let go
+        if self.progress_dragging.get() {
+            return;
+        }
+
+        eprintln!("< seek: {:?}", position.position);
+        // a client that has ended already sent Disconnect
+        let _ = input_tx.unbounded_send(Command::Seek(position.position));
+    }
+
+    /// Shows the cached art of `track_id`, returns `false` on a cache miss.
+    fn show_cached_art(&self, track_id: &str) -> bool {
+        let data = match self.art_cache.borrow_mut().as_mut() {
+            Some(art_cache) => art_cache.get(track_id),
+            None => None,
+        };
+        match data {
+            Some(data) => {
+                self.show_art(Some(&data));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn fetch_art(&self, track_id: String, url: String) {
+        if !is_downloadable(&url) {
+            eprintln!("Not downloading art from {}", url);
+            return;
+        }
+        let obj = MainWindow::instance(self);
+        glib::MainContext::default().spawn_local(clone!(@weak obj => async move {
+            match gio::File::for_uri(&url).load_contents_async_future().await {
+                Ok((data, _)) => {
+                    let priv_ = MainWindow::from_instance(&obj);
+                    priv_.store_art(&track_id, &data);
+                }
+                Err(e) => eprintln!("Could not download art {}: {}", url, e),
+            }
+        }));
+    }
+
+    /// Caches `data` and shows it if it's the art of the current track.
+    /// Images that don't decode are dropped, so they're fetched again.
+    fn store_art(&self, track_id: &str, data: &[u8]) {
+        let pixbuf = match decode_art(data) {
+            Ok(pixbuf) => pixbuf,
+            Err(e) => {
+                eprintln!("Could not decode art: {}", e);
+                return;
+            }
+        };
+        if let Some(art_cache) = self.art_cache.borrow_mut().as_mut() {
+            if let Err(e) = art_cache.insert(track_id, data) {
+                eprintln!("Could not cache art: {}", e);
+            }
+        }
+        if self.track_id.borrow().as_deref() == Some(track_id) {
+            self.show_pixbuf(Some(&pixbuf));
+        }
+    }
+
+    fn show_art(&self, data: Option<&[u8]>) {
+        match data.map(decode_art) {
+            Some(Ok(pixbuf)) => self.show_pixbuf(Some(&pixbuf)),
+            Some(Err(e)) => {
+                eprintln!("Could not decode art: {}", e);
+                self.show_pixbuf(None);
+            }
+            None => self.show_pixbuf(None),
+        }
+    }
+
+    fn show_pixbuf(&self, pixbuf: Option<&gdk_pixbuf::Pixbuf>) {
+        let art_image = self.art_image.get().unwrap();
+        match pixbuf {
+            Some(pixbuf) => art_image.set_from_pixbuf(Some(pixbuf)),
+            None => art_image.set_from_icon_name(Some("audio-x-generic"), gtk::IconSize::Dialog),
+        }
+    }
+
+    /// Sends `cmd` on the current connection like `send_command`, if there
+    /// is one.
+    fn send(&self, cmd: Command) {
+        let input_tx = self.input_tx.borrow().clone();
+        if let Some(input_tx) = input_tx {
+            self.send_command(&input_tx, cmd);
+        }
+    }
+
+    /// Sends `cmd` on the current connection, if any.
+    fn request(&self, cmd: Command) {
+        if let Some(input_tx) = self.input_tx.borrow().as_ref() {
+            // a client that has ended already sent Disconnect
+            let _ = input_tx.unbounded_send(cmd);
+        }
+    }
+
+    /// Sends `cmd`, marking its widget busy until the server acknowledges it
+    /// when acknowledgements are supported.
+    fn send_command(&self, input_tx: &UnboundedSender<Command>, cmd: Command) {
+        // a client that has ended already sent Disconnect, nothing to wait for
+        if input_tx.unbounded_send(cmd).is_err() {
+            return;
+        }
+        if self.acks_supported.get() {
+            self.set_command_busy(cmd, true);
+        }
+    }
+
+    /// The actions disabled while `cmd` is in flight, along with the
+    /// widgets using them.
+    fn command_actions(cmd: Command) -> &'static [&'static str] {
+        match cmd {
+            Command::PrevTrack => &["prev-track"],
+            Command::TogglePlayPause => &["play-pause"],
+            Command::NextTrack => &["next-track"],
+            Command::ToggleShuffle => &["toggle-shuffle"],
+            Command::ToggleRepeatState => &["toggle-repeat"],
+            Command::SetMute(_) => &["mute"],
+            Command::Shutdown(_) | Command::Reboot(_) | Command::CancelPower => &["shutdown", "reboot", "cancel-power"],
+            // the volume slider stays usable while changes are in flight
+            Command::GetVolume | Command::SetVolume(_) => &[],
+            // the progress bar stays usable while seeking
+            Command::GetPlaybackState | Command::GetNowPlaying | Command::GetArt | Command::Seek(_) => &[],
+            Command::Auth(_) => &[],
+        }
+    }
+
+    fn set_command_busy(&self, cmd: Command, busy: bool) {
+        for name in MainWindow::command_actions(cmd) {
+            self.action(name).set_enabled(!busy && self.is_connected() && self.supports_action(name));
+        }
+    }
+
+    /// Whether the Pi advertised what action `name` needs.
+    fn supports_action(&self, name: &str) -> bool {
+        name != "cancel-power" || self.power_delay.get()
+    }
+
+    /// Shows a transient message in the action bar.
+    fn show_status(&self, message: String) {
+        let status_label = self.status_label.get().unwrap();
+        status_label.set_label(&message);
+
+        if let Some(id) = self.status_source_id.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        let obj = MainWindow::instance(self);
+        let source_id = glib::timeout_add_seconds_local_once(5, clone!(@weak obj => move || {
+            let priv_ = MainWindow::from_instance(&obj);
+            priv_.status_source_id.replace(None);
+            priv_.status_label.get().unwrap().set_label("");
+        }));
+        self.status_source_id.replace(Some(source_id));
+    }
+
+    fn set_latency_value(&self, latency: Option<Duration>) {
+        let latency_label = self.latency_label.get().unwrap();
+        match latency {
+            Some(latency) => latency_label.set_label(&format!("Latency: {} ms", latency.as_millis())),
+            None => latency_label.set_label(""),
+        }
+    }
+
+    /// Sends the slider value. The client coalesces bursts of changes, so
+    /// this can run on every step of a drag.
+    fn send_volume_value(&self, input_tx: &UnboundedSender<Command>) {
+        let volume_scale = self.volume_scale.get().unwrap();
+        if self.lock_volume_scale_signal.get() {
+            return;
+        }
+        self.volume_changed_at.set(Some(Instant::now()));
+        let value = volume_scale.value().round() as u8;
+        eprintln!("< volume: {}", value);
+        // a client that has ended already sent Disconnect
+        let _ = input_tx.unbounded_send(Command::SetVolume(value));
+    }
+
+    /// Shows the volume reported by the Pi, once the user stopped moving the
+    /// slider.
+    fn set_volume_value(&self, volume: u8) {
+        let settled_at = self.volume_changed_at.get().map(|changed| changed + VOLUME_SETTLE);
+        let remaining = settled_at.map(|settled| settled.saturating_duration_since(Instant::now()));
+        match remaining {
+            Some(remaining) if !remaining.is_zero() => {
+                self.reported_volume.set(Some(volume));
+                if self.volume_sync_source_id.borrow().is_none() {
+                    let obj = MainWindow::instance(self);
+                    let source_id = glib::timeout_add_local_once(remaining, clone!(@weak obj => move || {
+                        let priv_ = MainWindow::from_instance(&obj);
+                        priv_.volume_sync_source_id.replace(None);
+                        if let Some(volume) = priv_.reported_volume.take() {
+                            priv_.set_volume_value(volume);
+                        }
+                    }));
+                    self.volume_sync_source_id.replace(Some(source_id));
+                }
+            }
+            _ => {
+                self.reported_volume.set(None);
+                let volume_scale = self.volume_scale.get().unwrap();
+                if volume_scale.value().round() as u8 != volume {
+                    self.lock_volume_scale_signal.set(true);
+                    volume_scale.set_value(volume as f64);
+                    self.lock_volume_scale_signal.set(false);
+                }
+            }
+        }
+    }
+
+    /// Mutes natively when the Pi supports it, by setting the volume to zero
+    /// otherwise.
+    fn toggle_mute(&self, input_tx: &UnboundedSender<Command>) {
+        let mute = !self.muted.get();
+        if self.native_mute.get() {
+            self.send_command(input_tx, Command::SetMute(mute));
+            return;
+        }
+
+        let device = self.device.borrow().clone().unwrap_or_default();
+        let volume = if mute {
+            self.remember_volume(self.volume_scale.get().unwrap().value().round() as u8);
+            0
+        } else {
+            self.premute_volumes.borrow().get(&device).copied().unwrap_or(UNMUTE_FALLBACK_VOLUME)
+        };
+        self.send_command(input_tx, Command::SetVolume(volume));
+    }
+
+    /// Keeps track of the last audible volume, whoever set it, so unmuting
+    /// restores it.
+    fn remember_volume(&self, volume: u8) {
+        if volume > 0 {
+            if let Some(device) = self.device.borrow().clone() {
+                self.premute_volumes.borrow_mut().insert(device, volume);
+            }
+        }
+        if !self.native_mute.get() {
+            self.set_muted(volume == 0);
+        }
+    }
+
+    fn set_muted(&self, muted: bool) {
+        self.muted.set(muted);
+        let mute_button = self.mute_button.get().unwrap();
+        if mute_button.is_active() != muted {
+            self.lock_state_buttons_signal.set(true);
+            mute_button.set_active(muted);
+            self.lock_state_buttons_signal.set(false);
+        }
+        let (tooltip, icon) = if muted {
+            ("Unmute", "audio-volume-muted")
+        } else {
+            ("Mute", "audio-volume-high")
+        };
+        mute_button.set_tooltip_text(Some(tooltip));
+        mute_button.set_image(Some(&gtk::Image::from_icon_name(Some(icon), gtk::IconSize::Button)));
+    }
+}
+
+impl WidgetImpl for MainWindow {}
+impl ContainerImpl for MainWindow {}
+impl BinImpl for MainWindow {}
+impl WindowImpl for MainWindow {}
+impl ApplicationWindowImpl for MainWindow {}
+
+
+/// The query for the state a failed command may have left out of sync.
+fn resync_command(cmd: Command) -> Option<Command> {
+    match cmd {
+        Command::SetVolume(_) | Command::SetMute(_) => Some(Command::GetVolume),
+        Command::Seek(_) => Some(Command::GetNowPlaying),
+        Command::TogglePlayPause | Command::ToggleShuffle | Command::ToggleRepeatState => Some(Command::GetPlaybackState),
+        _ => None,
+    }
+}
+
+fn devices_path() -> PathBuf {
+    config_dir().join("devices.toml")
+}
+
+/// Asks for the name, address, password and certificate trust of a
+/// device, prefilled with `device` when editing one. Returns `None` when
+/// cancelled.
+async fn device_dialog<W: IsA<gtk::Window>>(window: &W, device: Option<Device>) -> Option<Device> {
+    let title = if device.is_some() { "Edit device" } else { "Add device" };
+    let dialog = gtk::Dialog::builder()
+        .transient_for(window)
+        .modal(true)
+        .title(title)
+        .window_position(gtk::WindowPosition::CenterOnParent)
+        .build();
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let save_button = dialog.add_button("Save", gtk::ResponseType::Accept);
+    dialog.set_default_response(gtk::ResponseType::Accept);
+    let device = device.unwrap_or_default();
+
+    let grid = gtk::Grid::builder()
+        .margin(10)
+        .row_spacing(5)
+        .column_spacing(10)
+        .build();
+    let name_entry = gtk::Entry::builder()
+        .placeholder_text("Kitchen")
+        .text(&device.name)
+        .activates_default(true)
+        .hexpand(true)
+        .build();
+    let addr_entry = gtk::Entry::builder()
+        .placeholder_text("spotifypi.local:9487 or wss://spotifypi.local:9487")
+        .text(&device.addr)
+        .activates_default(true)
+        .build();
+    let secret_entry = gtk::Entry::builder()
+        .placeholder_text("Only if the Pi asks for one")
+        .text(device.secret.as_deref().unwrap_or_default())
+        .visibility(false)
+        .input_purpose(gtk::InputPurpose::Password)
+        .activates_default(true)
+        .build();
+
+    // only used for wss:// addresses
+    let trust_combo = gtk::ComboBoxText::new();
+    trust_combo.append(Some("system"), "System certificates");
+    trust_combo.append(Some("ca-file"), "Signed by a CA certificate file");
+    trust_combo.append(Some("pin"), "Pin the certificate on first use");
+    trust_combo.set_active_id(Some(match device.trust {
+        TrustMode::System => "system",
+        TrustMode::CaFile => "ca-file",
+        TrustMode::Pin => "pin",
+    }));
+    let ca_file_button = gtk::FileChooserButton::new("CA certificate", gtk::FileChooserAction::Open);
+    if let Some(path) = &device.ca_file {
+        ca_file_button.set_filename(path);
+    }
+    let fingerprint = Rc::new(Cell::new(device.fingerprint));
+    let fingerprint_label = gtk::Label::builder()
+        .xalign(0.)
+        .selectable(true)
+        .wrap(true)
+        .wrap_mode(gtk::pango::WrapMode::Char)
+        .max_width_chars(48)
+        .build();
+    let forget_button = gtk::Button::with_label("Forget");
+
+    let rows: [(&str, gtk::Widget); 6] = [
+        ("Name:", name_entry.clone().upcast()),
+        ("Address:", addr_entry.clone().upcast()),
+        ("Password:", secret_entry.clone().upcast()),
+        ("Certificate:", trust_combo.clone().upcast()),
+        ("CA file:", ca_file_button.clone().upcast()),
+        ("Pinned:", fingerprint_label.clone().upcast()),
+    ];
+    for (row, (label, widget)) in rows.iter().enumerate() {
+        grid.attach(&gtk::Label::builder().label(label).xalign(0.).build(), 0, row as i32, 1, 1);
+        grid.attach(widget, 1, row as i32, 1, 1);
+    }
+    grid.attach(&forget_button, 2, 5, 1, 1);
+    dialog.content_area().add(&grid);
+
+    let update = clone!(@weak name_entry, @weak addr_entry, @weak trust_combo, @weak ca_file_button,
+        @weak fingerprint_label, @weak forget_button, @weak save_button, @strong fingerprint => move || {
+        let url = ws_url(addr_entry.text().trim());
+        let tls = url.as_ref().is_ok_and(|url| url.scheme() == "wss");
+        let trust = trust_combo.active_id();
+        let ca_file = trust.as_deref() == Some("ca-file");
+        let pin = trust.as_deref() == Some("pin");
+
+        trust_combo.set_sensitive(tls);
+        ca_file_button.set_sensitive(tls && ca_file);
+        fingerprint_label.set_sensitive(tls && pin);
+        forget_button.set_sensitive(tls && pin && fingerprint.get().is_some());
+        match fingerprint.get() {
+            Some(fingerprint) => fingerprint_label.set_text(&fingerprint.to_string()),
+            None => fingerprint_label.set_text("Nothing yet, learned on the next connection"),
+        }
+
+        let valid = !name_entry.text().trim().is_empty()
+            && url.is_ok()
+            && !(tls && ca_file && ca_file_button.filename().is_none());
+        save_button.set_sensitive(valid);
+    });
+    update();
+    name_entry.connect_changed(clone!(@strong update => move |_| update()));
+    addr_entry.connect_changed(clone!(@strong update => move |_| update()));
+    trust_combo.connect_changed(clone!(@strong update => move |_| update()));
+    ca_file_button.connect_file_set(clone!(@strong update => move |_| update()));
+    forget_button.connect_clicked(clone!(@strong fingerprint => move |_| {
+        fingerprint.set(None);
+        update();
+    }));
+
+    dialog.show_all();
+    let response = dialog.run_future().await;
+    let trust = match trust_combo.active_id().as_deref() {
+        Some("ca-file") => TrustMode::CaFile,
+        Some("pin") => TrustMode::Pin,
+        _ => TrustMode::System,
+    };
+    let device = Device {
+        name: name_entry.text().trim().to_string(),
+        addr: addr_entry.text().trim().to_string(),
+        secret: Some(secret_entry.text().to_string()).filter(|secret| !secret.is_empty()),
+        trust,
+        ca_file: ca_file_button.filename(),
+        fingerprint: fingerprint.get(),
+    };
+    dialog.close();
+    (response == gtk::ResponseType::Accept).then_some(device)
+}
+
+/// Warns that `device` presented a certificate other than the pinned one.
+/// Returns `true` when the user chose to trust the new certificate.
+async fn certificate_changed_dialog<W: IsA<gtk::Window>>(window: &W, device: &Device, presented: Fingerprint) -> bool {
+    let pinned = device.fingerprint.map_or_else(|| "none".to_string(), |pin| pin.to_string());
+    let dialog = gtk::MessageDialog::builder()
+        .transient_for(window)
+        .modal(true)
+        .message_type(gtk::MessageType::Warning)
+        .title("Certificate changed")
+        .text(&format!("The certificate of {} has changed", device.name))
+        .secondary_text(&format!(
+            "This happens when the Pi got a new certificate, but it can also mean someone is \
+             intercepting the connection. Only trust the new certificate if you know why it changed.\n\n\
+             Pinned:\n{}\n\nPresented:\n{}",
+            pinned, presented
+        ))
+        .window_position(gtk::WindowPosition::CenterOnParent)
+        .build();
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button("Trust new certificate", gtk::ResponseType::Accept);
+    dialog.set_default_response(gtk::ResponseType::Cancel);
+    let response = dialog.run_future().await;
+    dialog.close();
+    response == gtk::ResponseType::Accept
+}
+
+/// Asks to confirm shutting down or rebooting the device called `name`,
+/// and when if `delayable`. Returns the delay, or `None` when cancelled.
+async fn power_dialog<W: IsA<gtk::Window>>(
+    window: &W,
+    name: &str,
+    action: PowerAction,
+    delayable: bool,
+) -> Option<Duration> {
+    let (text, secondary_text, confirm) = match action {
+        PowerAction::Shutdown => (
+            format!("Shut down {}?", name),
+            "Playback stops until the Pi is switched on again.",
+            "Shut down",
+        ),
+        PowerAction::Reboot => (
+            format!("Reboot {}?", name),
+            "Playback stops while the Pi restarts.",
+            "Reboot",
+        ),
+    };
+    let dialog = gtk::MessageDialog::builder()
+        .transient_for(window)
+        .modal(true)
+        .message_type(gtk::MessageType::Question)
+        .title(power_noun(action))
+        .text(&text)
+        .secondary_text(secondary_text)
+        .window_position(gtk::WindowPosition::CenterOnParent)
+        .build();
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let confirm_button = dialog.add_button(confirm, gtk::ResponseType::Accept);
+    confirm_button.style_context().add_class("destructive-action");
+    dialog.set_default_response(gtk::ResponseType::Cancel);
+
+    let delay_combo = gtk::ComboBoxText::new();
+    for minutes in POWER_DELAYS {
+        let label = match minutes {
+            0 => "Now".to_string(),
+            1 => "In 1 minute".to_string(),
+            _ => format!("In {} minutes", minutes),
+        };
+        delay_combo.append(Some(&minutes.to_string()), &label);
+    }
+    delay_combo.set_active(Some(0));
+    let delay_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(10)
+        .build();
+    delay_box.pack_start(&gtk::Label::new(Some("When:")), false, false, 0);
+    delay_box.pack_start(&delay_combo, false, false, 0);
+    delay_box.show_all();
+    // the Pi can only go down right away
+    delay_box.set_visible(delayable);
+    dialog.message_area().downcast::<gtk::Box>().expect("message area is a box").pack_start(&delay_box, false, false, 0);
+
+    let response = dialog.run_future().await;
+    let minutes = delay_combo.active_id().and_then(|id| id.parse::<u64>().ok()).unwrap_or(0);
+    dialog.close();
+    (response == gtk::ResponseType::Accept).then_some(Duration::from_secs(minutes * 60))
+}
+
+fn power_noun(action: PowerAction) -> &'static str {
+    match action {
+        PowerAction::Shutdown => "Shutdown",
+        PowerAction::Reboot => "Reboot",
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Decodes an image and scales it to fit in `ART_SIZE`, keeping its aspect ratio.
+fn decode_art(data: &[u8]) -> Result<gdk_pixbuf::Pixbuf, glib::Error> {
+    let loader = gdk_pixbuf::PixbufLoader::new();
+    loader.write(data)?;
+    loader.close()?;
+    let pixbuf = loader.pixbuf()
+        .ok_or_else(|| glib::Error::new(gdk_pixbuf::PixbufError::CorruptImage, "empty image"))?;
+
+    let (width, height) = (pixbuf.width(), pixbuf.height());
+    let scale = ART_SIZE as f64 / width.max(height) as f64;
+    let scaled_width = ((width as f64 * scale).round() as i32).max(1);
+    let scaled_height = ((height as f64 * scale).round() as i32).max(1);
+    pixbuf.scale_simple(scaled_width, scaled_height, gdk_pixbuf::InterpType::Bilinear)
+        .ok_or_else(|| glib::Error::new(gdk_pixbuf::PixbufError::InsufficientMemory, "could not scale image"))
+}
+
+async fn show_dialog<W: IsA<gtk::Window>>(window: W, message: String) {
+    let dialog = gtk::MessageDialog::builder()
         .transient_for(&window)
         .modal(true)
         .buttons(gtk::ButtonsType::Ok)
@@ -525,4 +2792,3 @@ async fn show_dialog<W: IsA<gtk::Window>>(window: W, message: String) {
     dialog.run_future().await;
     dialog.close();
 }
-
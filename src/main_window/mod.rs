@@ -1,15 +1,40 @@
 mod imp;
+mod preferences;
 
-use gtk::glib;
+use std::path::PathBuf;
+
+use gtk::{gio, glib};
+use gtk::subclass::prelude::*;
+
+use spotifypi_control_panel::config::Config;
+use spotifypi_control_panel::launch::LaunchOptions;
+
+pub const WINDOW_TITLE: &str = "SpotifyPi Control Panel";
 
 glib::wrapper! {
     pub struct MainWindow(ObjectSubclass<imp::MainWindow>)
         @extends gtk::Widget, gtk::Container, gtk::Bin, gtk::Window, gtk::ApplicationWindow,
-        @implements gtk::Buildable;          
+        @implements gio::ActionGroup, gio::ActionMap, gtk::Buildable;
 }
 
 impl MainWindow {
-    pub fn new(app: &gtk::Application) -> Self {
-        glib::Object::new(&[("application", app)]).expect("Failed to create MainWindow")
+    pub fn new(app: &gtk::Application, config: Config) -> Self {
+        let win: Self = glib::Object::new(&[("application", app)]).expect("Failed to create MainWindow");
+        imp::MainWindow::from_instance(&win).init_config(config);
+        win
+    }
+
+    /// Acts on the command line the panel was started or called with.
+    pub fn launch(&self, options: LaunchOptions) {
+        imp::MainWindow::from_instance(self).launch(options);
     }
 }
+
+/// Directory holding the settings and the device list.
+pub fn config_dir() -> PathBuf {
+    glib::user_config_dir().join("spotifypi-control-panel")
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
@@ -0,0 +1,307 @@
+//! Dialog editing the settings stored in the config file.
+
+use std::rc::Rc;
+
+use glib::clone;
+use gtk::glib;
+use gtk::prelude::*;
+
+use spotifypi_control_panel::config::Config;
+
+
+struct Form {
+    grid: gtk::Grid,
+    row: i32,
+}
+
+impl Form {
+    fn new() -> Self {
+        let grid = gtk::Grid::builder()
+            .margin(10)
+            .row_spacing(5)
+            .column_spacing(10)
+            .build();
+        Form { grid, row: 0 }
+    }
+
+    fn section(&mut self, title: &str) {
+        let label = gtk::Label::builder()
+            .label(&format!("<b>{}</b>", glib::markup_escape_text(title)))
+            .use_markup(true)
+            .xalign(0.)
+            .margin_top(if self.row == 0 { 0 } else { 10 })
+            .build();
+        self.grid.attach(&label, 0, self.row, 2, 1);
+        self.row += 1;
+    }
+
+    fn add<W: IsA<gtk::Widget>>(&mut self, label: &str, widget: &W) {
+        let label = gtk::Label::builder()
+            .label(label)
+            .xalign(0.)
+            .margin_start(10)
+            .build();
+        self.grid.attach(&label, 0, self.row, 1, 1);
+        self.grid.attach(widget, 1, self.row, 1, 1);
+        self.row += 1;
+    }
+
+    fn check(&mut self, label: &str, active: bool) -> gtk::CheckButton {
+        let button = gtk::CheckButton::builder()
+            .label(label)
+            .active(active)
+            .margin_start(10)
+            .build();
+        self.grid.attach(&button, 0, self.row, 2, 1);
+        self.row += 1;
+        button
+    }
+
+    fn spin(&mut self, label: &str, value: f64, (min, max): (f64, f64), step: f64, digits: u32) -> Spin {
+        let button = gtk::SpinButton::with_range(min, max, step);
+        button.set_digits(digits);
+        button.set_value(value);
+        button.set_hexpand(true);
+        self.add(label, &button);
+        let shown = button.text().to_string();
+        Spin { button, shown }
+    }
+}
+
+/// A spin button, and what it showed at first.
+struct Spin {
+    button: gtk::SpinButton,
+    shown: String,
+}
+
+impl Spin {
+    /// The value entered, or `original` when it was left alone: the range
+    /// and digits of the button may not fit values set in the file.
+    fn value(&self, original: f64) -> f64 {
+        if self.button.text() == self.shown {
+            original
+        } else {
+            self.button.value()
+        }
+    }
+}
+
+/// The widgets of the dialog, one per setting.
+struct Fields {
+    auto_reconnect: gtk::CheckButton,
+    initial_delay: Spin,
+    max_delay: Spin,
+    max_attempts: Spin,
+    heartbeat_interval: Spin,
+    heartbeat_timeout: Spin,
+    ack_timeout: Spin,
+    max_retries: Spin,
+    volume_coalesce: Spin,
+    challenge_wait: Spin,
+    discovery_enabled: gtk::CheckButton,
+    service_type: gtk::Entry,
+    show_art: gtk::CheckButton,
+    art_cache_mib: Spin,
+    mpris: gtk::CheckButton,
+    tray: gtk::CheckButton,
+    close_to_tray: gtk::CheckButton,
+    notify_track: gtk::CheckButton,
+    notify_connection: gtk::CheckButton,
+    notify_power: gtk::CheckButton,
+    track_interval: Spin,
+}
+
+impl Fields {
+    /// `config` with the values shown in the dialog.
+    fn edited(&self, config: &Config) -> Config {
+        let mut edited = config.clone();
+        edited.reconnect.auto = self.auto_reconnect.is_active();
+        let reconnect = &config.reconnect;
+        edited.reconnect.initial_delay = self.initial_delay.value(reconnect.initial_delay);
+        edited.reconnect.max_delay = self.max_delay.value(reconnect.max_delay);
+        edited.reconnect.max_attempts = match self.max_attempts.value(reconnect.max_attempts.unwrap_or(0).into()) as u32 {
+            0 => None,
+            attempts => Some(attempts),
+        };
+        let timeouts = &config.timeouts;
+        edited.timeouts.heartbeat_interval = self.heartbeat_interval.value(timeouts.heartbeat_interval);
+        edited.timeouts.heartbeat_timeout = self.heartbeat_timeout.value(timeouts.heartbeat_timeout);
+        edited.timeouts.ack_timeout = self.ack_timeout.value(timeouts.ack_timeout);
+        edited.timeouts.max_retries = self.max_retries.value(timeouts.max_retries.into()) as u32;
+        edited.timeouts.volume_coalesce_ms = self.volume_coalesce.value(timeouts.volume_coalesce_ms as f64) as u64;
+        edited.timeouts.challenge_wait_ms = self.challenge_wait.value(timeouts.challenge_wait_ms as f64) as u64;
+        edited.discovery.enabled = self.discovery_enabled.is_active();
+        edited.discovery.service_type = self.service_type.text().trim().to_string();
+        edited.ui.show_art = self.show_art.is_active();
+        edited.ui.art_cache_mib = self.art_cache_mib.value(config.ui.art_cache_mib as f64) as u64;
+        edited.desktop.mpris = self.mpris.is_active();
+        edited.desktop.tray = self.tray.is_active();
+        edited.desktop.close_to_tray = self.close_to_tray.is_active();
+        edited.notifications.track = self.notify_track.is_active();
+        edited.notifications.connection = self.notify_connection.is_active();
+        edited.notifications.power = self.notify_power.is_active();
+        edited.notifications.track_interval = self.track_interval.value(config.notifications.track_interval);
+        edited
+    }
+
+    /// Why the values shown can't be saved, if they can't.
+    fn problem(&self, config: &Config) -> Option<String> {
+        if self.service_type.text().trim().is_empty() {
+            return Some("The service type can't be empty".to_string());
+        }
+        self.edited(config).validate().err()
+    }
+}
+
+/// Shows the settings of `config` for editing. Returns the edited copy, or
+/// `None` when cancelled.
+pub(super) async fn preferences_dialog<W: IsA<gtk::Window>>(window: &W, config: &Config) -> Option<Config> {
+    let dialog = gtk::Dialog::builder()
+        .transient_for(window)
+        .modal(true)
+        .title("Preferences")
+        .window_position(gtk::WindowPosition::CenterOnParent)
+        .build();
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    let save_button = dialog.add_button("Save", gtk::ResponseType::Accept);
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let mut form = Form::new();
+
+    form.section("Reconnect");
+    let auto_reconnect = form.check("Reconnect when the connection is lost", config.reconnect.auto);
+    let initial_delay = form.spin("First delay (s):", config.reconnect.initial_delay, (0.5, 3600.), 0.5, 1);
+    let max_delay = form.spin("Longest delay (s):", config.reconnect.max_delay, (0.5, 3600.), 1., 1);
+    let max_attempts = form.spin(
+        "Attempts (0 = forever):",
+        config.reconnect.max_attempts.unwrap_or(0).into(),
+        (0., 1000.),
+        1.,
+        0,
+    );
+
+    form.section("Timeouts");
+    let heartbeat_interval = form.spin(
+        "Heartbeat every (s, 0 = off):",
+        config.timeouts.heartbeat_interval,
+        (0., 600.),
+        1.,
+        1,
+    );
+    let heartbeat_timeout = form.spin("Heartbeat timeout (s):", config.timeouts.heartbeat_timeout, (1., 600.), 1., 1);
+    let ack_timeout = form.spin("Command timeout (s):", config.timeouts.ack_timeout, (0.5, 600.), 0.5, 1);
+    let max_retries = form.spin("Command retries:", config.timeouts.max_retries.into(), (0., 20.), 1., 0);
+    let volume_coalesce = form.spin(
+        "Volume updates every (ms):",
+        config.timeouts.volume_coalesce_ms as f64,
+        (0., 2000.),
+        10.,
+        0,
+    );
+    let challenge_wait = form.spin(
+        "Wait for a password request (ms):",
+        config.timeouts.challenge_wait_ms as f64,
+        (0., 10000.),
+        50.,
+        0,
+    );
+
+    form.section("Discovery");
+    let discovery_enabled = form.check("Find devices on the local network", config.discovery.enabled);
+    let service_type = gtk::Entry::builder()
+        .text(&config.discovery.service_type)
+        .activates_default(true)
+        .build();
+    form.add("Service type:", &service_type);
+
+    form.section("Appearance");
+    let show_art = form.check("Show cover art", config.ui.show_art);
+    let art_cache_mib = form.spin("Cover art cache (MiB):", config.ui.art_cache_mib as f64, (0., 4096.), 8., 0);
+
+    form.section("Desktop");
+    let mpris = form.check("Publish as a media player (MPRIS)", config.desktop.mpris);
+    let tray = form.check("Show an icon in the system tray", config.desktop.tray);
+    let close_to_tray = form.check("Closing the window hides it to the tray", config.desktop.close_to_tray);
+
+    form.section("Notifications");
+    let notify_track = form.check("Track changes", config.notifications.track);
+    let notify_connection = form.check("Connection lost or restored", config.notifications.connection);
+    let notify_power = form.check("Shutdown and reboot", config.notifications.power);
+    let track_interval = form.spin(
+        "Tracks at most every (s):",
+        config.notifications.track_interval,
+        (0., 3600.),
+        1.,
+        0,
+    );
+
+    let problem_label = gtk::Label::builder()
+        .xalign(0.)
+        .wrap(true)
+        .margin_top(10)
+        .build();
+    problem_label.style_context().add_class("error");
+    form.grid.attach(&problem_label, 0, form.row, 2, 1);
+
+    dialog.content_area().add(&form.grid);
+
+    discovery_enabled.connect_toggled(clone!(@weak service_type => move |button| {
+        service_type.set_sensitive(button.is_active());
+    }));
+    service_type.set_sensitive(config.discovery.enabled);
+
+    let fields = Rc::new(Fields {
+        auto_reconnect,
+        initial_delay,
+        max_delay,
+        max_attempts,
+        heartbeat_interval,
+        heartbeat_timeout,
+        ack_timeout,
+        max_retries,
+        volume_coalesce,
+        challenge_wait,
+        discovery_enabled,
+        service_type,
+        show_art,
+        art_cache_mib,
+        mpris,
+        tray,
+        close_to_tray,
+        notify_track,
+        notify_connection,
+        notify_power,
+        track_interval,
+    });
+    // Save stays insensitive while the values can't be saved
+    let check = Rc::new(clone!(@weak save_button, @weak problem_label, @weak fields, @strong config => move || {
+        let problem = fields.problem(&config);
+        save_button.set_sensitive(problem.is_none());
+        problem_label.set_label(problem.as_deref().unwrap_or(""));
+        problem_label.set_visible(problem.is_some());
+    }));
+    for spin in [
+        &fields.initial_delay,
+        &fields.max_delay,
+        &fields.max_attempts,
+        &fields.heartbeat_interval,
+        &fields.heartbeat_timeout,
+        &fields.ack_timeout,
+        &fields.max_retries,
+        &fields.volume_coalesce,
+        &fields.challenge_wait,
+        &fields.art_cache_mib,
+        &fields.track_interval,
+    ] {
+        spin.button.connect_value_changed(clone!(@strong check => move |_| check()));
+    }
+    fields.service_type.connect_changed(clone!(@strong check => move |_| check()));
+
+    dialog.show_all();
+    check();
+    let response = dialog.run_future().await;
+    let edited = fields.edited(config);
+    dialog.close();
+
+    (response == gtk::ResponseType::Accept && edited.validate().is_ok()).then_some(edited)
+}
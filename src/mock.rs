@@ -0,0 +1,636 @@
+//! In-process stand-in for the SpotifyPi server.
+//!
+//! Speaks the same protocol as the real device, keeps a simulated player
+//! state and can inject faults, so the panel can be developed and tested
+//! without a Pi on the LAN.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{accept_async, tungstenite::protocol::Message, WebSocketStream};
+
+use crate::auth;
+use crate::discovery::{browse_query_id, service_reply, DEFAULT_SERVICE_TYPE, MDNS_ADDR};
+use crate::protocol::{
+    art_frame, Command, Event, ParseError, Position, PowerAction, RepeatState, Request, Track, CAPABILITY_ACK,
+    CAPABILITY_MUTE, CAPABILITY_POWER_DELAY, MAX_VOLUME,
+};
+
+
+/// How long a client has to answer the authentication challenge.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Title, artist, album and length in seconds of the simulated playlist.
+const PLAYLIST: &[(&str, &str, &str, u64)] = &[
+    ("Mock Track One", "The Placeholders", "Fixtures Vol. 1", 215),
+    ("Loopback (Extended Mix)", "Localhost", "127.0.0.1", 342),
+    ("Half | Open", "Socket & the Pipes", "Fixtures Vol. 1", 187),
+    ("Backslash \\ Blues", "The Placeholders", "Escapes", 251),
+];
+
+/// Simulated player state shared by every connection.
+#[derive(Debug, Clone)]
+pub struct PlayerState {
+    pub playing: bool,
+    pub shuffle: bool,
+    pub repeat: RepeatState,
+    pub volume: u8,
+    pub muted: bool,
+    pub track: usize,
+    /// Position in the track when playback last started or stopped.
+    pub position: Duration,
+    /// When playback last started, `None` while paused.
+    pub resumed: Option<Instant>,
+}
+
+impl Default for PlayerState {
+    fn default() -> Self {
+        PlayerState {
+            playing: false,
+            shuffle: false,
+            repeat: RepeatState::Off,
+            volume: 50,
+            muted: false,
+            track: 0,
+            position: Duration::ZERO,
+            resumed: None,
+        }
+    }
+}
+
+impl PlayerState {
+    pub fn now_playing(&self) -> Track {
+        let (title, artist, album, secs) = PLAYLIST[self.track % PLAYLIST.len()];
+        Track {
+            id: format!("mock:track:{}", self.track % PLAYLIST.len()),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            duration: Duration::from_secs(secs),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        let duration = self.now_playing().duration;
+        let elapsed = self.resumed.map(|resumed| resumed.elapsed()).unwrap_or_default();
+        Position {
+            position: (self.position + elapsed).min(duration),
+            duration,
+            playing: self.playing,
+        }
+    }
+
+    fn change_track(&mut self, track: usize) -> Vec<Event> {
+        self.track = track % PLAYLIST.len();
+        self.position = Duration::ZERO;
+        self.resumed = self.playing.then(Instant::now);
+        vec![Event::Track(Some(self.now_playing())), Event::Position(self.position())]
+    }
+
+    /// Cover art of the current track: a small PPM gradient tinted per track.
+    pub fn art(&self) -> Vec<u8> {
+        const SIZE: usize = 64;
+        const TINTS: [[u8; 3]; 4] = [[0x1d, 0xb9, 0x54], [0xe9, 0x1e, 0x63], [0x3f, 0x51, 0xb5], [0xff, 0x98, 0x00]];
+        let tint = TINTS[self.track % PLAYLIST.len() % TINTS.len()];
+
+        let mut data = format!("P6\n{} {}\n255\n", SIZE, SIZE).into_bytes();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let shade = 255 - (x + y) * 255 / (2 * SIZE);
+                data.extend(tint.iter().map(|&c| (c as usize * shade / 255) as u8));
+            }
+        }
+        data
+    }
+
+    /// Applies a command and returns the events to reply with.
+    pub fn apply(&mut self, cmd: Command) -> Vec<Event> {
+        match cmd {
+            Command::PrevTrack => return self.change_track(self.track + PLAYLIST.len() - 1),
+            Command::TogglePlayPause => {
+                self.position = self.position().position;
+                self.playing = !self.playing;
+                self.resumed = self.playing.then(Instant::now);
+                return vec![Event::Playing(self.playing), Event::Position(self.position())];
+            }
+            Command::NextTrack => return self.change_track(self.track + 1),
+            Command::ToggleShuffle => {
+                self.shuffle = !self.shuffle;
+                return vec![Event::Shuffle(self.shuffle)];
+            }
+            Command::ToggleRepeatState => {
+                self.repeat = self.repeat.next();
+                return vec![Event::Repeat(self.repeat)];
+            }
+            Command::GetVolume => return vec![Event::Volume(self.volume), Event::Muted(self.muted)],
+            Command::SetVolume(volume) => {
+                // changing the volume unmutes
+                self.volume = volume.min(MAX_VOLUME);
+                self.muted = false;
+                return vec![Event::Volume(self.volume), Event::Muted(self.muted)];
+            }
+            Command::SetMute(muted) => {
+                self.muted = muted;
+                return vec![Event::Muted(self.muted)];
+            }
+            Command::GetPlaybackState => {
+                return vec![Event::Playing(self.playing), Event::Shuffle(self.shuffle), Event::Repeat(self.repeat)];
+            }
+            Command::GetNowPlaying => {
+                return vec![Event::Track(Some(self.now_playing())), Event::Position(self.position())];
+            }
+            Command::GetArt => {
+                let track_id = self.now_playing().id;
+                return vec![Event::ArtImage { track_id, data: self.art() }];
+            }
+            Command::Seek(position) => {
+                self.position = position.min(self.now_playing().duration);
+                self.resumed = self.playing.then(Instant::now);
+                return vec![Event::Position(self.position())];
+            }
+            // power commands are carried out by the session, which sees
+            // every client, and auth only means something while authenticating
+            Command::Shutdown(_) | Command::Reboot(_) | Command::CancelPower | Command::Auth(_) => {}
+        }
+        Vec::new()
+    }
+}
+
+/// Faults injected into every connection.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Delay before each reply is sent.
+    pub reply_delay: Duration,
+    /// Probability of dropping the connection when a command arrives.
+    pub drop_rate: f64,
+    /// Drop every connection after it has sent this many commands.
+    pub drop_after: Option<usize>,
+    /// Probability of sending a garbage frame before a reply.
+    pub garbage_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    pub state: PlayerState,
+    pub faults: Faults,
+    /// How long the server refuses connections after a `reboot`.
+    pub reboot_delay: Duration,
+    /// Seed for the fault injection, random when `None`.
+    pub seed: Option<u64>,
+    /// Advertise and send acknowledgements for tagged commands.
+    pub acks: bool,
+    /// Advertise and support native mute.
+    pub mute: bool,
+    /// Advertise and support delayed shutdown and reboot, and calling them
+    /// off.
+    pub power_delay: bool,
+    /// Serve `wss://` with these settings instead of plain `ws://`.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    /// Require clients to authenticate with this shared secret.
+    pub secret: Option<String>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            state: PlayerState::default(),
+            faults: Faults::default(),
+            reboot_delay: Duration::from_secs(3),
+            seed: None,
+            acks: true,
+            mute: true,
+            power_delay: true,
+            tls: None,
+            secret: None,
+        }
+    }
+}
+
+/// A delayed shutdown or reboot.
+struct Scheduled {
+    /// Tells this schedule apart from the ones replacing it.
+    id: u64,
+    action: PowerAction,
+    at: Instant,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    state: Mutex<PlayerState>,
+    faults: Faults,
+    acks: bool,
+    mute: bool,
+    power_delay: bool,
+    tls: Option<TlsAcceptor>,
+    secret: Option<String>,
+    rng: Mutex<StdRng>,
+    power_tx: broadcast::Sender<PowerAction>,
+    scheduled: Mutex<Option<Scheduled>>,
+    next_schedule_id: AtomicU64,
+    /// Events sent to every connected client.
+    notice_tx: broadcast::Sender<Event>,
+}
+
+impl Shared {
+    fn chance(&self, probability: f64) -> bool {
+        probability > 0. && self.rng.lock().unwrap().gen_bool(probability.min(1.))
+    }
+
+    fn garbage(&self) -> Message {
+        match self.rng.lock().unwrap().gen_range(0..4) {
+            0 => Message::text("garbage"),
+            1 => Message::text("[bogus](1)"),
+            2 => Message::text("[volume](loud)"),
+            _ => Message::binary(vec![0xff, 0xfe, 0xfd]),
+        }
+    }
+
+    /// Whether `command` is one this server advertised, or needs no
+    /// capability.
+    fn supports(&self, command: Command) -> bool {
+        match command {
+            Command::SetMute(_) => self.mute,
+            Command::Shutdown(delay) | Command::Reboot(delay) => delay.is_zero() || self.power_delay,
+            Command::CancelPower => self.power_delay,
+            _ => true,
+        }
+    }
+
+    /// The pending shutdown or reboot, with the time left until it happens.
+    fn power_scheduled(&self) -> Option<Event> {
+        self.scheduled.lock().unwrap().as_ref().map(|scheduled| Event::PowerScheduled {
+            action: scheduled.action,
+            delay: scheduled.at.saturating_duration_since(Instant::now()),
+        })
+    }
+
+    /// Calls off the pending shutdown or reboot, telling every client.
+    /// Returns false when there was none.
+    fn cancel_power(&self) -> bool {
+        match self.scheduled.lock().unwrap().take() {
+            Some(scheduled) => {
+                scheduled.task.abort();
+                eprintln!("Mock server {} cancelled", scheduled.action);
+                let _ = self.notice_tx.send(Event::PowerCancelled);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Performs `action` once `delay` has passed, replacing any pending one.
+fn schedule_power(shared: &Arc<Shared>, action: PowerAction, delay: Duration) {
+    if delay.is_zero() {
+        let _ = shared.power_tx.send(action);
+        return;
+    }
+
+    let id = shared.next_schedule_id.fetch_add(1, Ordering::Relaxed);
+    let task = tokio::spawn({
+        let shared = shared.clone();
+        async move {
+            tokio::time::sleep(delay).await;
+            {
+                // a replaced schedule is aborted, but may already be awake
+                let mut scheduled = shared.scheduled.lock().unwrap();
+                if scheduled.as_ref().is_none_or(|scheduled| scheduled.id != id) {
+                    return;
+                }
+                scheduled.take();
+            }
+            let _ = shared.power_tx.send(action);
+        }
+    });
+    let scheduled = Scheduled { id, action, at: Instant::now() + delay, task };
+    if let Some(previous) = shared.scheduled.lock().unwrap().replace(scheduled) {
+        previous.task.abort();
+    }
+    eprintln!("Mock server {} in {:?}", action, delay);
+    let _ = shared.notice_tx.send(Event::PowerScheduled { action, delay });
+}
+
+/// Accepts connections on `listener` until a client sends `shutdown`.
+pub async fn serve(listener: TcpListener, config: MockConfig) -> io::Result<()> {
+    let rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let (power_tx, mut power_rx) = broadcast::channel(4);
+    let (notice_tx, _) = broadcast::channel(16);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(config.state),
+        faults: config.faults,
+        acks: config.acks,
+        mute: config.mute,
+        power_delay: config.power_delay,
+        tls: config.tls.map(TlsAcceptor::from),
+        secret: config.secret,
+        rng: Mutex::new(rng),
+        power_tx,
+        scheduled: Mutex::new(None),
+        next_schedule_id: AtomicU64::new(0),
+        notice_tx,
+    });
+
+    let addr = listener.local_addr()?;
+    let mut listener = listener;
+    let scheme = if shared.tls.is_some() { "wss" } else { "ws" };
+    eprintln!("Mock server listening on {}://{}", scheme, addr);
+
+    loop {
+        let power = tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(accept(stream, peer, shared.clone()));
+                    }
+                    // out of file descriptors or an aborted handshake, the
+                    // next connection may do better
+                    Err(e) => {
+                        eprintln!("Mock server failed to accept a connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+                continue;
+            }
+            power = power_rx.recv() => power,
+        };
+
+        match power {
+            Ok(PowerAction::Shutdown) => {
+                eprintln!("Mock server shutting down");
+                return Ok(());
+            }
+            Ok(PowerAction::Reboot) => {
+                // close the socket so connection attempts are refused while "down"
+                eprintln!("Mock server rebooting for {:?}", config.reboot_delay);
+                drop(listener);
+                tokio::time::sleep(config.reboot_delay).await;
+                listener = TcpListener::bind(addr).await?;
+                eprintln!("Mock server is back up");
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+async fn accept(stream: TcpStream, peer: SocketAddr, shared: Arc<Shared>) {
+    match shared.tls.clone() {
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => handle_connection(stream, peer, shared).await,
+            Err(e) => eprintln!("[{}] TLS handshake failed: {}", peer, e),
+        },
+        None => handle_connection(stream, peer, shared).await,
+    }
+}
+
+async fn handle_connection<S>(stream: S, peer: SocketAddr, shared: Arc<Shared>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut ws_stream = match accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("[{}] handshake failed: {}", peer, e);
+            return;
+        }
+    };
+    eprintln!("[{}] connected", peer);
+
+    if let Some(secret) = &shared.secret {
+        let authenticated = authenticate(&mut ws_stream, secret).await;
+        let result = Event::AuthResult(authenticated);
+        if ws_stream.send(Message::text(result.to_string())).await.is_err() || !authenticated {
+            eprintln!("[{}] authentication failed", peer);
+            let _ = ws_stream.close(None).await;
+            return;
+        }
+        eprintln!("[{}] authenticated", peer);
+    }
+
+    let capabilities: Vec<String> = [
+        (shared.acks, CAPABILITY_ACK),
+        (shared.mute, CAPABILITY_MUTE),
+        (shared.power_delay, CAPABILITY_POWER_DELAY),
+    ]
+    .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, capability)| capability.to_string())
+        .collect();
+    if !capabilities.is_empty() {
+        let capabilities = Event::Capabilities(capabilities);
+        if ws_stream.send(Message::text(capabilities.to_string())).await.is_err() {
+            return;
+        }
+    }
+
+    let mut power_rx = shared.power_tx.subscribe();
+    let mut notice_rx = shared.notice_tx.subscribe();
+    if let Some(scheduled) = shared.power_scheduled() {
+        if ws_stream.send(Message::text(scheduled.to_string())).await.is_err() {
+            return;
+        }
+    }
+    let mut commands = 0;
+
+    loop {
+        let message = tokio::select! {
+            message = ws_stream.next() => message,
+            _ = power_rx.recv() => {
+                eprintln!("[{}] dropped by power action", peer);
+                return;
+            }
+            notice = notice_rx.recv() => {
+                if let Ok(event) = notice {
+                    eprintln!("[{}] > {}", peer, event);
+                    if ws_stream.send(Message::text(event.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                continue;
+            }
+        };
+
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => break,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                eprintln!("[{}] read failed: {}", peer, e);
+                return;
+            }
+        };
+        eprintln!("[{}] < {}", peer, text);
+
+        commands += 1;
+        let drop_now = shared.faults.drop_after.is_some_and(|n| commands > n)
+            || shared.chance(shared.faults.drop_rate);
+        if drop_now {
+            eprintln!("[{}] dropping connection", peer);
+            return;
+        }
+
+        let request = text.parse::<Request>().and_then(|request| match request.command {
+            command if !shared.supports(command) => Err(ParseError::UnknownCommand(command.to_string())),
+            _ => Ok(request),
+        });
+        let (id, cmd) = match request {
+            Ok(request) if request.id.is_none() || shared.acks => (request.id, request.command),
+            Ok(_) => {
+                eprintln!("[{}] tagged command, but acks are disabled", peer);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("[{}] {}", peer, e);
+                // answer tagged garbage so the client doesn't wait for it
+                if let Some(id) = tagged_id(&text).filter(|_| shared.acks) {
+                    let error = Event::Error { id, message: e.to_string() };
+                    if ws_stream.send(Message::text(error.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                continue;
+            }
+        };
+        let mut replies = shared.state.lock().unwrap().apply(cmd);
+        // every client hears of a cancelled shutdown or reboot, but with
+        // nothing to cancel the sender is still answered
+        if cmd == Command::CancelPower && !shared.cancel_power() {
+            replies.push(Event::PowerCancelled);
+        }
+        if !shared.mute {
+            replies.retain(|event| !matches!(event, Event::Muted(_)));
+        }
+        if let Some(id) = id {
+            replies.push(Event::Ack(id));
+        }
+
+        if !shared.faults.reply_delay.is_zero() {
+            tokio::time::sleep(shared.faults.reply_delay).await;
+        }
+        if shared.chance(shared.faults.garbage_rate) && ws_stream.send(shared.garbage()).await.is_err() {
+            return;
+        }
+        for event in replies {
+            eprintln!("[{}] > {}", peer, event);
+            if ws_stream.send(event_message(event)).await.is_err() {
+                return;
+            }
+        }
+
+        match cmd {
+            Command::Shutdown(delay) => schedule_power(&shared, PowerAction::Shutdown, delay),
+            Command::Reboot(delay) => schedule_power(&shared, PowerAction::Reboot, delay),
+            _ => {}
+        }
+    }
+
+    eprintln!("[{}] disconnected", peer);
+}
+
+/// Challenges the client and checks its answer, which has to be the first
+/// thing it sends.
+async fn authenticate<S>(ws_stream: &mut WebSocketStream<S>, secret: &str) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let nonce = auth::nonce();
+    if ws_stream.send(Message::text(Event::AuthChallenge(nonce.clone()).to_string())).await.is_err() {
+        return false;
+    }
+    let answer = loop {
+        match tokio::time::timeout(AUTH_TIMEOUT, ws_stream.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => break text,
+            Ok(Some(Ok(Message::Ping(_) | Message::Pong(_)))) => continue,
+            _ => return false,
+        }
+    };
+    match answer.parse::<Request>() {
+        Ok(Request { command: Command::Auth(proof), .. }) => proof.verify(secret, &nonce),
+        _ => false,
+    }
+}
+
+fn event_message(event: Event) -> Message {
+    match event {
+        Event::ArtImage { track_id, data } => Message::binary(art_frame(&track_id, &data)),
+        event => Message::text(event.to_string()),
+    }
+}
+
+fn tagged_id(text: &str) -> Option<u64> {
+    let (id, _) = text.strip_prefix('@')?.split_once(' ')?;
+    id.parse().ok()
+}
+
+/// How the mock announces itself over DNS-SD.
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    pub service_type: String,
+    /// Instance name, e.g. `Kitchen`.
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// Sent along as an A record of `host` when known.
+    pub ip: Option<Ipv4Addr>,
+    /// Lifetime of the records in seconds.
+    pub ttl: u32,
+}
+
+impl Advertisement {
+    pub fn new(name: &str, addr: SocketAddr) -> Self {
+        let ip = match addr {
+            SocketAddr::V4(addr) if !addr.ip().is_unspecified() => Some(*addr.ip()),
+            _ => None,
+        };
+        Advertisement {
+            service_type: DEFAULT_SERVICE_TYPE.to_string(),
+            name: name.to_string(),
+            host: "spotifypi-mock.local".to_string(),
+            port: addr.port(),
+            ip,
+            ttl: 120,
+        }
+    }
+}
+
+/// Answers DNS-SD browse queries arriving on `socket` until receiving fails.
+///
+/// One-shot queries are answered to the sender, queries from other mDNS
+/// responders (port 5353) to the mDNS group.
+pub async fn advertise(socket: UdpSocket, advertisement: Advertisement) -> io::Result<()> {
+    eprintln!("Advertising {:?} on {}", advertisement.name, socket.local_addr()?);
+    let mut buf = vec![0; 9000];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let id = match browse_query_id(&buf[..len], &advertisement.service_type) {
+            Some(id) => id,
+            None => continue,
+        };
+        let reply = service_reply(
+            id,
+            &advertisement.service_type,
+            &advertisement.name,
+            &advertisement.host,
+            advertisement.port,
+            advertisement.ip,
+            advertisement.ttl,
+        )?;
+        let to = if peer.port() == MDNS_ADDR.port() { MDNS_ADDR } else { peer };
+        if let Err(e) = socket.send_to(&reply, to).await {
+            eprintln!("Failed to answer mDNS query from {}: {}", peer, e);
+        }
+    }
+}
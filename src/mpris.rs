@@ -0,0 +1,442 @@
+//! MPRIS bridge, publishing the Pi as a media player on the D-Bus session
+//! bus.
+//!
+//! Desktop media widgets, media keys and `playerctl` drive players through
+//! `org.mpris.MediaPlayer2.Player`. The bridge turns their calls into
+//! [`Command`]s for the Pi, and keeps the properties in sync with the
+//! [`Event`]s the Pi sends back; it never talks to the Pi itself.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::UnboundedSender;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{connection, fdo, interface};
+
+use crate::protocol::{Command, ConnectStatus, Event, Position, RepeatState, Track, MAX_VOLUME};
+
+
+/// Well-known name the bridge owns.
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.spotifypi_control_panel";
+/// Path of the player object, fixed by the specification.
+pub const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+/// Track id meaning there is no track.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// Position reports further off than this from where playback should be
+/// are announced as a seek.
+const SEEK_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Player state as last reported by the Pi.
+#[derive(Debug, Default)]
+struct State {
+    connected: bool,
+    playing: bool,
+    shuffle: bool,
+    repeat: RepeatState,
+    volume: u8,
+    muted: bool,
+    track: Option<Track>,
+    art_url: Option<String>,
+    /// Last position received, and when it was received.
+    position: Option<(Position, Instant)>,
+}
+
+impl State {
+    /// Where playback is now, interpolated from the last report.
+    fn position(&self) -> Duration {
+        match self.position {
+            Some((position, at)) if position.playing => (position.position + at.elapsed()).min(position.duration),
+            Some((position, _)) => position.position,
+            None => Duration::ZERO,
+        }
+    }
+
+    fn track_id(&self) -> ObjectPath<'static> {
+        let path = self.track.as_ref().filter(|track| !track.id.is_empty()).map(|track| {
+            let id: String = track.id.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+            format!("/org/spotifypi/track/{}", id)
+        });
+        ObjectPath::try_from(path.unwrap_or_else(|| NO_TRACK.to_string())).expect("track ids are sanitized")
+    }
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn identity(&self) -> &str {
+        "SpotifyPi"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Player {
+    state: State,
+    commands: UnboundedSender<Command>,
+}
+
+impl Player {
+    fn send(&self, cmd: Command) -> fdo::Result<()> {
+        if !self.state.connected {
+            return Err(fdo::Error::Failed("Not connected to a SpotifyPi".to_string()));
+        }
+        self.commands.unbounded_send(cmd).map_err(|_| fdo::Error::Failed("The panel is gone".to_string()))
+    }
+
+    /// Seeks to `position` microseconds into the track.
+    fn seek_to(&self, position: i64) -> fdo::Result<()> {
+        let length = self.state.track.as_ref().map(|track| track.duration).unwrap_or_default();
+        let position = Duration::from_micros(position.max(0) as u64);
+        // seeking past the end skips to the next track
+        if position > length {
+            return self.send(Command::NextTrack);
+        }
+        self.send(Command::Seek(position))
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.send(Command::TogglePlayPause)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        if self.state.playing {
+            return Ok(());
+        }
+        self.send(Command::TogglePlayPause)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        if !self.state.playing {
+            return Ok(());
+        }
+        self.send(Command::TogglePlayPause)
+    }
+
+    /// There is no stop, pausing is the closest.
+    fn stop(&self) -> fdo::Result<()> {
+        self.pause()
+    }
+
+    fn next(&self) -> fdo::Result<()> {
+        self.send(Command::NextTrack)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.send(Command::PrevTrack)
+    }
+
+    /// Moves `offset` microseconds from the current position.
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        if self.state.track.is_none() {
+            return Ok(());
+        }
+        let position = self.state.position().as_micros() as i64;
+        self.seek_to(position.saturating_add(offset))
+    }
+
+    /// Ignored unless `track_id` is the current track, as the specification
+    /// asks.
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        if self.state.track.is_none() || track_id != self.state.track_id() || position < 0 {
+            return Ok(());
+        }
+        self.seek_to(position)
+    }
+
+    fn open_uri(&self, _uri: &str) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported("The SpotifyPi can't open URIs".to_string()))
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        match (self.state.connected && self.state.track.is_some(), self.state.playing) {
+            (false, _) => "Stopped",
+            (true, true) => "Playing",
+            (true, false) => "Paused",
+        }
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &str {
+        match self.state.repeat {
+            RepeatState::Off => "None",
+            RepeatState::Track => "Track",
+            RepeatState::Context => "Playlist",
+        }
+    }
+
+    /// The Pi only cycles through the repeat states, so this steps through
+    /// them until the asked one.
+    #[zbus(property)]
+    fn set_loop_status(&mut self, loop_status: &str) -> fdo::Result<()> {
+        let target = match loop_status {
+            "None" => RepeatState::Off,
+            "Track" => RepeatState::Track,
+            "Playlist" => RepeatState::Context,
+            _ => return Err(fdo::Error::InvalidArgs(format!("Unknown loop status: {}", loop_status))),
+        };
+        let mut repeat = self.state.repeat;
+        while repeat != target {
+            self.send(Command::ToggleRepeatState)?;
+            repeat = repeat.next();
+        }
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.
+    }
+
+    /// Playback always runs at normal speed.
+    #[zbus(property)]
+    fn set_rate(&mut self, _rate: f64) {}
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn minimum_rate(&self) -> f64 {
+        1.
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn maximum_rate(&self) -> f64 {
+        1.
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.state.shuffle
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, shuffle: bool) -> fdo::Result<()> {
+        if shuffle == self.state.shuffle {
+            return Ok(());
+        }
+        self.send(Command::ToggleShuffle)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let mut metadata = HashMap::new();
+        metadata.insert("mpris:trackid".to_string(), owned(self.state.track_id()));
+        if let Some(track) = &self.state.track {
+            metadata.insert("mpris:length".to_string(), owned(track.duration.as_micros() as i64));
+            metadata.insert("xesam:title".to_string(), owned(track.title.clone()));
+            metadata.insert("xesam:artist".to_string(), owned(vec![track.artist.clone()]));
+            metadata.insert("xesam:album".to_string(), owned(track.album.clone()));
+        }
+        if let Some(url) = &self.state.art_url {
+            metadata.insert("mpris:artUrl".to_string(), owned(url.clone()));
+        }
+        metadata
+    }
+
+    /// Zero while muted.
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        if self.state.muted {
+            return 0.;
+        }
+        self.state.volume as f64 / MAX_VOLUME as f64
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) -> fdo::Result<()> {
+        let volume = (volume.clamp(0., 1.) * MAX_VOLUME as f64).round() as u8;
+        self.send(Command::SetVolume(volume))
+    }
+
+    /// Read on demand, changes are announced with `Seeked` instead.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.state.position().as_micros() as i64
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.state.connected
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.state.connected
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.state.connected
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.state.connected
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.state.connected && self.state.track.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+}
+
+fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+    value.into().try_into().expect("metadata holds no file descriptors")
+}
+
+/// Whether `event` changes what the bridge publishes.
+pub fn is_published(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Connect(_)
+            | Event::Disconnect
+            | Event::Playing(_)
+            | Event::Shuffle(_)
+            | Event::Repeat(_)
+            | Event::Volume(_)
+            | Event::Muted(_)
+            | Event::Track(_)
+            | Event::Position(_)
+            | Event::ArtUrl { .. }
+    )
+}
+
+/// A running bridge. Dropping it leaves the bus.
+pub struct Mpris {
+    connection: zbus::Connection,
+}
+
+impl Mpris {
+    /// Publishes the player on the bus `builder` connects to, usually
+    /// `connection::Builder::session()`. Calls from the desktop are sent to
+    /// `commands`.
+    pub async fn start(builder: connection::Builder<'_>, commands: UnboundedSender<Command>) -> zbus::Result<Self> {
+        let player = Player { state: State::default(), commands };
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Root)?
+            .serve_at(OBJECT_PATH, player)?
+            .build()
+            .await?;
+        Ok(Mpris { connection })
+    }
+
+    /// Updates the published state with an event from the Pi.
+    pub async fn update(&self, event: &Event) -> zbus::Result<()> {
+        let player_ref = self.connection.object_server().interface::<_, Player>(OBJECT_PATH).await?;
+        let emitter = player_ref.signal_emitter();
+        let mut player = player_ref.get_mut().await;
+        let state = &mut player.state;
+
+        match event {
+            Event::Connect(ConnectStatus::Ok) => {
+                state.connected = true;
+                player.playback_status_changed(emitter).await?;
+                player.can_go_next_changed(emitter).await?;
+                player.can_go_previous_changed(emitter).await?;
+                player.can_play_changed(emitter).await?;
+                player.can_pause_changed(emitter).await?;
+                player.can_seek_changed(emitter).await?;
+            }
+            Event::Connect(_) | Event::Disconnect => {
+                player.state = State::default();
+                player.playback_status_changed(emitter).await?;
+                player.loop_status_changed(emitter).await?;
+                player.shuffle_changed(emitter).await?;
+                player.metadata_changed(emitter).await?;
+                player.volume_changed(emitter).await?;
+                player.can_go_next_changed(emitter).await?;
+                player.can_go_previous_changed(emitter).await?;
+                player.can_play_changed(emitter).await?;
+                player.can_pause_changed(emitter).await?;
+                player.can_seek_changed(emitter).await?;
+            }
+            Event::Playing(playing) => {
+                state.playing = *playing;
+                player.playback_status_changed(emitter).await?;
+            }
+            Event::Shuffle(shuffle) => {
+                state.shuffle = *shuffle;
+                player.shuffle_changed(emitter).await?;
+            }
+            Event::Repeat(repeat) => {
+                state.repeat = *repeat;
+                player.loop_status_changed(emitter).await?;
+            }
+            Event::Volume(volume) => {
+                state.volume = *volume;
+                player.volume_changed(emitter).await?;
+            }
+            Event::Muted(muted) => {
+                state.muted = *muted;
+                player.volume_changed(emitter).await?;
+            }
+            Event::Track(track) => {
+                if state.track.as_ref().map(|track| &track.id) != track.as_ref().map(|track| &track.id) {
+                    state.art_url = None;
+                    state.position = None;
+                }
+                state.track = track.clone();
+                player.metadata_changed(emitter).await?;
+                player.playback_status_changed(emitter).await?;
+                player.can_seek_changed(emitter).await?;
+            }
+            Event::Position(position) => {
+                let expected = state.position.map(|_| state.position());
+                state.position = Some((*position, Instant::now()));
+                let jumped = expected.is_some_and(|expected| {
+                    let off = expected.max(position.position) - expected.min(position.position);
+                    off > SEEK_THRESHOLD
+                });
+                if jumped {
+                    emitter.seeked(position.position.as_micros() as i64).await?;
+                }
+            }
+            Event::ArtUrl { track_id, url } if state.track.as_ref().is_some_and(|track| &track.id == track_id) => {
+                state.art_url = Some(url.clone());
+                player.metadata_changed(emitter).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
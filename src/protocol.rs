@@ -0,0 +1,708 @@
+//! Wire format spoken between the control panel and the SpotifyPi.
+//!
+//! Commands travel as plain text frames (`next_track`, `set_volume 40`),
+//! events come back as `[event](value)` frames (`[volume](40)`).
+//!
+//! Servers advertising the `ack` capability also accept commands tagged with
+//! a request id (`@17 next_track`) and answer each of them with `[ack](17)`
+//! or `[error](17 reason)`. Servers advertising `mute` can mute natively
+//! (`set_mute true`) and report it with `[muted](true)`, keeping the volume.
+//!
+//! `shutdown` and `reboot` take an optional delay in milliseconds
+//! (`reboot 300000`); a delayed one is announced to every client with
+//! `[power_scheduled](reboot|300000)` and can be called off with
+//! `cancel_power`, answered by `[power_cancelled]()`. Without a delay the
+//! command is sent bare, as servers that can't delay expect it.
+//!
+//! Servers that require authentication open the session with a challenge,
+//! see [`crate::auth`].
+//!
+//! Values made of several fields separate them with `|`; a literal `|` or
+//! `\` inside a field is escaped with a backslash.
+//!
+//! Cover art is the only binary payload: it travels in binary frames made of
+//! an `[art](track_id)` header line followed by the raw image bytes. Binary
+//! frames without that header are read as text.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth::Proof;
+use crate::tls::Fingerprint;
+
+/// Commands sent from the panel to the Pi.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    PrevTrack,
+    TogglePlayPause,
+    NextTrack,
+    ToggleShuffle,
+    ToggleRepeatState,
+    GetVolume,
+    SetVolume(u8),
+    /// Native mute, only understood by servers advertising `mute`.
+    SetMute(bool),
+    /// Play, shuffle and repeat state.
+    GetPlaybackState,
+    GetNowPlaying,
+    /// Cover art of the track being played.
+    GetArt,
+    /// Jump to a position in the track being played.
+    Seek(Duration),
+    /// Shut down after the given delay, right away when zero.
+    Shutdown(Duration),
+    /// Reboot after the given delay, right away when zero.
+    Reboot(Duration),
+    /// Call off a delayed shutdown or reboot.
+    CancelPower,
+    /// Answer to the authentication challenge, sent by the client itself.
+    Auth(Proof),
+}
+
+/// What happens when the end of the track or playlist is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatState {
+    #[default]
+    Off,
+    /// Repeat the current track.
+    Track,
+    /// Repeat the whole playlist or album.
+    Context,
+}
+
+impl RepeatState {
+    /// The state `toggle_repeat_state` switches to.
+    pub fn next(self) -> Self {
+        match self {
+            RepeatState::Off => RepeatState::Track,
+            RepeatState::Track => RepeatState::Context,
+            RepeatState::Context => RepeatState::Off,
+        }
+    }
+}
+
+impl fmt::Display for RepeatState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepeatState::Off => f.write_str("off"),
+            RepeatState::Track => f.write_str("track"),
+            RepeatState::Context => f.write_str("context"),
+        }
+    }
+}
+
+impl FromStr for RepeatState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(RepeatState::Off),
+            "track" => Ok(RepeatState::Track),
+            "context" => Ok(RepeatState::Context),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What a `shutdown` or `reboot` command does to the Pi.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Shutdown,
+    Reboot,
+}
+
+impl PowerAction {
+    /// The command performing this action after `delay`.
+    pub fn command(self, delay: Duration) -> Command {
+        match self {
+            PowerAction::Shutdown => Command::Shutdown(delay),
+            PowerAction::Reboot => Command::Reboot(delay),
+        }
+    }
+}
+
+impl fmt::Display for PowerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerAction::Shutdown => f.write_str("shutdown"),
+            PowerAction::Reboot => f.write_str("reboot"),
+        }
+    }
+}
+
+impl FromStr for PowerAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(PowerAction::Shutdown),
+            "reboot" => Ok(PowerAction::Reboot),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Metadata of the track being played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub duration: Duration,
+}
+
+/// Playback position within the current track, as of when it was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub position: Duration,
+    pub duration: Duration,
+    pub playing: bool,
+}
+
+/// Capability advertised by servers that acknowledge tagged commands.
+pub const CAPABILITY_ACK: &str = "ack";
+/// Capability advertised by servers that mute natively.
+pub const CAPABILITY_MUTE: &str = "mute";
+/// Capability advertised by servers that delay and call off a shutdown or
+/// reboot.
+pub const CAPABILITY_POWER_DELAY: &str = "power_delay";
+
+/// A command, optionally tagged with a request id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub id: Option<u64>,
+    pub command: Command,
+}
+
+/// Result of the WebSocket handshake, reported as `[connect](ok|failed)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStatus {
+    Ok,
+    Failed,
+    /// The server presented a certificate other than the pinned one, whose
+    /// fingerprint is given, `[connect](certificate_changed|AB:CD:...)`.
+    CertificateChanged(Fingerprint),
+    /// The server asked for credentials and refused ours, or we had none,
+    /// `[connect](auth_failed)`.
+    AuthFailed,
+}
+
+/// Events received from the Pi, plus the synthetic connection events
+/// produced by the client itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Connect(ConnectStatus),
+    Disconnect,
+    /// Fingerprint of the certificate accepted on first use, sent before
+    /// `Connect(Ok)` when connecting with nothing pinned yet,
+    /// `[certificate](AB:CD:...)`.
+    Certificate(Fingerprint),
+    /// Nonce to authenticate with, sent first by servers that require it,
+    /// `[auth_challenge](nonce)`.
+    AuthChallenge(String),
+    /// Outcome of authenticating, `[auth](ok|failed)`.
+    AuthResult(bool),
+    /// Round-trip time of the last heartbeat, reported as `[latency](ms)`.
+    Latency(Duration),
+    Volume(u8),
+    /// Native mute state, `[muted](true|false)`.
+    Muted(bool),
+    /// Whether the Pi is playing, `[playing](true|false)`.
+    Playing(bool),
+    /// Whether shuffle is on, `[shuffle](true|false)`.
+    Shuffle(bool),
+    /// `[repeat](off|track|context)`.
+    Repeat(RepeatState),
+    /// The track being played, `[track](id|title|artist|album|duration_ms)`,
+    /// or `None` when nothing is, `[track]()`.
+    Track(Option<Track>),
+    /// Playback position,
+    /// `[position](position_ms|duration_ms|playing)` or `...|paused)`.
+    Position(Position),
+    /// Where to download the cover art of a track, `[art](track_id|url)`.
+    ArtUrl { track_id: String, url: String },
+    /// Cover art of a track, sent in a binary frame (see [`art_frame`]). Its
+    /// text form only reports the image size.
+    ArtImage { track_id: String, data: Vec<u8> },
+    /// Optional protocol features the server supports, `[capabilities](ack)`.
+    Capabilities(Vec<String>),
+    /// The tagged request was executed.
+    Ack(u64),
+    /// The tagged request was rejected.
+    Error { id: u64, message: String },
+    /// A shutdown or reboot will happen once `delay` has passed,
+    /// `[power_scheduled](shutdown|delay_ms)`. Sent to every client, and on
+    /// connect while one is pending.
+    PowerScheduled { action: PowerAction, delay: Duration },
+    /// The pending shutdown or reboot was called off, `[power_cancelled]()`.
+    PowerCancelled,
+    /// An acknowledged command completed, produced by the client.
+    Done(Command),
+    /// An acknowledged command was rejected, produced by the client.
+    Failed(Command, String),
+    /// An acknowledged command got no answer in time, produced by the client.
+    TimedOut(Command),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The frame does not have the `[event](value)` shape.
+    MalformedFrame(String),
+    /// The frame is well formed but names an event we don't know.
+    UnknownEvent(String),
+    /// The event is known but its value can't be parsed.
+    InvalidValue { event: String, value: String },
+    /// The text is not a known command.
+    UnknownCommand(String),
+    /// The command is known but its argument is missing or invalid.
+    InvalidArgument { command: String, argument: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MalformedFrame(frame) => write!(f, "malformed frame: {:?}", frame),
+            ParseError::UnknownEvent(event) => write!(f, "unknown event: {:?}", event),
+            ParseError::InvalidValue { event, value } => {
+                write!(f, "invalid value {:?} for event {:?}", value, event)
+            }
+            ParseError::UnknownCommand(command) => write!(f, "unknown command: {:?}", command),
+            ParseError::InvalidArgument { command, argument } => {
+                write!(f, "invalid argument {:?} for command {:?}", argument, command)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+
+pub const MAX_VOLUME: u8 = 100;
+
+fn parse_volume(value: &str) -> Option<u8> {
+    match value.parse::<u8>() {
+        Ok(volume) if volume <= MAX_VOLUME => Some(volume),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::PrevTrack => f.write_str("prev_track"),
+            Command::TogglePlayPause => f.write_str("toggle_play_pause"),
+            Command::NextTrack => f.write_str("next_track"),
+            Command::ToggleShuffle => f.write_str("toggle_shuffle"),
+            Command::ToggleRepeatState => f.write_str("toggle_repeat_state"),
+            Command::GetVolume => f.write_str("get_volume"),
+            Command::SetVolume(volume) => write!(f, "set_volume {}", volume),
+            Command::SetMute(muted) => write!(f, "set_mute {}", muted),
+            Command::GetPlaybackState => f.write_str("get_playback_state"),
+            Command::GetNowPlaying => f.write_str("get_now_playing"),
+            Command::GetArt => f.write_str("get_art"),
+            Command::Seek(position) => write!(f, "seek {}", position.as_millis()),
+            // without a delay, as understood by servers that can't delay
+            Command::Shutdown(delay) if delay.is_zero() => f.write_str("shutdown"),
+            Command::Reboot(delay) if delay.is_zero() => f.write_str("reboot"),
+            Command::Shutdown(delay) => write!(f, "shutdown {}", delay.as_millis()),
+            Command::Reboot(delay) => write!(f, "reboot {}", delay.as_millis()),
+            Command::CancelPower => f.write_str("cancel_power"),
+            Command::Auth(proof) => write!(f, "auth {}", proof),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = match s.split_once(' ') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (s, None),
+        };
+
+        let command = match (name, argument) {
+            ("prev_track", None) => Command::PrevTrack,
+            ("toggle_play_pause", None) => Command::TogglePlayPause,
+            ("next_track", None) => Command::NextTrack,
+            ("toggle_shuffle", None) => Command::ToggleShuffle,
+            ("toggle_repeat_state", None) => Command::ToggleRepeatState,
+            ("get_volume", None) => Command::GetVolume,
+            ("set_volume", Some(argument)) => match parse_volume(argument) {
+                Some(volume) => Command::SetVolume(volume),
+                None => return Err(ParseError::InvalidArgument {
+                    command: name.to_string(),
+                    argument: argument.to_string(),
+                }),
+            },
+            ("set_volume", None) => return Err(ParseError::InvalidArgument {
+                command: name.to_string(),
+                argument: String::new(),
+            }),
+            ("set_mute", Some(argument)) => match argument.parse::<bool>() {
+                Ok(muted) => Command::SetMute(muted),
+                Err(_) => return Err(ParseError::InvalidArgument {
+                    command: name.to_string(),
+                    argument: argument.to_string(),
+                }),
+            },
+            ("set_mute", None) => return Err(ParseError::InvalidArgument {
+                command: name.to_string(),
+                argument: String::new(),
+            }),
+            ("get_playback_state", None) => Command::GetPlaybackState,
+            ("get_now_playing", None) => Command::GetNowPlaying,
+            ("get_art", None) => Command::GetArt,
+            ("seek", Some(argument)) => match argument.parse::<u64>() {
+                Ok(ms) => Command::Seek(Duration::from_millis(ms)),
+                Err(_) => return Err(ParseError::InvalidArgument {
+                    command: name.to_string(),
+                    argument: argument.to_string(),
+                }),
+            },
+            ("seek", None) => return Err(ParseError::InvalidArgument {
+                command: name.to_string(),
+                argument: String::new(),
+            }),
+            ("shutdown", None) => Command::Shutdown(Duration::ZERO),
+            ("reboot", None) => Command::Reboot(Duration::ZERO),
+            ("shutdown" | "reboot", Some(argument)) => match argument.parse::<u64>() {
+                Ok(ms) if name == "shutdown" => Command::Shutdown(Duration::from_millis(ms)),
+                Ok(ms) => Command::Reboot(Duration::from_millis(ms)),
+                Err(_) => return Err(ParseError::InvalidArgument {
+                    command: name.to_string(),
+                    argument: argument.to_string(),
+                }),
+            },
+            ("cancel_power", None) => Command::CancelPower,
+            ("auth", Some(argument)) => match argument.parse() {
+                Ok(proof) => Command::Auth(proof),
+                Err(_) => return Err(ParseError::InvalidArgument {
+                    command: name.to_string(),
+                    argument: argument.to_string(),
+                }),
+            },
+            ("auth", None) => return Err(ParseError::InvalidArgument {
+                command: name.to_string(),
+                argument: String::new(),
+            }),
+            _ => return Err(ParseError::UnknownCommand(s.to_string())),
+        };
+        Ok(command)
+    }
+}
+
+impl Command {
+    /// Whether sending the command twice has the same effect as sending it
+    /// once, which makes it safe to retry.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Command::GetVolume
+                | Command::SetVolume(_)
+                | Command::SetMute(_)
+                | Command::GetPlaybackState
+                | Command::GetNowPlaying
+                | Command::GetArt
+                | Command::Seek(_)
+                | Command::CancelPower
+        )
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, "@{} ", id)?;
+        }
+        write!(f, "{}", self.command)
+    }
+}
+
+impl FromStr for Request {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(rest) => {
+                let (id, command) = rest.split_once(' ').ok_or_else(|| ParseError::UnknownCommand(s.to_string()))?;
+                let id = id.parse::<u64>().map_err(|_| ParseError::UnknownCommand(s.to_string()))?;
+                Ok(Request { id: Some(id), command: command.parse()? })
+            }
+            None => Ok(Request { id: None, command: s.parse()? }),
+        }
+    }
+}
+
+impl From<Command> for Request {
+    fn from(command: Command) -> Self {
+        Request { id: None, command }
+    }
+}
+
+impl Event {
+    /// Whether the event is produced by the client itself, from the state
+    /// of the connection and of the requests it sent, rather than by the Pi.
+    pub fn is_synthetic(&self) -> bool {
+        matches!(
+            self,
+            Event::Connect(_)
+                | Event::Disconnect
+                | Event::Certificate(_)
+                | Event::Latency(_)
+                | Event::Done(_)
+                | Event::Failed(..)
+                | Event::TimedOut(_)
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Connect(_) => "connect",
+            Event::Disconnect => "disconnect",
+            Event::Certificate(_) => "certificate",
+            Event::AuthChallenge(_) => "auth_challenge",
+            Event::AuthResult(_) => "auth",
+            Event::Latency(_) => "latency",
+            Event::Volume(_) => "volume",
+            Event::Muted(_) => "muted",
+            Event::Playing(_) => "playing",
+            Event::Shuffle(_) => "shuffle",
+            Event::Repeat(_) => "repeat",
+            Event::Track(_) => "track",
+            Event::Position(_) => "position",
+            Event::ArtUrl { .. } => "art",
+            Event::ArtImage { .. } => "art_image",
+            Event::Capabilities(_) => "capabilities",
+            Event::Ack(_) => "ack",
+            Event::Error { .. } => "error",
+            Event::PowerScheduled { .. } => "power_scheduled",
+            Event::PowerCancelled => "power_cancelled",
+            Event::Done(_) => "done",
+            Event::Failed(..) => "failed",
+            Event::TimedOut(_) => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}](", self.name())?;
+        match self {
+            Event::Connect(ConnectStatus::Ok) => f.write_str("ok")?,
+            Event::Connect(ConnectStatus::Failed) => f.write_str("failed")?,
+            Event::Connect(ConnectStatus::CertificateChanged(fingerprint)) => {
+                write!(f, "certificate_changed|{}", fingerprint)?
+            }
+            Event::Disconnect => {}
+            Event::Connect(ConnectStatus::AuthFailed) => f.write_str("auth_failed")?,
+            Event::Certificate(fingerprint) => write!(f, "{}", fingerprint)?,
+            Event::AuthChallenge(nonce) => f.write_str(nonce)?,
+            Event::AuthResult(true) => f.write_str("ok")?,
+            Event::AuthResult(false) => f.write_str("failed")?,
+            Event::Latency(latency) => write!(f, "{}", latency.as_millis())?,
+            Event::Volume(volume) => write!(f, "{}", volume)?,
+            Event::Muted(on) | Event::Playing(on) | Event::Shuffle(on) => write!(f, "{}", on)?,
+            Event::Repeat(repeat) => write!(f, "{}", repeat)?,
+            Event::Track(None) => {}
+            Event::Track(Some(track)) => {
+                let duration = track.duration.as_millis().to_string();
+                write_fields(f, &[&track.id, &track.title, &track.artist, &track.album, &duration])?;
+            }
+            Event::Position(position) => {
+                let state = if position.playing { "playing" } else { "paused" };
+                write!(f, "{}|{}|{}", position.position.as_millis(), position.duration.as_millis(), state)?;
+            }
+            Event::ArtUrl { track_id, url } => write_fields(f, &[track_id, url])?,
+            Event::ArtImage { track_id, data } => {
+                write_fields(f, &[track_id, &format!("{} bytes", data.len())])?;
+            }
+            Event::Capabilities(capabilities) => f.write_str(&capabilities.join(","))?,
+            Event::Ack(id) => write!(f, "{}", id)?,
+            Event::Error { id, message } => write!(f, "{} {}", id, message)?,
+            Event::PowerScheduled { action, delay } => write!(f, "{}|{}", action, delay.as_millis())?,
+            Event::PowerCancelled => {}
+            Event::Done(cmd) | Event::TimedOut(cmd) => write!(f, "{}", cmd)?,
+            Event::Failed(cmd, message) => write!(f, "{}: {}", cmd, message)?,
+        }
+        f.write_str(")")
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[&str]) -> fmt::Result {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            f.write_str("|")?;
+        }
+        for c in field.chars() {
+            if c == '|' || c == '\\' {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits a multi-field value on unescaped `|`, unescaping the fields.
+pub fn split_fields(value: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            '|' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Splits a `[event](value)` frame into its event name and value.
+///
+/// The whole frame must match; the value runs up to the final `)` so it may
+/// itself contain parentheses.
+pub fn split_frame(frame: &str) -> Result<(&str, &str), ParseError> {
+    let malformed = || ParseError::MalformedFrame(frame.to_string());
+
+    let rest = frame.strip_prefix('[').ok_or_else(malformed)?;
+    let (event, rest) = rest.split_once("](").ok_or_else(malformed)?;
+    let value = rest.strip_suffix(')').ok_or_else(malformed)?;
+    if event.is_empty() || event.contains(['[', ']']) {
+        return Err(malformed());
+    }
+    Ok((event, value))
+}
+
+impl FromStr for Event {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (event, value) = split_frame(s)?;
+        let invalid = || ParseError::InvalidValue {
+            event: event.to_string(),
+            value: value.to_string(),
+        };
+
+        match event {
+            "connect" => match value {
+                "ok" => Ok(Event::Connect(ConnectStatus::Ok)),
+                "failed" => Ok(Event::Connect(ConnectStatus::Failed)),
+                "auth_failed" => Ok(Event::Connect(ConnectStatus::AuthFailed)),
+                _ => match value.split_once('|') {
+                    Some(("certificate_changed", fingerprint)) => fingerprint
+                        .parse()
+                        .map(|fingerprint| Event::Connect(ConnectStatus::CertificateChanged(fingerprint)))
+                        .map_err(|_| invalid()),
+                    _ => Err(invalid()),
+                },
+            },
+            "disconnect" if value.is_empty() => Ok(Event::Disconnect),
+            "disconnect" => Err(invalid()),
+            "certificate" => value.parse().map(Event::Certificate).map_err(|_| invalid()),
+            "auth_challenge" if !value.is_empty() => Ok(Event::AuthChallenge(value.to_string())),
+            "auth_challenge" => Err(invalid()),
+            "auth" => match value {
+                "ok" => Ok(Event::AuthResult(true)),
+                "failed" => Ok(Event::AuthResult(false)),
+                _ => Err(invalid()),
+            },
+            "latency" => value.parse::<u64>().map(|ms| Event::Latency(Duration::from_millis(ms))).map_err(|_| invalid()),
+            "volume" => parse_volume(value).map(Event::Volume).ok_or_else(invalid),
+            "muted" => value.parse().map(Event::Muted).map_err(|_| invalid()),
+            "playing" => value.parse().map(Event::Playing).map_err(|_| invalid()),
+            "shuffle" => value.parse().map(Event::Shuffle).map_err(|_| invalid()),
+            "repeat" => value.parse().map(Event::Repeat).map_err(|_| invalid()),
+            "track" if value.is_empty() => Ok(Event::Track(None)),
+            "track" => match split_fields(value).as_slice() {
+                [id, title, artist, album, duration] => {
+                    let duration = duration.parse::<u64>().map_err(|_| invalid())?;
+                    Ok(Event::Track(Some(Track {
+                        id: id.clone(),
+                        title: title.clone(),
+                        artist: artist.clone(),
+                        album: album.clone(),
+                        duration: Duration::from_millis(duration),
+                    })))
+                }
+                _ => Err(invalid()),
+            },
+            "position" => match value.split('|').collect::<Vec<_>>().as_slice() {
+                [position, duration, state] => {
+                    let position = position.parse::<u64>().map_err(|_| invalid())?;
+                    let duration = duration.parse::<u64>().map_err(|_| invalid())?;
+                    let playing = match *state {
+                        "playing" => true,
+                        "paused" => false,
+                        _ => return Err(invalid()),
+                    };
+                    Ok(Event::Position(Position {
+                        position: Duration::from_millis(position),
+                        duration: Duration::from_millis(duration),
+                        playing,
+                    }))
+                }
+                _ => Err(invalid()),
+            },
+            "art" => match split_fields(value).as_slice() {
+                [track_id, url] => Ok(Event::ArtUrl { track_id: track_id.clone(), url: url.clone() }),
+                _ => Err(invalid()),
+            },
+            "capabilities" => Ok(Event::Capabilities(
+                value.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect(),
+            )),
+            "ack" => value.parse::<u64>().map(Event::Ack).map_err(|_| invalid()),
+            "error" => {
+                let (id, message) = value.split_once(' ').unwrap_or((value, ""));
+                let id = id.parse::<u64>().map_err(|_| invalid())?;
+                Ok(Event::Error { id, message: message.to_string() })
+            }
+            "power_scheduled" => {
+                let (action, delay) = value.split_once('|').ok_or_else(invalid)?;
+                Ok(Event::PowerScheduled {
+                    action: action.parse().map_err(|_| invalid())?,
+                    delay: Duration::from_millis(delay.parse().map_err(|_| invalid())?),
+                })
+            }
+            "power_cancelled" if value.is_empty() => Ok(Event::PowerCancelled),
+            "power_cancelled" => Err(invalid()),
+            "done" => value.parse().map(Event::Done).map_err(|_| invalid()),
+            "failed" => {
+                let (cmd, message) = value.split_once(": ").ok_or_else(invalid)?;
+                let cmd = cmd.parse().map_err(|_| invalid())?;
+                Ok(Event::Failed(cmd, message.to_string()))
+            }
+            "timeout" => value.parse().map(Event::TimedOut).map_err(|_| invalid()),
+            _ => Err(ParseError::UnknownEvent(event.to_string())),
+        }
+    }
+}
+
+/// Builds a binary art frame: an `[art](track_id)` header line followed by
+/// the image bytes.
+pub fn art_frame(track_id: &str, data: &[u8]) -> Vec<u8> {
+    let header = format!("[art]({})\n", track_id);
+    let mut frame = Vec::with_capacity(header.len() + data.len());
+    frame.extend_from_slice(header.as_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Parses a binary art frame, returning `None` for anything else.
+pub fn parse_art_frame(frame: &[u8]) -> Option<Event> {
+    let newline = frame.iter().position(|&b| b == b'\n')?;
+    let header = std::str::from_utf8(&frame[..newline]).ok()?;
+    match split_frame(header).ok()? {
+        ("art", track_id) if !track_id.is_empty() => Some(Event::ArtImage {
+            track_id: track_id.to_string(),
+            data: frame[newline + 1..].to_vec(),
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,30 @@
+//! Spacing out repeated notifications.
+
+use std::time::{Duration, Instant};
+
+
+/// Lets something happen at most once every `interval`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RateLimit {
+    pub interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(interval: Duration) -> Self {
+        RateLimit { interval, last: None }
+    }
+
+    /// How long from `now` until it may happen again, zero if right away.
+    pub fn wait(&self, now: Instant) -> Duration {
+        match self.last {
+            Some(last) => (last + self.interval).saturating_duration_since(now),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Notes that it happened at `now`.
+    pub fn record(&mut self, now: Instant) {
+        self.last = Some(now);
+    }
+}
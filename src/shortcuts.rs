@@ -0,0 +1,139 @@
+//! Window actions the panel can be driven with, and their default keyboard
+//! shortcuts.
+//!
+//! Every control of the main window is a `win.<action>` action, so it can be
+//! bound to keys, put in menus and triggered from outside the window. The
+//! shortcuts use the GTK accelerator syntax (`<Primary>Right`,
+//! `XF86AudioNext`) and can be remapped in the `[shortcuts]` table of the
+//! config file.
+
+/// An action of the main window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortcut {
+    /// Name of the action, without the `win.` prefix.
+    pub action: &'static str,
+    /// What the action does, as shown in the shortcuts window.
+    pub title: &'static str,
+    /// Heading the action is listed under in the shortcuts window.
+    pub group: &'static str,
+    pub default_accels: &'static [&'static str],
+}
+
+impl Shortcut {
+    /// Name to activate or bind the action with, `win.next-track`.
+    pub fn detailed_name(&self) -> String {
+        format!("win.{}", self.action)
+    }
+}
+
+/// Every action, in the order the shortcuts window lists them.
+pub const SHORTCUTS: &[Shortcut] = &[
+    Shortcut {
+        action: "connect",
+        title: "Connect or disconnect",
+        group: "Connection",
+        default_accels: &["<Primary>k"],
+    },
+    Shortcut {
+        action: "disconnect",
+        title: "Cancel connecting or disconnect",
+        group: "Connection",
+        default_accels: &["<Primary><Shift>k"],
+    },
+    Shortcut {
+        action: "play-pause",
+        title: "Play / pause",
+        group: "Playback",
+        default_accels: &["<Primary>p", "XF86AudioPlay", "XF86AudioPause"],
+    },
+    Shortcut {
+        action: "next-track",
+        title: "Next track",
+        group: "Playback",
+        default_accels: &["<Primary>Right", "XF86AudioNext"],
+    },
+    Shortcut {
+        action: "prev-track",
+        title: "Previous track",
+        group: "Playback",
+        default_accels: &["<Primary>Left", "XF86AudioPrev"],
+    },
+    Shortcut {
+        action: "seek-forward",
+        title: "Seek forward",
+        group: "Playback",
+        default_accels: &["<Primary><Shift>Right", "XF86AudioForward"],
+    },
+    Shortcut {
+        action: "seek-backward",
+        title: "Seek backward",
+        group: "Playback",
+        default_accels: &["<Primary><Shift>Left", "XF86AudioRewind"],
+    },
+    Shortcut {
+        action: "toggle-shuffle",
+        title: "Toggle shuffle",
+        group: "Playback",
+        default_accels: &["<Primary>s", "XF86AudioRandomPlay"],
+    },
+    Shortcut {
+        action: "toggle-repeat",
+        title: "Cycle repeat",
+        group: "Playback",
+        default_accels: &["<Primary>r", "XF86AudioRepeat"],
+    },
+    Shortcut {
+        action: "volume-up",
+        title: "Volume up",
+        group: "Volume",
+        default_accels: &["<Primary>Up", "XF86AudioRaiseVolume"],
+    },
+    Shortcut {
+        action: "volume-down",
+        title: "Volume down",
+        group: "Volume",
+        default_accels: &["<Primary>Down", "XF86AudioLowerVolume"],
+    },
+    Shortcut {
+        action: "mute",
+        title: "Mute / unmute",
+        group: "Volume",
+        default_accels: &["<Primary>m", "XF86AudioMute"],
+    },
+    // asked for confirmation, but still left unbound by default
+    Shortcut {
+        action: "shutdown",
+        title: "Shut down the Pi",
+        group: "Power",
+        default_accels: &[],
+    },
+    Shortcut {
+        action: "reboot",
+        title: "Reboot the Pi",
+        group: "Power",
+        default_accels: &[],
+    },
+    Shortcut {
+        action: "cancel-power",
+        title: "Cancel a delayed shutdown or reboot",
+        group: "Power",
+        default_accels: &[],
+    },
+    Shortcut {
+        action: "preferences",
+        title: "Preferences",
+        group: "General",
+        default_accels: &["<Primary>comma"],
+    },
+    Shortcut {
+        action: "shortcuts",
+        title: "Keyboard shortcuts",
+        group: "General",
+        default_accels: &["<Primary>question", "<Primary>F1"],
+    },
+];
+
+/// The action called `action`.
+pub fn find(action: &str) -> Option<&'static Shortcut> {
+    SHORTCUTS.iter().find(|shortcut| shortcut.action == action)
+}
@@ -0,0 +1,209 @@
+//! TLS settings for `wss://` connections.
+//!
+//! A device is trusted either through the system root certificates, through
+//! a CA certificate read from a PEM file, or by pinning the SHA-256
+//! fingerprint of its certificate. Pinning is meant for the self-signed
+//! certificate of a Pi: the fingerprint is learned on the first connection
+//! (trust on first use) and any other certificate is refused afterwards.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+
+
+/// SHA-256 digest of a DER encoded certificate, written as colon separated
+/// hex pairs (`AB:CD:...`).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    pub fn of(der: &[u8]) -> Self {
+        Fingerprint(Sha256::digest(der).into())
+    }
+
+    /// Fingerprint of the first certificate in a PEM file.
+    pub fn of_pem(pem: &[u8]) -> io::Result<Self> {
+        let certs = pem_certs(pem)?;
+        Ok(Fingerprint::of(&certs[0].0))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFingerprint(pub String);
+
+impl fmt::Display for InvalidFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid SHA-256 fingerprint: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidFingerprint {}
+
+impl FromStr for Fingerprint {
+    type Err = InvalidFingerprint;
+
+    /// Accepts hex digits in any case, with or without colons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidFingerprint(s.to_string());
+        let digits: Vec<u8> = s.bytes().filter(|&b| b != b':').collect();
+        if digits.len() != 64 {
+            return Err(invalid());
+        }
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Fingerprint(bytes))
+    }
+}
+
+impl TryFrom<String> for Fingerprint {
+    type Error = InvalidFingerprint;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Fingerprint> for String {
+    fn from(fingerprint: Fingerprint) -> Self {
+        fingerprint.to_string()
+    }
+}
+
+/// How the certificate of a `wss://` server is checked.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Trust {
+    /// Signed by one of the system root certificates.
+    #[default]
+    System,
+    /// Signed by the CA certificate(s) in this PEM file.
+    CaFile(PathBuf),
+    /// Has this fingerprint. `None` accepts any certificate and reports its
+    /// fingerprint so it can be pinned.
+    Pinned(Option<Fingerprint>),
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn pem_certs(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])?;
+    if certs.is_empty() {
+        return Err(invalid_data("no certificate found"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_ca_file(path: &Path) -> io::Result<RootCertStore> {
+    let pem = fs::read(path)?;
+    let mut roots = RootCertStore::empty();
+    for cert in pem_certs(&pem)? {
+        roots.add(&cert).map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+    }
+    Ok(roots)
+}
+
+fn system_roots() -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        // skip the odd certificate webpki can't parse rather than failing
+        let _ = roots.add(&rustls::Certificate(cert.0));
+    }
+    Ok(roots)
+}
+
+/// Accepts the certificate whose fingerprint is pinned, or any certificate
+/// when nothing is pinned yet. Either way the fingerprint the server
+/// presented is recorded, so the caller can pin it or report the mismatch.
+struct PinVerifier {
+    pin: Option<Fingerprint>,
+    presented: Arc<Mutex<Option<Fingerprint>>>,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of(&end_entity.0);
+        *self.presented.lock().unwrap() = Some(fingerprint);
+        match self.pin {
+            Some(pin) if pin != fingerprint => Err(rustls::Error::InvalidCertificateData(format!(
+                "certificate fingerprint {} does not match the pinned {}",
+                fingerprint, pin
+            ))),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+/// Builds the client side TLS settings for `trust`. With
+/// [`Trust::Pinned`], the fingerprint of the certificate the server presents
+/// is stored in `presented` during the handshake.
+pub fn client_config(trust: &Trust, presented: Arc<Mutex<Option<Fingerprint>>>) -> io::Result<rustls::ClientConfig> {
+    let builder = rustls::ClientConfig::builder().with_safe_defaults();
+    let config = match trust {
+        Trust::System => builder.with_root_certificates(system_roots()?).with_no_client_auth(),
+        Trust::CaFile(path) => builder.with_root_certificates(read_ca_file(path)?).with_no_client_auth(),
+        Trust::Pinned(pin) => builder
+            .with_custom_certificate_verifier(Arc::new(PinVerifier { pin: *pin, presented }))
+            .with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+/// Builds the server side TLS settings from a PEM certificate chain and a
+/// PEM private key (PKCS#8, RSA or EC).
+pub fn server_config(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<rustls::ServerConfig> {
+    let certs = pem_certs(cert_pem)?;
+    let mut reader = key_pem;
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(invalid_data("no private key found")),
+        }
+    };
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)
+}
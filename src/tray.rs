@@ -0,0 +1,639 @@
+//! Tray icon, a StatusNotifierItem on the D-Bus session bus.
+//!
+//! The icon and its menu are drawn by the desktop's tray host, which reads
+//! them from `org.kde.StatusNotifierItem` and `com.canonical.dbusmenu`
+//! objects, so no GUI toolkit is involved. Like the
+//! [MPRIS bridge](crate::mpris) the tray only mirrors the state it's given:
+//! clicks come back as [`TrayRequest`]s for the panel to carry out.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::channel::mpsc::UnboundedSender;
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedValue, Type, Value};
+use zbus::{connection, fdo, interface};
+
+use crate::protocol::{ConnectStatus, Event, Track};
+
+
+/// Path of the item object.
+pub const ITEM_PATH: &str = "/StatusNotifierItem";
+/// Path of the menu object.
+pub const MENU_PATH: &str = "/MenuBar";
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+/// Volumes the volume submenu offers.
+const VOLUME_PRESETS: [u8; 4] = [25, 50, 75, 100];
+
+// menu item ids, devices are numbered from DEVICE_ITEMS up and volume
+// presets are VOLUME_ITEMS plus the volume
+const ROOT: i32 = 0;
+const SHOW_WINDOW: i32 = 1;
+const PLAY_PAUSE: i32 = 2;
+const NEXT_TRACK: i32 = 3;
+const PREV_TRACK: i32 = 4;
+const VOLUME: i32 = 5;
+const VOLUME_UP: i32 = 6;
+const VOLUME_DOWN: i32 = 7;
+const MUTE: i32 = 8;
+const DEVICE: i32 = 9;
+const POWER: i32 = 10;
+const SHUTDOWN: i32 = 11;
+const REBOOT: i32 = 12;
+const CANCEL_POWER: i32 = 13;
+const QUIT: i32 = 14;
+const SEPARATORS: i32 = 20;
+const VOLUME_ITEMS: i32 = 100;
+const DEVICE_ITEMS: i32 = 1000;
+
+/// An icon image: width, height and ARGB32 pixels.
+type Pixmap = (i32, i32, Vec<u8>);
+
+/// What a click on the tray asks the panel to do, or news of the tray host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrayRequest {
+    /// Activate the window action, see [`shortcuts`](crate::shortcuts).
+    Action(&'static str),
+    SetVolume(u8),
+    SelectDevice(String),
+    /// Show the window when it's hidden, hide it otherwise.
+    ToggleWindow,
+    Quit,
+    /// The tray host went away, hiding the icon, or a new one showed it
+    /// again.
+    Shown(bool),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct State {
+    /// How the connection is doing, in words.
+    connection: String,
+    connected: bool,
+    playing: bool,
+    volume: u8,
+    muted: bool,
+    track: Option<Track>,
+    power_scheduled: bool,
+    devices: Vec<String>,
+    selected: Option<String>,
+    window_visible: bool,
+}
+
+impl State {
+    fn apply(&mut self, event: &Event) {
+        match event {
+            Event::Connect(ConnectStatus::Ok) => self.connected = true,
+            Event::Connect(_) | Event::Disconnect => {
+                *self = State {
+                    connection: std::mem::take(&mut self.connection),
+                    devices: std::mem::take(&mut self.devices),
+                    selected: self.selected.take(),
+                    window_visible: self.window_visible,
+                    ..State::default()
+                };
+            }
+            Event::Playing(playing) => self.playing = *playing,
+            Event::Volume(volume) => self.volume = *volume,
+            Event::Muted(muted) => self.muted = *muted,
+            Event::Track(track) => self.track = track.clone(),
+            Event::PowerScheduled { .. } => self.power_scheduled = true,
+            Event::PowerCancelled => self.power_scheduled = false,
+            _ => {}
+        }
+    }
+
+    fn icon_name(&self) -> &'static str {
+        match (self.connected, self.playing) {
+            (false, _) => "audio-x-generic",
+            (true, true) => "media-playback-start",
+            (true, false) => "media-playback-pause",
+        }
+    }
+
+    fn tool_tip(&self) -> String {
+        match &self.track {
+            Some(track) if self.connected => format!("{}\n{} - {}", self.connection, track.title, track.artist),
+            _ => self.connection.clone(),
+        }
+    }
+
+    fn menu(&self) -> MenuItem {
+        let connected = self.connected;
+        let volume_label = if self.muted {
+            "Volume (muted)".to_string()
+        } else if connected {
+            format!("Volume ({}%)", self.volume)
+        } else {
+            "Volume".to_string()
+        };
+        let mut volume_items = vec![
+            MenuItem::new(VOLUME_UP, "Volume up", connected, TrayRequest::Action("volume-up")),
+            MenuItem::new(VOLUME_DOWN, "Volume down", connected, TrayRequest::Action("volume-down")),
+            MenuItem::new(MUTE, "Mute", connected, TrayRequest::Action("mute"))
+                .toggle("checkmark", self.muted)
+                .separated_by(SEPARATORS),
+        ];
+        volume_items.extend(VOLUME_PRESETS.iter().map(|&volume| {
+            MenuItem::new(VOLUME_ITEMS + volume as i32, &format!("{}%", volume), connected, TrayRequest::SetVolume(volume))
+                .toggle("radio", connected && !self.muted && self.volume == volume)
+        }));
+        let device_items = self.devices.iter().enumerate().map(|(i, name)| {
+            MenuItem::new(DEVICE_ITEMS + i as i32, name, true, TrayRequest::SelectDevice(name.clone()))
+                .toggle("radio", self.selected.as_ref() == Some(name))
+        });
+
+        MenuItem::submenu(ROOT, "", vec![
+            MenuItem::new(
+                SHOW_WINDOW,
+                if self.window_visible { "Hide window" } else { "Show window" },
+                true,
+                TrayRequest::ToggleWindow,
+            )
+            .separated_by(SEPARATORS + 1),
+            MenuItem::new(
+                PLAY_PAUSE,
+                if self.playing { "Pause" } else { "Play" },
+                connected,
+                TrayRequest::Action("play-pause"),
+            ),
+            MenuItem::new(NEXT_TRACK, "Next track", connected, TrayRequest::Action("next-track")),
+            MenuItem::new(PREV_TRACK, "Previous track", connected, TrayRequest::Action("prev-track")),
+            MenuItem::submenu(VOLUME, &volume_label, volume_items),
+            MenuItem::submenu(DEVICE, "Device", device_items.collect()).separated_by(SEPARATORS + 2),
+            MenuItem::submenu(POWER, "Power", vec![
+                MenuItem::new(SHUTDOWN, "Shut down...", connected, TrayRequest::Action("shutdown")),
+                MenuItem::new(REBOOT, "Reboot...", connected, TrayRequest::Action("reboot")),
+                MenuItem::new(
+                    CANCEL_POWER,
+                    "Cancel shutdown / reboot",
+                    connected && self.power_scheduled,
+                    TrayRequest::Action("cancel-power"),
+                ),
+            ])
+            .separated_by(SEPARATORS + 3),
+            MenuItem::new(QUIT, "Quit", true, TrayRequest::Quit),
+        ])
+    }
+}
+
+/// An entry of the tray menu.
+#[derive(Debug)]
+struct MenuItem {
+    id: i32,
+    label: String,
+    enabled: bool,
+    /// Toggle type, `checkmark` or `radio`, and whether it's on.
+    toggle: Option<(&'static str, bool)>,
+    request: Option<TrayRequest>,
+    children: Vec<MenuItem>,
+    /// Id of a separator following the item.
+    separator_after: Option<i32>,
+}
+
+impl MenuItem {
+    fn new(id: i32, label: &str, enabled: bool, request: TrayRequest) -> Self {
+        MenuItem {
+            id,
+            // underscores mark mnemonics
+            label: label.replace('_', "__"),
+            enabled,
+            toggle: None,
+            request: Some(request),
+            children: Vec::new(),
+            separator_after: None,
+        }
+    }
+
+    fn submenu(id: i32, label: &str, children: Vec<MenuItem>) -> Self {
+        MenuItem {
+            id,
+            label: label.to_string(),
+            enabled: !children.is_empty(),
+            toggle: None,
+            request: None,
+            children,
+            separator_after: None,
+        }
+    }
+
+    fn toggle(self, toggle_type: &'static str, on: bool) -> Self {
+        MenuItem { toggle: Some((toggle_type, on)), ..self }
+    }
+
+    fn separated_by(self, separator: i32) -> Self {
+        MenuItem { separator_after: Some(separator), ..self }
+    }
+
+    fn properties(&self) -> HashMap<String, OwnedValue> {
+        let mut properties = HashMap::new();
+        if !self.label.is_empty() {
+            properties.insert("label".to_string(), owned(self.label.as_str()));
+        }
+        properties.insert("enabled".to_string(), owned(self.enabled));
+        if let Some((toggle_type, on)) = self.toggle {
+            properties.insert("toggle-type".to_string(), owned(toggle_type));
+            properties.insert("toggle-state".to_string(), owned(on as i32));
+        }
+        if !self.children.is_empty() {
+            properties.insert("children-display".to_string(), owned("submenu"));
+        }
+        properties
+    }
+
+    /// The item and its children down to `depth` levels, or all of them when
+    /// negative.
+    fn layout(&self, depth: i32, names: &[String]) -> Layout {
+        let mut children = Vec::new();
+        if depth != 0 {
+            for child in &self.children {
+                children.push(owned(child.layout(depth - 1, names)));
+                if let Some(id) = child.separator_after {
+                    children.push(owned(separator(id)));
+                }
+            }
+        }
+        Layout { id: self.id, properties: filter(self.properties(), names), children }
+    }
+
+    fn find(&self, id: i32) -> Option<&MenuItem> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    /// Properties of the item with `id`, separators included.
+    fn properties_of(&self, id: i32) -> Option<HashMap<String, OwnedValue>> {
+        if let Some(item) = self.find(id) {
+            return Some(item.properties());
+        }
+        let is_separator = (SEPARATORS..VOLUME_ITEMS).contains(&id);
+        is_separator.then(|| separator(id).properties)
+    }
+}
+
+fn separator(id: i32) -> Layout {
+    let mut properties = HashMap::new();
+    properties.insert("type".to_string(), owned("separator"));
+    Layout { id, properties, children: Vec::new() }
+}
+
+/// Keeps the properties named in `names`, all of them when it's empty.
+fn filter(mut properties: HashMap<String, OwnedValue>, names: &[String]) -> HashMap<String, OwnedValue> {
+    if !names.is_empty() {
+        properties.retain(|name, _| names.contains(name));
+    }
+    properties
+}
+
+/// A menu item as `GetLayout` returns it.
+#[derive(Debug, Serialize, Type, Value)]
+struct Layout {
+    id: i32,
+    properties: HashMap<String, OwnedValue>,
+    children: Vec<OwnedValue>,
+}
+
+fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+    value.into().try_into().expect("the menu holds no file descriptors")
+}
+
+struct Item {
+    state: State,
+    requests: UnboundedSender<TrayRequest>,
+}
+
+impl Item {
+    fn request(&self, request: TrayRequest) -> fdo::Result<()> {
+        self.requests.unbounded_send(request).map_err(|_| fdo::Error::Failed("The panel is gone".to_string()))
+    }
+}
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    fn activate(&self, _x: i32, _y: i32) -> fdo::Result<()> {
+        self.request(TrayRequest::ToggleWindow)
+    }
+
+    /// Middle click.
+    fn secondary_activate(&self, _x: i32, _y: i32) -> fdo::Result<()> {
+        if !self.state.connected {
+            return Ok(());
+        }
+        self.request(TrayRequest::Action("play-pause"))
+    }
+
+    /// Hosts show [`Menu`] themselves.
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    fn scroll(&self, delta: i32, orientation: &str) -> fdo::Result<()> {
+        if !self.state.connected || delta == 0 || !orientation.eq_ignore_ascii_case("vertical") {
+            return Ok(());
+        }
+        self.request(TrayRequest::Action(if delta > 0 { "volume-up" } else { "volume-down" }))
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn category(&self) -> &str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn id(&self) -> &str {
+        "spotifypi-control-panel"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn title(&self) -> &str {
+        "SpotifyPi Control Panel"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn status(&self) -> &str {
+        "Active"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn window_id(&self) -> i32 {
+        0
+    }
+
+    /// Announced with `NewIcon`, like the other properties that change.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn icon_name(&self) -> &str {
+        self.state.icon_name()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn icon_pixmap(&self) -> Vec<Pixmap> {
+        Vec::new()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn overlay_icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn attention_icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn tool_tip(&self) -> (String, Vec<Pixmap>, String, String) {
+        (self.state.icon_name().to_string(), Vec::new(), "SpotifyPi".to_string(), self.state.tool_tip())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn menu(&self) -> ObjectPath<'_> {
+        ObjectPath::from_static_str_unchecked(MENU_PATH)
+    }
+
+    #[zbus(signal)]
+    async fn new_icon(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+struct Menu {
+    state: State,
+    revision: u32,
+    requests: UnboundedSender<TrayRequest>,
+}
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    fn get_layout(&self, parent_id: i32, recursion_depth: i32, property_names: Vec<String>) -> fdo::Result<(u32, Layout)> {
+        let menu = self.state.menu();
+        let parent = menu.find(parent_id)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No menu item {}", parent_id)))?;
+        Ok((self.revision, parent.layout(recursion_depth, &property_names)))
+    }
+
+    fn get_group_properties(&self, ids: Vec<i32>, property_names: Vec<String>) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        let menu = self.state.menu();
+        ids.into_iter()
+            .filter_map(|id| Some((id, filter(menu.properties_of(id)?, &property_names))))
+            .collect()
+    }
+
+    fn get_property(&self, id: i32, name: &str) -> fdo::Result<OwnedValue> {
+        self.state.menu().properties_of(id)
+            .and_then(|mut properties| properties.remove(name))
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No property {} on menu item {}", name, id)))
+    }
+
+    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _timestamp: u32) -> fdo::Result<()> {
+        if event_id != "clicked" {
+            return Ok(());
+        }
+        let menu = self.state.menu();
+        let item = menu.find(id).ok_or_else(|| fdo::Error::InvalidArgs(format!("No menu item {}", id)))?;
+        match &item.request {
+            Some(request) if item.enabled => self.requests.unbounded_send(request.clone())
+                .map_err(|_| fdo::Error::Failed("The panel is gone".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the ids that were not found.
+    fn event_group(&self, events: Vec<(i32, String, OwnedValue, u32)>) -> fdo::Result<Vec<i32>> {
+        let mut not_found = Vec::new();
+        for (id, event_id, data, timestamp) in events {
+            if self.event(id, &event_id, data.into(), timestamp).is_err() {
+                not_found.push(id);
+            }
+        }
+        Ok(not_found)
+    }
+
+    /// The layout is always up to date.
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (Vec::new(), Vec::new())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(emitter: &SignalEmitter<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+}
+
+/// Whether `event` changes what the tray shows.
+pub fn is_published(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Connect(_)
+            | Event::Disconnect
+            | Event::Playing(_)
+            | Event::Volume(_)
+            | Event::Muted(_)
+            | Event::Track(_)
+            | Event::PowerScheduled { .. }
+            | Event::PowerCancelled
+    )
+}
+
+/// A tray icon being shown. Dropping it removes the icon.
+pub struct Tray {
+    connection: zbus::Connection,
+    /// Registers the icon again with tray hosts taking over.
+    watch_task: JoinHandle<()>,
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        self.watch_task.abort();
+    }
+}
+
+async fn register(connection: &zbus::Connection, name: &str) -> zbus::Result<()> {
+    connection.call_method(
+        Some(WATCHER_NAME),
+        WATCHER_PATH,
+        Some(WATCHER_NAME),
+        "RegisterStatusNotifierItem",
+        &name,
+    ).await?;
+    Ok(())
+}
+
+impl Tray {
+    /// Registers the icon with the tray host on the bus `builder` connects
+    /// to, usually `connection::Builder::session()`. Fails when there is no
+    /// tray host. Clicks are sent to `requests`, and so is the icon being
+    /// hidden when the host goes away, or shown again by a new one.
+    pub async fn start(builder: connection::Builder<'_>, requests: UnboundedSender<TrayRequest>) -> zbus::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        let name = format!(
+            "org.kde.StatusNotifierItem-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+        );
+        let item = Item { state: State::default(), requests: requests.clone() };
+        let menu = Menu { state: State::default(), revision: 0, requests: requests.clone() };
+        let connection = builder
+            .name(name.as_str())?
+            .serve_at(ITEM_PATH, item)?
+            .serve_at(MENU_PATH, menu)?
+            .build()
+            .await?;
+        // listening first, so a host restarting meanwhile isn't missed
+        let mut owner_changes = fdo::DBusProxy::new(&connection)
+            .await?
+            .receive_name_owner_changed_with_args(&[(0, WATCHER_NAME)])
+            .await?;
+        register(&connection, &name).await?;
+
+        let watch_task = tokio::spawn({
+            let connection = connection.clone();
+            async move {
+                while let Some(change) = owner_changes.next().await {
+                    let Ok(args) = change.args() else { continue };
+                    let shown = match args.new_owner().as_ref() {
+                        Some(_) => match register(&connection, &name).await {
+                            Ok(()) => true,
+                            Err(e) => {
+                                eprintln!("Could not show the tray icon again: {}", e);
+                                false
+                            }
+                        },
+                        None => false,
+                    };
+                    if requests.unbounded_send(TrayRequest::Shown(shown)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Tray { connection, watch_task })
+    }
+
+    /// Updates the icon and the menu with an event from the Pi.
+    pub async fn update(&self, event: &Event) -> zbus::Result<()> {
+        self.change(|state| state.apply(event)).await
+    }
+
+    /// Sets the connection state shown in the tooltip.
+    pub async fn set_connection(&self, connection: &str) -> zbus::Result<()> {
+        self.change(|state| state.connection = connection.to_string()).await
+    }
+
+    /// Sets the devices to choose from, and the selected one.
+    pub async fn set_devices(&self, devices: Vec<String>, selected: Option<String>) -> zbus::Result<()> {
+        self.change(|state| {
+            state.devices = devices;
+            state.selected = selected;
+        }).await
+    }
+
+    /// Tells whether the window is shown, for the menu to offer the other.
+    pub async fn set_window_visible(&self, visible: bool) -> zbus::Result<()> {
+        self.change(|state| state.window_visible = visible).await
+    }
+
+    async fn change(&self, change: impl FnOnce(&mut State)) -> zbus::Result<()> {
+        let object_server = self.connection.object_server();
+        let menu_ref = object_server.interface::<_, Menu>(MENU_PATH).await?;
+        let item_ref = object_server.interface::<_, Item>(ITEM_PATH).await?;
+        let mut menu = menu_ref.get_mut().await;
+        let mut item = item_ref.get_mut().await;
+
+        let mut state = menu.state.clone();
+        change(&mut state);
+        if state == menu.state {
+            return Ok(());
+        }
+        // the connection only shows in the tooltip
+        let menu_changed = State { connection: String::new(), ..state.clone() }
+            != State { connection: String::new(), ..menu.state.clone() };
+        let icon_changed = state.icon_name() != menu.state.icon_name();
+        let tool_tip_changed = icon_changed || state.tool_tip() != menu.state.tool_tip();
+        menu.state = state.clone();
+        item.state = state;
+
+        if menu_changed {
+            menu.revision += 1;
+            Menu::layout_updated(menu_ref.signal_emitter(), menu.revision, ROOT).await?;
+        }
+        if icon_changed {
+            Item::new_icon(item_ref.signal_emitter()).await?;
+        }
+        if tool_tip_changed {
+            Item::new_tool_tip(item_ref.signal_emitter()).await?;
+        }
+        Ok(())
+    }
+}
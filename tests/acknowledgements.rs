@@ -0,0 +1,156 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use spotifypi_control_panel::client::{connect_to_ws_with_config, ClientConfig};
+use spotifypi_control_panel::mock::{Faults, MockConfig};
+use spotifypi_control_panel::protocol::{
+    Command, ConnectStatus, Event, Request, CAPABILITY_ACK, CAPABILITY_MUTE, CAPABILITY_POWER_DELAY,
+};
+
+use common::{next_event, serve_once, start_mock};
+
+
+fn short_timeouts() -> ClientConfig {
+    ClientConfig {
+        heartbeat: None,
+        ack_timeout: Duration::from_millis(100),
+        max_retries: 2,
+        ..ClientConfig::default()
+    }
+}
+
+async fn expect_connected(output_rx: &mut UnboundedReceiver<Event>, capabilities: &[&str]) {
+    assert_eq!(next_event(output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    if !capabilities.is_empty() {
+        let capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+        assert_eq!(next_event(output_rx).await, Some(Event::Capabilities(capabilities)));
+    }
+}
+
+#[tokio::test]
+async fn acknowledged_commands_report_done() {
+    let url = start_mock(MockConfig::default()).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[CAPABILITY_ACK, CAPABILITY_MUTE, CAPABILITY_POWER_DELAY]).await;
+
+    input_tx.unbounded_send(Command::ToggleShuffle).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Shuffle(true)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::ToggleShuffle)));
+
+    input_tx.unbounded_send(Command::SetVolume(30)).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(30)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Muted(false)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::SetVolume(30))));
+
+    input_tx.unbounded_send(Command::SetMute(true)).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Muted(true)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::SetMute(true))));
+}
+
+#[tokio::test]
+async fn native_mute_is_rejected_when_unsupported() {
+    let url = start_mock(MockConfig { mute: false, ..MockConfig::default() }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[CAPABILITY_ACK, CAPABILITY_POWER_DELAY]).await;
+
+    input_tx.unbounded_send(Command::SetMute(true)).unwrap();
+    assert!(matches!(next_event(&mut output_rx).await, Some(Event::Failed(Command::SetMute(true), _))));
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(50)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::GetVolume)));
+}
+
+#[tokio::test]
+async fn servers_without_acks_are_not_tracked() {
+    let url = start_mock(MockConfig { acks: false, mute: false, power_delay: false, ..MockConfig::default() }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[]).await;
+
+    input_tx.unbounded_send(Command::ToggleShuffle).unwrap();
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Shuffle(true)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(50)));
+
+    // well past the ack timeout, nothing is reported as timed out
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    drop(input_tx);
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn slow_server_times_out_after_retries() {
+    let faults = Faults {
+        reply_delay: Duration::from_millis(250),
+        ..Faults::default()
+    };
+    let url = start_mock(MockConfig { faults, ..MockConfig::default() }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[CAPABILITY_ACK, CAPABILITY_MUTE, CAPABILITY_POWER_DELAY]).await;
+
+    // not idempotent, so it times out without being resent
+    input_tx.unbounded_send(Command::NextTrack).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::TimedOut(Command::NextTrack)));
+}
+
+#[tokio::test]
+async fn idempotent_commands_are_retried() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::text("[capabilities](ack)")).await.unwrap();
+        // swallow the first attempt, answer the retry
+        let first = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let first = first.parse::<Request>().unwrap();
+        assert_eq!(first.command, Command::GetVolume);
+        let retry = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let retry = retry.parse::<Request>().unwrap();
+        assert_eq!(retry.command, Command::GetVolume);
+        assert_ne!(retry.id, first.id);
+        ws.send(Message::text("[volume](20)")).await.unwrap();
+        ws.send(Message::text(format!("[ack]({})", retry.id.unwrap()))).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[CAPABILITY_ACK]).await;
+
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(20)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::GetVolume)));
+}
+
+#[tokio::test]
+async fn rejected_commands_report_failed() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::text("[capabilities](ack)")).await.unwrap();
+        let request = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let request = request.parse::<Request>().unwrap();
+        ws.send(Message::text(format!("[error]({} not now)", request.id.unwrap()))).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, short_timeouts(), input_rx, output_tx));
+    expect_connected(&mut output_rx, &[CAPABILITY_ACK]).await;
+
+    input_tx.unbounded_send(Command::Reboot(Duration::ZERO)).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Failed(Command::Reboot(Duration::ZERO), "not now".to_string())));
+}
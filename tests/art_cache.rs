@@ -0,0 +1,63 @@
+mod common;
+
+use spotifypi_control_panel::art_cache::{is_downloadable, ArtCache};
+
+use common::temp_dir;
+
+
+#[test]
+fn images_survive_reopening() {
+    let dir = temp_dir("spotifypi-art-cache");
+    let mut cache = ArtCache::open(&dir, 1024).unwrap();
+    assert_eq!(cache.get("spotify:track:1"), None);
+    cache.insert("spotify:track:1", b"image one").unwrap();
+    cache.insert("spotify:track:1", b"image one, again").unwrap();
+    assert_eq!(cache.len(), 1);
+
+    let mut cache = ArtCache::open(&dir, 1024).unwrap();
+    assert_eq!(cache.get("spotify:track:1"), Some(b"image one, again".to_vec()));
+    assert_eq!(cache.total_bytes(), 16);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn least_recently_used_images_are_evicted() {
+    let dir = temp_dir("spotifypi-art-cache");
+    let mut cache = ArtCache::open(&dir, 30).unwrap();
+    cache.insert("a", &[0; 10]).unwrap();
+    cache.insert("b", &[1; 10]).unwrap();
+    cache.insert("c", &[2; 10]).unwrap();
+    // using "a" makes "b" the oldest
+    assert!(cache.get("a").is_some());
+    cache.insert("d", &[3; 10]).unwrap();
+
+    assert_eq!(cache.get("b"), None);
+    assert!(cache.get("a").is_some());
+    assert!(cache.get("c").is_some());
+    assert!(cache.get("d").is_some());
+    assert_eq!(cache.total_bytes(), 30);
+
+    // a smaller limit evicts on open
+    let cache = ArtCache::open(&dir, 20).unwrap();
+    assert_eq!(cache.len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn oversized_images_are_not_stored() {
+    let dir = temp_dir("spotifypi-art-cache");
+    let mut cache = ArtCache::open(&dir, 8).unwrap();
+    cache.insert("big", &[0; 9]).unwrap();
+    assert!(cache.is_empty());
+    assert_eq!(cache.get("big"), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_web_urls_are_downloaded() {
+    assert!(is_downloadable("https://i.scdn.co/image/ab67616d0000b273"));
+    assert!(is_downloadable("http://spotifypi.local/art/1.jpg"));
+    for url in ["file:///etc/passwd", "smb://nas/share/cover.jpg", "sftp://host/cover.jpg", "/etc/passwd", ""] {
+        assert!(!is_downloadable(url), "{}", url);
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use spotifypi_control_panel::auth::{nonce, Proof};
+use spotifypi_control_panel::client::{connect_to_ws_with_config, ClientConfig};
+use spotifypi_control_panel::mock::MockConfig;
+use spotifypi_control_panel::protocol::{
+    Command, ConnectStatus, Event, Request, CAPABILITY_ACK, CAPABILITY_MUTE, CAPABILITY_POWER_DELAY,
+};
+
+use common::{next_event, serve_once, start_mock};
+
+
+fn with_secret(secret: &str) -> MockConfig {
+    MockConfig {
+        secret: Some(secret.to_string()),
+        ..MockConfig::default()
+    }
+}
+
+fn connect(url: url::Url, secret: Option<&str>) -> (UnboundedSender<Command>, UnboundedReceiver<Event>) {
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        secret: secret.map(str::to_string),
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+    (input_tx, output_rx)
+}
+
+fn capabilities() -> Event {
+    Event::Capabilities(vec![
+        CAPABILITY_ACK.to_string(),
+        CAPABILITY_MUTE.to_string(),
+        CAPABILITY_POWER_DELAY.to_string(),
+    ])
+}
+
+#[tokio::test]
+async fn right_secret_opens_the_session() {
+    let url = start_mock(with_secret("hunter2")).await;
+    let (input_tx, mut output_rx) = connect(url, Some("hunter2"));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(capabilities()));
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(50)));
+}
+
+#[tokio::test]
+async fn wrong_secret_fails_authentication() {
+    let url = start_mock(with_secret("hunter2")).await;
+    let (_input_tx, mut output_rx) = connect(url, Some("*******"));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::AuthFailed)));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn missing_secret_fails_authentication() {
+    let url = start_mock(with_secret("hunter2")).await;
+    let (_input_tx, mut output_rx) = connect(url, None);
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::AuthFailed)));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn secret_is_not_needed_by_open_servers() {
+    let url = start_mock(MockConfig::default()).await;
+    let (_input_tx, mut output_rx) = connect(url, Some("hunter2"));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    // the frame read while waiting for a challenge isn't lost
+    assert_eq!(next_event(&mut output_rx).await, Some(capabilities()));
+}
+
+#[tokio::test]
+async fn late_challenge_ends_the_session() {
+    let url = serve_once(|mut ws| async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        ws.send(Message::text(Event::AuthChallenge(nonce()).to_string())).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        secret: Some("hunter2".to_string()),
+        challenge_wait: Duration::ZERO,
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+
+    // Connect and Disconnect still bracket the session
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[test]
+fn proofs() {
+    let nonce = nonce();
+    assert_eq!(nonce.len(), 32);
+    let proof = Proof::new("hunter2", &nonce);
+    assert!(proof.verify("hunter2", &nonce));
+    assert!(!proof.verify("hunter3", &nonce));
+    assert!(!proof.verify("hunter2", "another nonce"));
+
+    let request = Request::from(Command::Auth(proof));
+    assert_eq!(request.to_string().parse(), Ok(request));
+    assert!("auth 1234".parse::<Command>().is_err());
+    for event in [
+        Event::AuthChallenge(nonce),
+        Event::AuthResult(false),
+        Event::Connect(ConnectStatus::AuthFailed),
+    ] {
+        assert_eq!(event.to_string().parse(), Ok(event));
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use spotifypi_control_panel::backoff::Backoff;
+
+
+#[test]
+fn delay_grows_exponentially_up_to_max() {
+    let backoff = Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+        multiplier: 2.,
+        jitter: 0.,
+        max_attempts: None,
+    };
+
+    assert_eq!(backoff.delay(0), None);
+    assert_eq!(backoff.delay(1), Some(Duration::from_secs(1)));
+    assert_eq!(backoff.delay(2), Some(Duration::from_secs(2)));
+    assert_eq!(backoff.delay(4), Some(Duration::from_secs(8)));
+    assert_eq!(backoff.delay(5), Some(Duration::from_secs(10)));
+    assert_eq!(backoff.delay(u32::MAX), Some(Duration::from_secs(10)));
+}
+
+#[test]
+fn jitter_stays_within_bounds() {
+    let backoff = Backoff {
+        initial: Duration::from_secs(4),
+        max: Duration::from_secs(60),
+        jitter: 0.25,
+        ..Backoff::default()
+    };
+
+    for _ in 0..100 {
+        let delay = backoff.delay(1).unwrap();
+        assert!(delay >= Duration::from_secs(3) && delay <= Duration::from_secs(5), "{:?}", delay);
+    }
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let backoff = Backoff {
+        max_attempts: Some(3),
+        ..Backoff::default()
+    };
+
+    assert!(backoff.delay(3).is_some());
+    assert_eq!(backoff.delay(4), None);
+}
@@ -0,0 +1,129 @@
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedReceiver;
+use futures_util::StreamExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use zbus::connection;
+
+use spotifypi_control_panel::mock::{serve, MockConfig};
+use spotifypi_control_panel::protocol::Event;
+
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A fresh path under the temporary directory, unique to this test, for
+/// the test to create.
+pub fn temp_dir(prefix: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "{}-{}-{}",
+        prefix,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Accepts a single WebSocket connection on a random local port and hands
+/// it to `handler`. Returns the url to connect to.
+pub async fn serve_once<F, Fut>(handler: F) -> url::Url
+where
+    F: FnOnce(WebSocketStream<TcpStream>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let ws_stream = accept_async(stream).await.unwrap();
+        handler(ws_stream).await;
+    });
+    url::Url::parse(&format!("ws://{}", addr)).unwrap()
+}
+
+/// Runs the mock server on a random local port.
+pub async fn start_mock(config: MockConfig) -> url::Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, config));
+    url::Url::parse(&format!("ws://{}", addr)).unwrap()
+}
+
+/// Url of a local port nothing is listening on.
+pub async fn refused_url() -> url::Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    url::Url::parse(&format!("ws://{}", addr)).unwrap()
+}
+
+pub async fn next_event(output_rx: &mut UnboundedReceiver<Event>) -> Option<Event> {
+    tokio::time::timeout(TIMEOUT, output_rx.next()).await.expect("timed out waiting for an event")
+}
+
+/// A private bus, stopped on drop.
+pub struct Bus {
+    daemon: Child,
+    dir: PathBuf,
+    pub address: String,
+}
+
+impl Bus {
+    pub fn builder(&self) -> connection::Builder<'_> {
+        connection::Builder::address(self.address.as_str()).unwrap()
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Starts a dbus-daemon of its own, `None` when there is none to run.
+pub fn start_bus() -> Option<Bus> {
+    let dir = temp_dir("spotifypi-bus");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("bus.conf");
+    std::fs::write(&config, format!(r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#, dir.join("bus").display())).unwrap();
+
+    let daemon = std::process::Command::new("dbus-daemon")
+        .arg(format!("--config-file={}", config.display()))
+        .args(["--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut daemon = match daemon {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            eprintln!("skipped, could not run dbus-daemon: {}", e);
+            let _ = std::fs::remove_dir_all(&dir);
+            return None;
+        }
+    };
+    let mut address = String::new();
+    BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    Some(Bus { daemon, dir, address: address.trim().to_string() })
+}
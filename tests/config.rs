@@ -0,0 +1,97 @@
+mod common;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use spotifypi_control_panel::backoff::Backoff;
+use spotifypi_control_panel::client::ClientConfig;
+use spotifypi_control_panel::config::Config;
+
+use common::temp_dir;
+
+
+fn config_path() -> PathBuf {
+    temp_dir("spotifypi-config").join("config.toml")
+}
+
+#[test]
+fn missing_file_gives_the_built_in_defaults() {
+    let config = Config::load(&config_path()).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.client_config(), ClientConfig::default());
+    assert_eq!(config.backoff(), Backoff::default());
+}
+
+#[test]
+fn partial_files_keep_the_other_defaults() {
+    let path = config_path();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, "\
+[reconnect]
+auto = true
+max_attempts = 5
+
+[timeouts]
+heartbeat_interval = 0
+volume_coalesce_ms = 250
+challenge_wait_ms = 0
+").unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert!(config.reconnect.auto);
+    assert_eq!(config.backoff().max_attempts, Some(5));
+    assert_eq!(config.backoff().initial, Backoff::default().initial);
+    let client_config = config.client_config();
+    assert_eq!(client_config.heartbeat, None);
+    assert_eq!(client_config.volume_coalesce, Duration::from_millis(250));
+    assert_eq!(client_config.challenge_wait, Duration::ZERO);
+    assert_eq!(client_config.ack_timeout, ClientConfig::default().ack_timeout);
+
+    config.save(&path).unwrap();
+    assert_eq!(Config::load(&path).unwrap(), config);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    let path = config_path();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    for text in [
+        "[timeouts]\nack_timeout = -1",
+        "[reconnect]\njitter = 2.0",
+        "[window]\nwidth = \"wide\"",
+        "[notifications]\ntrack_interval = -5",
+        "[timeouts]\nheartbeat_timeout = 0",
+        "[reconnect]\ninitial_delay = 30.0\nmax_delay = 10.0",
+    ] {
+        std::fs::write(&path, text).unwrap();
+        let e = Config::load(&path).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{}", text);
+    }
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn shortcuts_can_be_remapped() {
+    let path = config_path();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, "\
+[shortcuts]
+next-track = [\"<Primary>n\"]
+mute = []
+").unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.accels("next-track"), vec!["<Primary>n".to_string()]);
+    assert!(config.accels("mute").is_empty());
+    assert_eq!(config.accels("volume-up"), vec!["<Primary>Up".to_string(), "XF86AudioRaiseVolume".to_string()]);
+
+    config.save(&path).unwrap();
+    assert_eq!(Config::load(&path).unwrap(), config);
+
+    std::fs::write(&path, "[shortcuts]\nself-destruct = [\"<Primary>d\"]").unwrap();
+    let e = Config::load(&path).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    assert!(e.to_string().contains("self-destruct"), "{}", e);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,382 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::unbounded;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use spotifypi_control_panel::client::{connect_to_ws, connect_to_ws_with_config, ClientConfig, Heartbeat};
+use spotifypi_control_panel::mock::MockConfig;
+use spotifypi_control_panel::protocol::{art_frame, Command, ConnectStatus, Event, Position, RepeatState, Track};
+
+use common::{next_event, refused_url, serve_once, start_mock};
+
+
+#[tokio::test]
+async fn handshake_then_commands_and_events() {
+    let url = serve_once(|mut ws| async move {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("get_volume"));
+        ws.send(Message::text("[volume](40)")).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("set_volume 12"));
+        ws.send(Message::text("[volume](12)")).await.unwrap();
+        // wait for the client to close
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(40)));
+    input_tx.unbounded_send(Command::SetVolume(12)).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(12)));
+
+    drop(input_tx);
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn refused_port_reports_connect_failed() {
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(refused_url().await, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Failed)));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn server_close_reports_disconnect() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::text("[volume](7)")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(7)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn dropped_socket_reports_disconnect() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::text("[volume](3)")).await.unwrap();
+        // drop the TCP stream without a closing handshake
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(3)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+    assert_eq!(next_event(&mut output_rx).await, None);
+}
+
+#[tokio::test]
+async fn malformed_frames_are_skipped() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::text("garbage")).await.unwrap();
+        ws.send(Message::text("[bogus](1)")).await.unwrap();
+        ws.send(Message::text("[volume](loud)")).await.unwrap();
+        ws.send(Message::text("[volume](101)")).await.unwrap();
+        ws.send(Message::text("[volume](25)")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(25)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn track_frames_are_unescaped() {
+    let url = serve_once(|mut ws| async move {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("get_now_playing"));
+        ws.send(Message::text(r"[track](spotify:track:1|Half \| Open|Back\\slash|Album|187000)")).await.unwrap();
+        ws.send(Message::text("[track](spotify:track:1|missing fields)")).await.unwrap();
+        ws.send(Message::text("[track]()")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    input_tx.unbounded_send(Command::GetNowPlaying).unwrap();
+    let track = Track {
+        id: "spotify:track:1".to_string(),
+        title: "Half | Open".to_string(),
+        artist: "Back\\slash".to_string(),
+        album: "Album".to_string(),
+        duration: Duration::from_millis(187_000),
+    };
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Track(Some(track))));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Track(None)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn binary_frames() {
+    let url = serve_once(|mut ws| async move {
+        ws.send(Message::binary(vec![0xff, 0xfe, 0xfd])).await.unwrap();
+        ws.send(Message::binary(b"[volume](60)".to_vec())).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    // non-UTF-8 payloads are dropped, UTF-8 ones are parsed like text frames
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(60)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn volume_changes_are_coalesced() {
+    let (received_tx, mut received_rx) = unbounded();
+    let url = serve_once(move |mut ws| async move {
+        while let Some(Ok(Message::Text(text))) = ws.next().await {
+            received_tx.unbounded_send(text).unwrap();
+        }
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        volume_coalesce: Duration::from_millis(200),
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+
+    // the first change goes out right away, the rest of the burst is reduced
+    // to its latest value once the window is over
+    for volume in 1..=10 {
+        input_tx.unbounded_send(Command::SetVolume(volume)).unwrap();
+    }
+    assert_eq!(received_rx.next().await.as_deref(), Some("set_volume 1"));
+    assert_eq!(received_rx.next().await.as_deref(), Some("set_volume 10"));
+
+    // other commands don't overtake a value held back
+    input_tx.unbounded_send(Command::SetVolume(11)).unwrap();
+    input_tx.unbounded_send(Command::SetVolume(12)).unwrap();
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(received_rx.next().await.as_deref(), Some("set_volume 12"));
+    assert_eq!(received_rx.next().await.as_deref(), Some("get_volume"));
+
+    // a value still held back is flushed before closing
+    input_tx.unbounded_send(Command::SetVolume(20)).unwrap();
+    input_tx.unbounded_send(Command::SetVolume(30)).unwrap();
+    drop(input_tx);
+    assert_eq!(received_rx.next().await.as_deref(), Some("set_volume 30"));
+    assert_eq!(received_rx.next().await, None);
+}
+
+#[tokio::test]
+async fn mute_after_a_volume_change_sticks() {
+    let url = start_mock(MockConfig::default()).await;
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        volume_coalesce: Duration::from_millis(200),
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+
+    // changing the volume unmutes, so it has to reach the Pi first
+    input_tx.unbounded_send(Command::SetVolume(20)).unwrap();
+    input_tx.unbounded_send(Command::SetVolume(30)).unwrap();
+    input_tx.unbounded_send(Command::SetMute(true)).unwrap();
+    // asking once anything held back must have gone out
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    let mut muted = None;
+    loop {
+        match next_event(&mut output_rx).await {
+            Some(Event::Muted(state)) => muted = Some(state),
+            Some(Event::Done(Command::GetVolume)) => break,
+            Some(_) => {}
+            None => panic!("disconnected"),
+        }
+    }
+    assert_eq!(muted, Some(true));
+}
+
+#[tokio::test]
+async fn playback_state_events() {
+    let url = serve_once(|mut ws| async move {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("get_playback_state"));
+        ws.send(Message::text("[playing](true)")).await.unwrap();
+        ws.send(Message::text("[shuffle](yes)")).await.unwrap();
+        ws.send(Message::text("[shuffle](false)")).await.unwrap();
+        ws.send(Message::text("[repeat](album)")).await.unwrap();
+        ws.send(Message::text("[repeat](context)")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    input_tx.unbounded_send(Command::GetPlaybackState).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Playing(true)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Shuffle(false)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Repeat(RepeatState::Context)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn seek_and_position() {
+    let url = serve_once(|mut ws| async move {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("seek 61000"));
+        ws.send(Message::text("[position](61000|215000|playing)")).await.unwrap();
+        ws.send(Message::text("[position](61000|215000|stopped)")).await.unwrap();
+        ws.send(Message::text("[position](0|0|paused)")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    input_tx.unbounded_send(Command::Seek(Duration::from_secs(61))).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Position(Position {
+        position: Duration::from_secs(61),
+        duration: Duration::from_secs(215),
+        playing: true,
+    })));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Position(Position {
+        position: Duration::ZERO,
+        duration: Duration::ZERO,
+        playing: false,
+    })));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn art_frames() {
+    let url = serve_once(|mut ws| async move {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::text("get_art"));
+        ws.send(Message::binary(art_frame("spotify:track:1", &[0x89, b'P', b'N', b'G', b'\n', 0xff]))).await.unwrap();
+        ws.send(Message::text("[art](spotify:track:2|https://i.scdn.co/image/ab67616d)")).await.unwrap();
+        ws.close(None).await.unwrap();
+    }).await;
+
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    input_tx.unbounded_send(Command::GetArt).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::ArtImage {
+        track_id: "spotify:track:1".to_string(),
+        data: vec![0x89, b'P', b'N', b'G', b'\n', 0xff],
+    }));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::ArtUrl {
+        track_id: "spotify:track:2".to_string(),
+        url: "https://i.scdn.co/image/ab67616d".to_string(),
+    }));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+fn fast_heartbeat() -> ClientConfig {
+    ClientConfig {
+        heartbeat: Some(Heartbeat {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(200),
+        }),
+        ..ClientConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn heartbeat_reports_latency() {
+    let url = serve_once(|mut ws| async move {
+        // reading is what answers the pings
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, fast_heartbeat(), input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    for _ in 0..2 {
+        match next_event(&mut output_rx).await {
+            Some(Event::Latency(latency)) => assert!(latency < Duration::from_millis(200)),
+            other => panic!("expected latency, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn missing_pong_reports_disconnect() {
+    let url = serve_once(|ws| async move {
+        // hold the socket open without ever reading, so no pong goes out
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        drop(ws);
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_config(url, fast_heartbeat(), input_rx, output_tx));
+
+    let started = Instant::now();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn client_events_from_the_server_are_dropped() {
+    let url = serve_once(|mut ws| async move {
+        for event in [
+            Event::Connect(ConnectStatus::Failed),
+            Event::Disconnect,
+            Event::Latency(Duration::from_millis(5)),
+            Event::Done(Command::NextTrack),
+            Event::Failed(Command::NextTrack, "nope".to_string()),
+            Event::TimedOut(Command::NextTrack),
+            Event::Volume(7),
+        ] {
+            ws.send(Message::text(event.to_string())).await.unwrap();
+        }
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(connect_to_ws(url, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(7)));
+}
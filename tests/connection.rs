@@ -0,0 +1,100 @@
+mod common;
+
+use futures::channel::mpsc::unbounded;
+use futures_util::StreamExt;
+
+use spotifypi_control_panel::client::{connect_to_ws_with_progress, ClientConfig};
+use spotifypi_control_panel::connection::{ConnectPhase, ConnectionState, Input};
+use spotifypi_control_panel::mock::MockConfig;
+use spotifypi_control_panel::protocol::{ConnectStatus, Event};
+
+use common::{next_event, refused_url, start_mock};
+
+
+fn run(inputs: Vec<Input>) -> Option<ConnectionState> {
+    inputs.into_iter().try_fold(ConnectionState::Idle, |state, input| state.next(input))
+}
+
+#[test]
+fn connects_through_each_phase_and_closes() {
+    let mut state = ConnectionState::Idle;
+    for (input, expected) in [
+        (Input::Start, ConnectionState::Resolving),
+        (Input::Phase(ConnectPhase::Connecting), ConnectionState::Connecting),
+        (Input::Phase(ConnectPhase::Authenticating), ConnectionState::Authenticating),
+        (Input::Connected, ConnectionState::Connected),
+        (Input::Close, ConnectionState::Disconnecting),
+        (Input::Closed, ConnectionState::Idle),
+    ] {
+        state = state.next(input).unwrap();
+        assert_eq!(state, expected);
+    }
+}
+
+#[test]
+fn failures_back_off_and_retry() {
+    let failed = ConnectionState::Failed("connect failed".to_string());
+    assert_eq!(run(vec![Input::Start, Input::Failed("connect failed".to_string())]), Some(failed.clone()));
+    assert_eq!(failed.next(Input::BackOff), Some(ConnectionState::Backoff));
+    assert_eq!(ConnectionState::Backoff.next(Input::Start), Some(ConnectionState::Resolving));
+    assert_eq!(ConnectionState::Backoff.next(Input::Reset), Some(ConnectionState::Idle));
+    assert_eq!(failed.next(Input::Start), Some(ConnectionState::Resolving));
+}
+
+#[test]
+fn late_inputs_are_ignored() {
+    // a failure racing a disconnect
+    let disconnecting = run(vec![Input::Start, Input::Connected, Input::Close]).unwrap();
+    assert_eq!(disconnecting.next(Input::Failed("connect failed".to_string())), None);
+    assert_eq!(disconnecting.next(Input::Connected), None);
+    // one connect at a time
+    assert_eq!(ConnectionState::Connecting.next(Input::Start), None);
+    assert_eq!(ConnectionState::Connected.next(Input::Phase(ConnectPhase::Authenticating)), None);
+    assert_eq!(ConnectionState::Idle.next(Input::Closed), None);
+    assert_eq!(ConnectionState::Idle.next(Input::BackOff), None);
+}
+
+#[test]
+fn cancelling_a_connect_closes_it() {
+    assert_eq!(
+        run(vec![Input::Start, Input::Phase(ConnectPhase::Connecting), Input::Close, Input::Closed]),
+        Some(ConnectionState::Idle)
+    );
+    assert!(ConnectionState::Authenticating.is_connecting());
+    assert!(ConnectionState::Disconnecting.is_active());
+    assert!(!ConnectionState::Backoff.is_active());
+}
+
+#[tokio::test]
+async fn client_reports_each_phase() {
+    let url = start_mock(MockConfig {
+        secret: Some("hunter2".to_string()),
+        ..MockConfig::default()
+    }).await;
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let (progress_tx, progress_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        secret: Some("hunter2".to_string()),
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_progress(url, config, input_rx, output_tx, progress_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    let phases: Vec<ConnectPhase> = progress_rx.take(3).collect().await;
+    assert_eq!(phases, [ConnectPhase::Resolving, ConnectPhase::Connecting, ConnectPhase::Authenticating]);
+}
+
+#[tokio::test]
+async fn refused_connect_stops_after_connecting() {
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let (progress_tx, progress_rx) = unbounded();
+    tokio::spawn(connect_to_ws_with_progress(refused_url().await, ClientConfig::default(), input_rx, output_tx, progress_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Failed)));
+    assert_eq!(next_event(&mut output_rx).await, None);
+    let phases: Vec<ConnectPhase> = progress_rx.collect().await;
+    assert_eq!(phases, [ConnectPhase::Resolving, ConnectPhase::Connecting]);
+}
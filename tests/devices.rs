@@ -0,0 +1,107 @@
+mod common;
+
+use std::path::PathBuf;
+
+use spotifypi_control_panel::client::DEFAULT_ADDR;
+use spotifypi_control_panel::devices::{Device, DeviceList, TrustMode};
+use spotifypi_control_panel::tls::{Fingerprint, Trust};
+
+use common::temp_dir;
+
+
+fn devices_path() -> PathBuf {
+    temp_dir("spotifypi-devices").join("devices.toml")
+}
+
+fn device(name: &str, addr: &str) -> Device {
+    Device {
+        name: name.to_string(),
+        addr: addr.to_string(),
+        ..Device::default()
+    }
+}
+
+#[test]
+fn missing_file_gives_default_device() {
+    let devices = DeviceList::load(&devices_path()).unwrap();
+    assert_eq!(devices.devices.len(), 1);
+    assert_eq!(devices.devices[0].addr, DEFAULT_ADDR);
+    assert_eq!(devices.selected, None);
+}
+
+#[test]
+fn devices_survive_saving() {
+    let path = devices_path();
+    let mut devices = DeviceList::default();
+    devices.upsert(None, device("Kitchen", "192.168.1.20:9487"));
+    devices.selected = Some("Kitchen".to_string());
+    devices.save(&path).unwrap();
+
+    let loaded = DeviceList::load(&path).unwrap();
+    assert_eq!(loaded, devices);
+    assert_eq!(loaded.get("Kitchen"), Some(&device("Kitchen", "192.168.1.20:9487")));
+
+    std::fs::write(&path, "device = 3").unwrap();
+    assert_eq!(DeviceList::load(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn saved_list_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = devices_path();
+    let mut devices = DeviceList::default();
+    devices.upsert(None, Device {
+        secret: Some("hunter2".to_string()),
+        ..device("Kitchen", "192.168.1.20:9487")
+    });
+    devices.save(&path).unwrap();
+    devices.save(&path).unwrap();
+
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert!(!path.with_extension("toml.tmp").exists());
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn renaming_keeps_the_selection() {
+    let mut devices = DeviceList::default();
+    devices.upsert(None, device("Kitchen", "kitchen.local:9487"));
+    devices.selected = Some("Kitchen".to_string());
+
+    devices.upsert(Some("Kitchen"), device("Living room", "kitchen.local:9487"));
+    assert_eq!(devices.selected.as_deref(), Some("Living room"));
+    assert_eq!(devices.get("Kitchen"), None);
+    assert_eq!(devices.devices.len(), 2);
+
+    assert!(devices.remove("Living room"));
+    assert!(!devices.remove("Living room"));
+    assert_eq!(devices.selected, None);
+}
+
+#[test]
+fn certificate_trust_survives_saving() {
+    let path = devices_path();
+    let fingerprint = Fingerprint::of(b"certificate");
+    let mut devices = DeviceList::default();
+    devices.upsert(None, Device {
+        trust: TrustMode::Pin,
+        fingerprint: Some(fingerprint),
+        ..device("Kitchen", "wss://kitchen.local:9487")
+    });
+    devices.upsert(None, Device {
+        trust: TrustMode::CaFile,
+        ..device("Attic", "wss://attic.local:9487")
+    });
+    devices.save(&path).unwrap();
+
+    let loaded = DeviceList::load(&path).unwrap();
+    assert_eq!(loaded, devices);
+    assert_eq!(loaded.get("Kitchen").unwrap().tls_trust(), Ok(Trust::Pinned(Some(fingerprint))));
+    assert_eq!(loaded.get("SpotifyPi").unwrap().tls_trust(), Ok(Trust::System));
+    // a CA file must be picked
+    assert!(loaded.get("Attic").unwrap().tls_trust().is_err());
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures_util::StreamExt;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use spotifypi_control_panel::discovery::{
+    browse, browse_query_id, service_reply, DiscoveryConfig, DiscoveryEvent, Service,
+};
+use spotifypi_control_panel::mock::{advertise, Advertisement};
+
+use common::TIMEOUT;
+
+
+async fn next_discovery(output_rx: &mut UnboundedReceiver<DiscoveryEvent>) -> Option<DiscoveryEvent> {
+    timeout(TIMEOUT, output_rx.next()).await.expect("timed out waiting for a discovery event")
+}
+
+fn browse_config(query_addr: SocketAddr) -> DiscoveryConfig {
+    DiscoveryConfig {
+        query_addr,
+        ..DiscoveryConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn mock_is_found_then_lost_when_its_records_expire() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let query_addr = socket.local_addr().unwrap();
+    let mut advertisement = Advertisement::new("Living Room", "127.0.0.1:9487".parse().unwrap());
+    advertisement.ttl = 1;
+    let responder = tokio::spawn(advertise(socket, advertisement));
+
+    let (output_tx, mut output_rx) = unbounded();
+    tokio::spawn(browse(browse_config(query_addr), output_tx));
+
+    let service = Service {
+        name: "Living Room".to_string(),
+        host: "spotifypi-mock.local".to_string(),
+        port: 9487,
+        ip: Some(Ipv4Addr::LOCALHOST),
+    };
+    assert_eq!(service.addr(), "127.0.0.1:9487");
+    assert_eq!(next_discovery(&mut output_rx).await, Some(DiscoveryEvent::Found(service)));
+
+    // no answer to the next query, so the records run out
+    responder.abort();
+    assert_eq!(next_discovery(&mut output_rx).await, Some(DiscoveryEvent::Lost("Living Room".to_string())));
+}
+
+#[tokio::test]
+async fn goodbye_packets_and_other_services() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let query_addr = socket.local_addr().unwrap();
+
+    let (output_tx, mut output_rx) = unbounded();
+    let config = DiscoveryConfig {
+        service_type: "_kitchen._tcp".to_string(),
+        ..browse_config(query_addr)
+    };
+    tokio::spawn(browse(config, output_tx));
+
+    let mut buf = vec![0; 9000];
+    let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(browse_query_id(&buf[..len], "_spotifypi._tcp.local"), None);
+    let id = browse_query_id(&buf[..len], "_kitchen._tcp.local").unwrap();
+
+    let other = service_reply(id, "_other._tcp", "Other", "other.local", 1, None, 120).unwrap();
+    socket.send_to(&other, peer).await.unwrap();
+    let kitchen = service_reply(id, "_kitchen._tcp.local", "Pi", "Kitchen-Pi.local.", 9487, None, 120).unwrap();
+    socket.send_to(&kitchen, peer).await.unwrap();
+
+    let service = Service {
+        name: "Pi".to_string(),
+        host: "kitchen-pi.local".to_string(),
+        port: 9487,
+        ip: None,
+    };
+    assert_eq!(service.addr(), "kitchen-pi.local:9487");
+    assert_eq!(next_discovery(&mut output_rx).await, Some(DiscoveryEvent::Found(service)));
+
+    let goodbye = service_reply(id, "_kitchen._tcp.local", "Pi", "kitchen-pi.local", 9487, None, 0).unwrap();
+    socket.send_to(&goodbye, peer).await.unwrap();
+    assert_eq!(next_discovery(&mut output_rx).await, Some(DiscoveryEvent::Lost("Pi".to_string())));
+}
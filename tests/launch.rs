@@ -0,0 +1,33 @@
+use spotifypi_control_panel::launch::{parse_args, LaunchOptions};
+
+
+fn parse(args: &[&str]) -> Result<Option<LaunchOptions>, String> {
+    parse_args(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn options_and_actions_are_parsed() {
+    let options = parse(&["--device", "kitchen", "--next", "--volume-up", "--prev"]).unwrap().unwrap();
+    assert_eq!(options.device.as_deref(), Some("kitchen"));
+    assert_eq!(options.actions, ["next-track", "volume-up", "prev-track"]);
+    assert!(options.wants_connection());
+
+    let options = parse(&["--connect", "wss://pi.local:9487", "--minimized"]).unwrap().unwrap();
+    assert_eq!(options.connect.as_deref(), Some("wss://pi.local:9487"));
+    assert!(options.minimized);
+    assert!(options.actions.is_empty());
+
+    // just bringing up the window
+    assert!(!parse(&[]).unwrap().unwrap().wants_connection());
+    assert!(!parse(&["--device", "kitchen"]).unwrap().unwrap().wants_connection());
+    assert!(parse(&["--autoconnect"]).unwrap().unwrap().wants_connection());
+}
+
+#[test]
+fn bad_arguments_are_rejected() {
+    assert_eq!(parse(&["--next", "-h"]), Ok(None));
+    assert!(parse(&["--device"]).is_err());
+    assert!(parse(&["--connect"]).is_err());
+    assert!(parse(&["--skip"]).is_err());
+    assert!(parse(&["next-track"]).is_err());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures_util::StreamExt;
+use zbus::fdo::PropertiesProxy;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::Proxy;
+
+use spotifypi_control_panel::mpris::{Mpris, BUS_NAME, OBJECT_PATH};
+use spotifypi_control_panel::protocol::{Command, ConnectStatus, Event, RepeatState, Track};
+
+use common::start_bus;
+
+
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+async fn next_command(commands: &mut UnboundedReceiver<Command>) -> Command {
+    tokio::time::timeout(Duration::from_secs(5), commands.next()).await.unwrap().unwrap()
+}
+
+async fn get(properties: &PropertiesProxy<'_>, name: &str) -> OwnedValue {
+    properties.get(InterfaceName::from_static_str_unchecked(PLAYER), name).await.unwrap()
+}
+
+#[tokio::test]
+async fn desktop_calls_become_commands_and_events_become_properties() {
+    let bus = match start_bus() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let (commands_tx, mut commands_rx) = unbounded();
+    let mpris = Mpris::start(bus.builder(), commands_tx).await.unwrap();
+
+    let desktop = bus.builder().build().await.unwrap();
+    let player = Proxy::new(&desktop, BUS_NAME, OBJECT_PATH, PLAYER).await.unwrap();
+    let properties = PropertiesProxy::builder(&desktop).destination(BUS_NAME).unwrap()
+        .path(OBJECT_PATH).unwrap()
+        .build().await.unwrap();
+
+    // nothing to control until connected
+    assert_eq!(get(&properties, "PlaybackStatus").await, OwnedValue::from(zbus::zvariant::Str::from("Stopped")));
+    assert!(player.call_method("PlayPause", &()).await.is_err());
+
+    let track = Track {
+        id: "spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+        title: "Never Gonna Give You Up".to_string(),
+        artist: "Rick Astley".to_string(),
+        album: "Whenever You Need Somebody".to_string(),
+        duration: Duration::from_secs(213),
+    };
+    for event in [
+        Event::Connect(ConnectStatus::Ok),
+        Event::Track(Some(track)),
+        Event::Playing(true),
+        Event::Volume(40),
+        Event::Repeat(RepeatState::Track),
+    ] {
+        mpris.update(&event).await.unwrap();
+    }
+
+    assert_eq!(get(&properties, "PlaybackStatus").await, OwnedValue::from(zbus::zvariant::Str::from("Playing")));
+    assert_eq!(get(&properties, "Volume").await, OwnedValue::from(0.4));
+    assert_eq!(get(&properties, "LoopStatus").await, OwnedValue::from(zbus::zvariant::Str::from("Track")));
+    let metadata: HashMap<String, OwnedValue> = get(&properties, "Metadata").await.try_into().unwrap();
+    assert_eq!(metadata["xesam:title"], OwnedValue::from(zbus::zvariant::Str::from("Never Gonna Give You Up")));
+    assert_eq!(metadata["mpris:length"], OwnedValue::from(213_000_000i64));
+
+    player.call_method("PlayPause", &()).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::TogglePlayPause);
+    player.call_method("Next", &()).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::NextTrack);
+    player.call_method("Previous", &()).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::PrevTrack);
+
+    let set = |name: &'static str, value: Value<'static>| {
+        let properties = &properties;
+        async move { properties.set(InterfaceName::from_static_str_unchecked(PLAYER), name, value).await }
+    };
+    set("Volume", Value::from(0.55)).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::SetVolume(55));
+    set("Shuffle", Value::from(true)).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::ToggleShuffle);
+    // Track -> Playlist -> None takes two steps of the Pi's cycle
+    set("LoopStatus", Value::from("None")).await.unwrap();
+    assert_eq!(next_command(&mut commands_rx).await, Command::ToggleRepeatState);
+    assert_eq!(next_command(&mut commands_rx).await, Command::ToggleRepeatState);
+
+    // losing the Pi empties the player
+    mpris.update(&Event::Disconnect).await.unwrap();
+    assert_eq!(get(&properties, "PlaybackStatus").await, OwnedValue::from(zbus::zvariant::Str::from("Stopped")));
+    let metadata: HashMap<String, OwnedValue> = get(&properties, "Metadata").await.try_into().unwrap();
+    assert!(!metadata.contains_key("xesam:title"));
+}
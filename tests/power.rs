@@ -0,0 +1,156 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use spotifypi_control_panel::client::{connect_to_ws_with_config, ClientConfig};
+use spotifypi_control_panel::mock::MockConfig;
+use spotifypi_control_panel::protocol::{Command, ConnectStatus, Event, PowerAction, Request, CAPABILITY_POWER_DELAY};
+
+use common::{next_event, start_mock};
+
+
+/// Connects and skips the events every session starts with.
+async fn connect(url: &url::Url) -> (UnboundedSender<Command>, UnboundedReceiver<Event>) {
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url.clone(), config, input_rx, output_tx));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert!(matches!(next_event(&mut output_rx).await, Some(Event::Capabilities(_))));
+    (input_tx, output_rx)
+}
+
+#[tokio::test]
+async fn delayed_shutdown_is_announced_and_can_be_cancelled() {
+    let url = start_mock(MockConfig::default()).await;
+    let (input_tx, mut output_rx) = connect(&url).await;
+    let (_other_tx, mut other_rx) = connect(&url).await;
+
+    let shutdown = Command::Shutdown(Duration::from_secs(60));
+    input_tx.unbounded_send(shutdown).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(shutdown)));
+    let scheduled = Event::PowerScheduled { action: PowerAction::Shutdown, delay: Duration::from_secs(60) };
+    assert_eq!(next_event(&mut output_rx).await, Some(scheduled.clone()));
+    assert_eq!(next_event(&mut other_rx).await, Some(scheduled));
+
+    // clients connecting meanwhile learn about it too
+    let (_late_tx, mut late_rx) = connect(&url).await;
+    match next_event(&mut late_rx).await {
+        Some(Event::PowerScheduled { action: PowerAction::Shutdown, delay }) => {
+            assert!(delay <= Duration::from_secs(60));
+        }
+        event => panic!("expected the scheduled shutdown, got {:?}", event),
+    }
+
+    input_tx.unbounded_send(Command::CancelPower).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::CancelPower)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::PowerCancelled));
+    assert_eq!(next_event(&mut other_rx).await, Some(Event::PowerCancelled));
+    assert_eq!(next_event(&mut late_rx).await, Some(Event::PowerCancelled));
+
+    // still up, and nothing pending any more
+    let (input_tx, mut output_rx) = connect(&url).await;
+    input_tx.unbounded_send(Command::GetVolume).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(50)));
+}
+
+#[tokio::test]
+async fn delayed_reboot_drops_the_connection() {
+    let url = start_mock(MockConfig { reboot_delay: Duration::from_millis(100), ..MockConfig::default() }).await;
+    let (input_tx, mut output_rx) = connect(&url).await;
+
+    let reboot = Command::Reboot(Duration::from_millis(200));
+    input_tx.unbounded_send(reboot).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(reboot)));
+    assert_eq!(
+        next_event(&mut output_rx).await,
+        Some(Event::PowerScheduled { action: PowerAction::Reboot, delay: Duration::from_millis(200) }),
+    );
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn rescheduling_replaces_the_pending_one() {
+    let url = start_mock(MockConfig { reboot_delay: Duration::from_millis(100), ..MockConfig::default() }).await;
+    let (input_tx, mut output_rx) = connect(&url).await;
+
+    let reboot = Command::Reboot(Duration::from_millis(200));
+    input_tx.unbounded_send(reboot).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(reboot)));
+    assert!(matches!(next_event(&mut output_rx).await, Some(Event::PowerScheduled { .. })));
+    let shutdown = Command::Shutdown(Duration::from_secs(60));
+    input_tx.unbounded_send(shutdown).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(shutdown)));
+    assert!(matches!(
+        next_event(&mut output_rx).await,
+        Some(Event::PowerScheduled { action: PowerAction::Shutdown, .. })
+    ));
+
+    // the reboot doesn't happen, and the shutdown is still pending
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    input_tx.unbounded_send(Command::CancelPower).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::CancelPower)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::PowerCancelled));
+}
+
+#[tokio::test]
+async fn delays_are_rejected_when_unsupported() {
+    let url = start_mock(MockConfig { power_delay: false, ..MockConfig::default() }).await;
+    let (input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig { heartbeat: None, ..ClientConfig::default() };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    match next_event(&mut output_rx).await {
+        Some(Event::Capabilities(capabilities)) => assert!(!capabilities.iter().any(|c| c == CAPABILITY_POWER_DELAY)),
+        event => panic!("expected the capabilities, got {:?}", event),
+    }
+
+    let shutdown = Command::Shutdown(Duration::from_secs(60));
+    input_tx.unbounded_send(shutdown).unwrap();
+    assert!(matches!(next_event(&mut output_rx).await, Some(Event::Failed(cmd, _)) if cmd == shutdown));
+    input_tx.unbounded_send(Command::CancelPower).unwrap();
+    assert!(matches!(next_event(&mut output_rx).await, Some(Event::Failed(Command::CancelPower, _))));
+
+    // going down right away needs no capability
+    let reboot = Command::Reboot(Duration::ZERO);
+    input_tx.unbounded_send(reboot).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(reboot)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Disconnect));
+}
+
+#[tokio::test]
+async fn cancelling_nothing_is_answered() {
+    let url = start_mock(MockConfig::default()).await;
+    let (input_tx, mut output_rx) = connect(&url).await;
+
+    input_tx.unbounded_send(Command::CancelPower).unwrap();
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::PowerCancelled));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Done(Command::CancelPower)));
+}
+
+#[test]
+fn power_commands_and_events() {
+    // servers that can't delay keep getting the bare command
+    assert_eq!(Command::Shutdown(Duration::ZERO).to_string(), "shutdown");
+    assert_eq!(Command::Reboot(Duration::from_secs(300)).to_string(), "reboot 300000");
+    assert_eq!("reboot".parse(), Ok(Command::Reboot(Duration::ZERO)));
+    assert!("shutdown soon".parse::<Command>().is_err());
+
+    for cmd in [Command::Shutdown(Duration::from_millis(1500)), Command::CancelPower] {
+        let request = Request::from(cmd);
+        assert_eq!(request.to_string().parse(), Ok(request));
+    }
+    for event in [
+        Event::PowerScheduled { action: PowerAction::Reboot, delay: Duration::from_secs(60) },
+        Event::PowerCancelled,
+    ] {
+        assert_eq!(event.to_string().parse(), Ok(event));
+    }
+    assert!("[power_scheduled](halt|1000)".parse::<Event>().is_err());
+}
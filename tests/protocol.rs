@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use spotifypi_control_panel::auth::{nonce, Proof};
+use spotifypi_control_panel::protocol::{
+    art_frame, parse_art_frame, split_fields, Command, ConnectStatus, Event, ParseError, Position, PowerAction,
+    RepeatState, Request, Track,
+};
+use spotifypi_control_panel::tls::Fingerprint;
+
+
+fn commands() -> Vec<Command> {
+    vec![
+        Command::PrevTrack,
+        Command::TogglePlayPause,
+        Command::NextTrack,
+        Command::ToggleShuffle,
+        Command::ToggleRepeatState,
+        Command::GetVolume,
+        Command::SetVolume(0),
+        Command::SetVolume(100),
+        Command::SetMute(true),
+        Command::SetMute(false),
+        Command::GetPlaybackState,
+        Command::GetNowPlaying,
+        Command::GetArt,
+        Command::Seek(Duration::from_millis(61_500)),
+        Command::Shutdown(Duration::ZERO),
+        Command::Shutdown(Duration::from_secs(300)),
+        Command::Reboot(Duration::ZERO),
+        Command::Reboot(Duration::from_secs(60)),
+        Command::CancelPower,
+        Command::Auth(Proof::new("hunter2", &nonce())),
+    ]
+}
+
+#[test]
+fn commands_round_trip() {
+    for command in commands() {
+        assert_eq!(command.to_string().parse(), Ok(command), "{}", command);
+        let request = Request { id: Some(7), command };
+        assert_eq!(request.to_string().parse(), Ok(request), "{}", request);
+        assert_eq!(Request::from(command).to_string(), command.to_string());
+    }
+}
+
+#[test]
+fn events_round_trip() {
+    let track = Track {
+        id: "spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+        title: "Never Gonna Give You Up".to_string(),
+        artist: "Rick Astley".to_string(),
+        album: "Whenever You Need Somebody".to_string(),
+        duration: Duration::from_secs(213),
+    };
+    let events = [
+        Event::Connect(ConnectStatus::Ok),
+        Event::Connect(ConnectStatus::Failed),
+        Event::Connect(ConnectStatus::CertificateChanged(Fingerprint::of(b"certificate"))),
+        Event::Connect(ConnectStatus::AuthFailed),
+        Event::Disconnect,
+        Event::Certificate(Fingerprint::of(b"certificate")),
+        Event::AuthChallenge(nonce()),
+        Event::AuthResult(true),
+        Event::AuthResult(false),
+        Event::Latency(Duration::from_millis(12)),
+        Event::Volume(42),
+        Event::Muted(true),
+        Event::Playing(false),
+        Event::Shuffle(true),
+        Event::Repeat(RepeatState::Off),
+        Event::Repeat(RepeatState::Track),
+        Event::Repeat(RepeatState::Context),
+        Event::Track(None),
+        Event::Track(Some(track)),
+        Event::Position(Position {
+            position: Duration::from_millis(1500),
+            duration: Duration::from_secs(213),
+            playing: true,
+        }),
+        Event::ArtUrl { track_id: "spotify:track:1".to_string(), url: "https://i.scdn.co/image/ab67".to_string() },
+        Event::Capabilities(vec![]),
+        Event::Capabilities(vec!["ack".to_string(), "mute".to_string()]),
+        Event::Ack(3),
+        Event::Error { id: 4, message: "not now".to_string() },
+        Event::PowerScheduled { action: PowerAction::Reboot, delay: Duration::from_secs(60) },
+        Event::PowerCancelled,
+        Event::Done(Command::SetVolume(20)),
+        Event::Failed(Command::Seek(Duration::from_secs(5)), "no track".to_string()),
+        Event::TimedOut(Command::GetVolume),
+    ];
+    for event in events {
+        assert_eq!(event.to_string().parse(), Ok(event.clone()), "{}", event);
+    }
+
+    // images go in binary frames, their text form is only for logs
+    let image = parse_art_frame(&art_frame("spotify:track:1", b"\x89PNG\n")).unwrap();
+    assert_eq!(image, Event::ArtImage { track_id: "spotify:track:1".to_string(), data: b"\x89PNG\n".to_vec() });
+    assert_eq!(image.to_string(), "[art_image](spotify:track:1|5 bytes)");
+}
+
+#[test]
+fn separators_in_fields_are_escaped() {
+    let track = Track {
+        id: "spotify:track:1".to_string(),
+        title: "Either | Or".to_string(),
+        artist: "Back\\Slash".to_string(),
+        album: "(Parenthesized)".to_string(),
+        duration: Duration::from_secs(1),
+    };
+    let event = Event::Track(Some(track));
+    assert_eq!(event.to_string(), r"[track](spotify:track:1|Either \| Or|Back\\Slash|(Parenthesized)|1000)");
+    assert_eq!(event.to_string().parse(), Ok(event));
+    assert_eq!(split_fields(r"a\|b|c\\|d"), ["a|b", "c\\", "d"]);
+}
+
+#[test]
+fn invalid_frames_are_rejected() {
+    assert_eq!(
+        "[volume](101)".parse::<Event>(),
+        Err(ParseError::InvalidValue { event: "volume".to_string(), value: "101".to_string() }),
+    );
+    assert_eq!("[loudness](3)".parse::<Event>(), Err(ParseError::UnknownEvent("loudness".to_string())));
+    for frame in ["[volume](40", "[volume]40)", "volume(40)", "[](40)", "[volume](40) trailing"] {
+        assert_eq!(frame.parse::<Event>(), Err(ParseError::MalformedFrame(frame.to_string())), "{}", frame);
+    }
+    assert_eq!(
+        "[track](spotify:track:1|too few)".parse::<Event>(),
+        Err(ParseError::InvalidValue { event: "track".to_string(), value: "spotify:track:1|too few".to_string() }),
+    );
+}
+
+#[test]
+fn invalid_commands_are_rejected() {
+    assert_eq!(
+        "set_volume 101".parse::<Command>(),
+        Err(ParseError::InvalidArgument { command: "set_volume".to_string(), argument: "101".to_string() }),
+    );
+    assert_eq!(
+        "set_volume".parse::<Command>(),
+        Err(ParseError::InvalidArgument { command: "set_volume".to_string(), argument: String::new() }),
+    );
+    assert_eq!(
+        "seek soon".parse::<Command>(),
+        Err(ParseError::InvalidArgument { command: "seek".to_string(), argument: "soon".to_string() }),
+    );
+    assert_eq!("dance".parse::<Command>(), Err(ParseError::UnknownCommand("dance".to_string())));
+    assert_eq!("next_track now".parse::<Command>(), Err(ParseError::UnknownCommand("next_track now".to_string())));
+    assert_eq!("@x next_track".parse::<Request>(), Err(ParseError::UnknownCommand("@x next_track".to_string())));
+}
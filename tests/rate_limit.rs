@@ -0,0 +1,27 @@
+use std::time::{Duration, Instant};
+
+use spotifypi_control_panel::rate_limit::RateLimit;
+
+
+#[test]
+fn waits_out_the_interval_after_each_time() {
+    let mut limit = RateLimit::new(Duration::from_secs(5));
+    let start = Instant::now();
+    assert_eq!(limit.wait(start), Duration::ZERO);
+
+    limit.record(start);
+    assert_eq!(limit.wait(start), Duration::from_secs(5));
+    assert_eq!(limit.wait(start + Duration::from_secs(2)), Duration::from_secs(3));
+    assert_eq!(limit.wait(start + Duration::from_secs(7)), Duration::ZERO);
+
+    limit.record(start + Duration::from_secs(7));
+    assert_eq!(limit.wait(start + Duration::from_secs(8)), Duration::from_secs(4));
+}
+
+#[test]
+fn zero_interval_never_waits() {
+    let mut limit = RateLimit::new(Duration::ZERO);
+    let now = Instant::now();
+    limit.record(now);
+    assert_eq!(limit.wait(now), Duration::ZERO);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::channel::mpsc::unbounded;
+use futures_util::{SinkExt, StreamExt};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use spotifypi_control_panel::client::{connect_to_ws_with_config, ws_url, AddrError, ClientConfig};
+use spotifypi_control_panel::mock::{serve, MockConfig};
+use spotifypi_control_panel::protocol::{ConnectStatus, Event};
+use spotifypi_control_panel::tls::{server_config, Fingerprint, Trust};
+
+use common::{next_event, serve_once, temp_dir};
+
+
+/// Runs the mock over TLS with a certificate for `localhost`, returning the
+/// url to connect to.
+async fn start_tls_mock(cert_pem: &str, key_pem: &str) -> url::Url {
+    let tls = server_config(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap();
+    let config = MockConfig {
+        tls: Some(Arc::new(tls)),
+        ..MockConfig::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(serve(listener, config));
+    ws_url(&format!("wss://localhost:{}", port)).unwrap()
+}
+
+/// Connects with `trust` and returns the events up to the connect status.
+async fn connect(url: &url::Url, trust: Trust) -> Vec<Event> {
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        trust,
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url.clone(), config, input_rx, output_tx));
+
+    let mut events = Vec::new();
+    loop {
+        let event = next_event(&mut output_rx).await.unwrap();
+        let done = matches!(event, Event::Connect(_));
+        events.push(event);
+        if done {
+            return events;
+        }
+    }
+}
+
+fn ca_path() -> PathBuf {
+    let dir = temp_dir("spotifypi-tls");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("ca.pem")
+}
+
+#[tokio::test]
+async fn certificate_is_pinned_on_first_use() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    // serialized once, every serialization is signed anew
+    let cert_pem = cert.serialize_pem().unwrap();
+    let url = start_tls_mock(&cert_pem, &cert.serialize_private_key_pem()).await;
+    let fingerprint = Fingerprint::of_pem(cert_pem.as_bytes()).unwrap();
+
+    assert_eq!(
+        connect(&url, Trust::Pinned(None)).await,
+        vec![Event::Certificate(fingerprint), Event::Connect(ConnectStatus::Ok)],
+    );
+    assert_eq!(connect(&url, Trust::Pinned(Some(fingerprint))).await, vec![Event::Connect(ConnectStatus::Ok)]);
+
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let other = Fingerprint::of_pem(other.serialize_pem().unwrap().as_bytes()).unwrap();
+    assert_eq!(
+        connect(&url, Trust::Pinned(Some(other))).await,
+        vec![Event::Connect(ConnectStatus::CertificateChanged(fingerprint))],
+    );
+
+    // self-signed, so nothing else trusts it
+    assert_eq!(connect(&url, Trust::System).await, vec![Event::Connect(ConnectStatus::Failed)]);
+}
+
+#[tokio::test]
+async fn certificate_events_from_the_server_are_dropped() {
+    let fingerprint = Fingerprint::of(b"certificate");
+    let url = serve_once(move |mut ws| async move {
+        ws.send(Message::text(Event::Certificate(fingerprint).to_string())).await.unwrap();
+        ws.send(Message::text(Event::Connect(ConnectStatus::CertificateChanged(fingerprint)).to_string())).await.unwrap();
+        ws.send(Message::text("[volume](7)")).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+
+    let (_input_tx, input_rx) = unbounded();
+    let (output_tx, mut output_rx) = unbounded();
+    let config = ClientConfig {
+        heartbeat: None,
+        trust: Trust::Pinned(None),
+        ..ClientConfig::default()
+    };
+    tokio::spawn(connect_to_ws_with_config(url, config, input_rx, output_tx));
+
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Connect(ConnectStatus::Ok)));
+    assert_eq!(next_event(&mut output_rx).await, Some(Event::Volume(7)));
+}
+
+#[tokio::test]
+async fn ca_file_trusts_the_certificates_it_signed() {
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+    let path = ca_path();
+    std::fs::write(&path, ca.serialize_pem().unwrap()).unwrap();
+
+    let leaf = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let url = start_tls_mock(&leaf.serialize_pem_with_signer(&ca).unwrap(), &leaf.serialize_private_key_pem()).await;
+
+    assert_eq!(connect(&url, Trust::CaFile(path.clone())).await, vec![Event::Connect(ConnectStatus::Ok)]);
+    assert_eq!(connect(&url, Trust::System).await, vec![Event::Connect(ConnectStatus::Failed)]);
+    assert_eq!(
+        connect(&url, Trust::CaFile(path.with_file_name("missing.pem"))).await,
+        vec![Event::Connect(ConnectStatus::Failed)],
+    );
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn addresses_and_fingerprints() {
+    assert_eq!(ws_url("kitchen.local:9487").unwrap().as_str(), "ws://kitchen.local:9487/");
+    assert_eq!(ws_url("wss://kitchen.local:9487").unwrap().scheme(), "wss");
+    assert_eq!(ws_url("https://kitchen.local"), Err(AddrError::Scheme("https".to_string())));
+
+    let fingerprint = Fingerprint::of(b"certificate");
+    let text = fingerprint.to_string();
+    assert_eq!(text.len(), 32 * 3 - 1);
+    assert_eq!(text.parse(), Ok(fingerprint));
+    assert_eq!(text.replace(':', "").to_lowercase().parse(), Ok(fingerprint));
+    assert!("AB:CD".parse::<Fingerprint>().is_err());
+
+    for event in [
+        Event::Certificate(fingerprint),
+        Event::Connect(ConnectStatus::CertificateChanged(fingerprint)),
+    ] {
+        assert_eq!(event.to_string().parse(), Ok(event));
+    }
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_util::StreamExt;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::proxy::{self, CacheProperties};
+use zbus::{interface, Connection, Proxy};
+
+use spotifypi_control_panel::protocol::{ConnectStatus, Event, PowerAction, Track};
+use spotifypi_control_panel::tray::{Tray, TrayRequest, ITEM_PATH, MENU_PATH};
+
+use common::{start_bus, TIMEOUT};
+
+
+const MENU: &str = "com.canonical.dbusmenu";
+
+/// Stands in for the tray host, taking note of the items registered.
+struct Watcher {
+    items: UnboundedSender<String>,
+}
+
+#[interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    fn register_status_notifier_item(&self, service: String) {
+        self.items.unbounded_send(service).unwrap();
+    }
+}
+
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+
+/// Label, id and whether it's enabled of every item of the menu, in order.
+async fn menu_items(menu: &Proxy<'_>) -> Vec<(String, i32, bool)> {
+    fn collect(layout: Layout, items: &mut Vec<(String, i32, bool)>) {
+        let (id, properties, children) = layout;
+        if let Some(label) = properties.get("label") {
+            let label = String::try_from(label.try_clone().unwrap()).unwrap();
+            let enabled = properties.get("enabled").is_none_or(|enabled| bool::try_from(enabled).unwrap());
+            items.push((label, id, enabled));
+        }
+        for child in children {
+            collect(Layout::try_from(child).unwrap(), items);
+        }
+    }
+    let reply = menu.call_method("GetLayout", &(0i32, -1i32, Vec::<String>::new())).await.unwrap();
+    let (_revision, layout): (u32, Layout) = reply.body().deserialize().unwrap();
+    let mut items = Vec::new();
+    collect(layout, &mut items);
+    items
+}
+
+async fn find(menu: &Proxy<'_>, label: &str) -> (i32, bool) {
+    let items = menu_items(menu).await;
+    items.iter()
+        .find(|(item, _, _)| item == label)
+        .map(|(_, id, enabled)| (*id, *enabled))
+        .unwrap_or_else(|| panic!("no {:?} in {:?}", label, items))
+}
+
+async fn click(menu: &Proxy<'_>, label: &str) {
+    let (id, _) = find(menu, label).await;
+    menu.call_method("Event", &(id, "clicked", Value::from(0i32), 0u32)).await.unwrap();
+}
+
+async fn next_request(requests: &mut UnboundedReceiver<TrayRequest>) -> TrayRequest {
+    tokio::time::timeout(TIMEOUT, requests.next()).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn tray_mirrors_the_player_and_forwards_clicks() {
+    let bus = match start_bus() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let (requests_tx, mut requests_rx) = unbounded();
+    // nowhere to show it yet
+    assert!(Tray::start(bus.builder(), requests_tx.clone()).await.is_err());
+
+    let (items_tx, mut items_rx) = unbounded();
+    let host: Connection = bus.builder()
+        .name("org.kde.StatusNotifierWatcher").unwrap()
+        .serve_at("/StatusNotifierWatcher", Watcher { items: items_tx }).unwrap()
+        .build().await.unwrap();
+    let tray = Tray::start(bus.builder(), requests_tx).await.unwrap();
+    let service = tokio::time::timeout(TIMEOUT, items_rx.next()).await.unwrap().unwrap();
+    assert!(service.starts_with("org.kde.StatusNotifierItem-"));
+
+    // hosts read the properties again when told they changed
+    let item: Proxy = proxy::Builder::new(&host)
+        .destination(service.as_str()).unwrap()
+        .path(ITEM_PATH).unwrap()
+        .interface("org.kde.StatusNotifierItem").unwrap()
+        .cache_properties(CacheProperties::No)
+        .build().await.unwrap();
+    let menu = Proxy::new(&host, service.as_str(), MENU_PATH, MENU).await.unwrap();
+
+    tray.set_connection("Not connected").await.unwrap();
+    tray.set_devices(vec!["kitchen".to_string(), "living_room".to_string()], Some("kitchen".to_string())).await.unwrap();
+    assert!(!find(&menu, "Next track").await.1);
+
+    tray.set_connection("Connected to kitchen").await.unwrap();
+    let track = Track {
+        id: "spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+        title: "Never Gonna Give You Up".to_string(),
+        artist: "Rick Astley".to_string(),
+        album: "Whenever You Need Somebody".to_string(),
+        duration: Duration::from_secs(213),
+    };
+    for event in [
+        Event::Connect(ConnectStatus::Ok),
+        Event::Track(Some(track)),
+        Event::Playing(true),
+        Event::Volume(40),
+    ] {
+        tray.update(&event).await.unwrap();
+    }
+
+    let icon: String = item.get_property("IconName").await.unwrap();
+    assert_eq!(icon, "media-playback-start");
+    let (_, _, _, description): ToolTip = item.get_property("ToolTip").await.unwrap();
+    assert_eq!(description, "Connected to kitchen\nNever Gonna Give You Up - Rick Astley");
+    assert!(find(&menu, "Volume (40%)").await.1);
+
+    click(&menu, "Pause").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Action("play-pause"));
+    click(&menu, "Next track").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Action("next-track"));
+    click(&menu, "75%").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::SetVolume(75));
+    // underscores are escaped, as they'd mark a mnemonic
+    click(&menu, "living__room").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::SelectDevice("living_room".to_string()));
+    item.call_method("Activate", &(0i32, 0i32)).await.unwrap();
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::ToggleWindow);
+
+    // disabled items do nothing
+    assert!(!find(&menu, "Cancel shutdown / reboot").await.1);
+    click(&menu, "Cancel shutdown / reboot").await;
+    tray.update(&Event::PowerScheduled { action: PowerAction::Reboot, delay: Duration::from_secs(60) }).await.unwrap();
+    click(&menu, "Cancel shutdown / reboot").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Action("cancel-power"));
+
+    tray.update(&Event::Disconnect).await.unwrap();
+    assert!(!find(&menu, "Play").await.1);
+    let icon: String = item.get_property("IconName").await.unwrap();
+    assert_eq!(icon, "audio-x-generic");
+    click(&menu, "Quit").await;
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Quit);
+}
+
+#[tokio::test]
+async fn icon_is_registered_again_with_a_new_host() {
+    let bus = match start_bus() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let (items_tx, mut items_rx) = unbounded();
+    let serve_watcher = |items: UnboundedSender<String>| {
+        bus.builder()
+            .name("org.kde.StatusNotifierWatcher").unwrap()
+            .serve_at("/StatusNotifierWatcher", Watcher { items }).unwrap()
+            .build()
+    };
+    let host: Connection = serve_watcher(items_tx.clone()).await.unwrap();
+    let (requests_tx, mut requests_rx) = unbounded();
+    let _tray = Tray::start(bus.builder(), requests_tx).await.unwrap();
+    let service = tokio::time::timeout(TIMEOUT, items_rx.next()).await.unwrap().unwrap();
+
+    // the host restarting, the panel is told meanwhile
+    drop(host);
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Shown(false));
+    let _host: Connection = serve_watcher(items_tx).await.unwrap();
+    assert_eq!(tokio::time::timeout(TIMEOUT, items_rx.next()).await.unwrap().unwrap(), service);
+    assert_eq!(next_request(&mut requests_rx).await, TrayRequest::Shown(true));
+}